@@ -0,0 +1,147 @@
+//! `create`, `find` and `all` against stores of growing size, for the
+//! in-memory repositories and, when `DATABASE_URL` is set, Postgres.
+//!
+//! `cargo bench --features test-utils`
+
+use std::env;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use sqlx::PgPool;
+use tokio::runtime::Runtime;
+
+use todo_api::models::id::{LabelId, TodoId};
+use todo_api::models::label::Label;
+use todo_api::models::todo::{CreateTodo, TodoListParams};
+use todo_api::repositories::label_repository::test_utils::LabelRepositoryForMemory;
+use todo_api::repositories::label_repository::LabelRepository;
+use todo_api::repositories::todo_repository::test_utils::TodoRepositoryForMemory;
+use todo_api::repositories::todo_repository::{TodoRepository, TodoRepositoryForDb};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+/// Text of the todos the Postgres benchmarks seed and clean up.
+const DB_TEXT: &str = "bench todo";
+
+/// Times `create` alone, deleting each new todo untimed so the store keeps
+/// its size however many iterations criterion runs.
+async fn create_at_size<R: TodoRepository>(repository: &R, iters: u64) -> Duration {
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..iters {
+        let start = Instant::now();
+        let todo = repository
+            .create(CreateTodo::new("created".to_string()))
+            .await
+            .unwrap();
+        elapsed += start.elapsed();
+        repository.delete(todo.id).await.unwrap();
+    }
+    elapsed
+}
+
+fn bench_todos<R: TodoRepository>(
+    c: &mut Criterion,
+    rt: &Runtime,
+    group: &str,
+    size: usize,
+    repository: &R,
+    find: TodoId,
+) {
+    let mut group = c.benchmark_group(group);
+    if size >= 10_000 {
+        group.sample_size(10);
+    }
+    group.bench_with_input(BenchmarkId::new("create", size), &size, |b, _| {
+        b.to_async(rt)
+            .iter_custom(|iters| create_at_size(repository, iters))
+    });
+    group.bench_with_input(BenchmarkId::new("find", size), &size, |b, _| {
+        b.to_async(rt)
+            .iter(|| async { repository.find(find).await.unwrap() })
+    });
+    group.bench_with_input(BenchmarkId::new("all", size), &size, |b, _| {
+        b.to_async(rt)
+            .iter(|| async { repository.all(TodoListParams::default()).await.unwrap() })
+    });
+    group.finish();
+}
+
+fn memory(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    for size in SIZES {
+        let repository = TodoRepositoryForMemory::seeded(size);
+        let middle = TodoId::new(size as i32 / 2).unwrap();
+        bench_todos(c, &rt, "memory/todos", size, &repository, middle);
+
+        let labels = LabelRepositoryForMemory::new();
+        labels.write_store_ref().extend((1..=size as i32).map(|id| {
+            let id = LabelId::new(id).unwrap();
+            (id, Label::new(id, format!("label {}", id.get())))
+        }));
+        c.benchmark_group("memory/labels").bench_with_input(
+            BenchmarkId::new("create", size),
+            &size,
+            |b, _| {
+                b.to_async(&rt).iter_custom(|iters| {
+                    let labels = labels.clone();
+                    async move {
+                        let mut elapsed = Duration::ZERO;
+                        for _ in 0..iters {
+                            let start = Instant::now();
+                            let label = labels.create("created".to_string()).await.unwrap();
+                            elapsed += start.elapsed();
+                            labels.delete(label.id).await.unwrap();
+                        }
+                        elapsed
+                    }
+                })
+            },
+        );
+    }
+}
+
+fn postgres(c: &mut Criterion) {
+    dotenv::dotenv().ok();
+    let database_url = match env::var("DATABASE_URL") {
+        Ok(database_url) => database_url,
+        Err(_) => {
+            eprintln!("DATABASE_URL not set, skipping the postgres benchmarks");
+            return;
+        }
+    };
+    let rt = Runtime::new().unwrap();
+    let pool = rt
+        .block_on(PgPool::connect(&database_url))
+        .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+    let repository = TodoRepositoryForDb::new(pool.clone());
+    for size in SIZES {
+        let first: TodoId = rt
+            .block_on(async {
+                sqlx::query(
+                    r#"
+                    INSERT INTO todos (text, completed)
+                    SELECT $1, false FROM generate_series(1, $2)
+                    "#,
+                )
+                .bind(DB_TEXT)
+                .bind(size as i32)
+                .execute(&pool)
+                .await?;
+                sqlx::query_scalar("SELECT min(id) FROM todos WHERE text = $1")
+                    .bind(DB_TEXT)
+                    .fetch_one(&pool)
+                    .await
+            })
+            .unwrap();
+        bench_todos(c, &rt, "postgres/todos", size, &repository, first);
+        rt.block_on(
+            sqlx::query("DELETE FROM todos WHERE text = $1")
+                .bind(DB_TEXT)
+                .execute(&pool),
+        )
+        .unwrap();
+    }
+}
+
+criterion_group!(benches, memory, postgres);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+use std::env;
+use std::process::Command;
+
+fn main() {
+    // prefer an explicit GIT_HASH, e.g. from a docker build without .git
+    let hash = env::var("GIT_HASH").ok().or_else(|| {
+        Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    });
+    println!(
+        "cargo:rustc-env=GIT_HASH={}",
+        hash.unwrap_or_else(|| "unknown".to_string())
+    );
+    println!("cargo:rerun-if-env-changed=GIT_HASH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+}
@@ -0,0 +1,4 @@
+ALTER TABLE todos
+    ADD COLUMN IF NOT EXISTS external_id TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS todos_external_id_key ON todos (external_id);
@@ -0,0 +1,69 @@
+-- Each todo carries the id of the transaction that created it and of the
+-- last one that changed it, and deleted todos leave a tombstone, so
+-- GET /todos/sync can tell what changed since a token. Transaction ids
+-- rather than a sequence: see TodoRepositoryForDb::sync.
+ALTER TABLE todos
+    ADD COLUMN IF NOT EXISTS created_seq BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS seq BIGINT NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS todos_seq_idx ON todos (seq);
+
+CREATE TABLE IF NOT EXISTS todo_tombstones
+(
+    id  INTEGER PRIMARY KEY,
+    seq BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS todo_tombstones_seq_idx ON todo_tombstones (seq);
+
+CREATE OR REPLACE FUNCTION stamp_todo() RETURNS TRIGGER AS
+$$
+BEGIN
+    NEW.seq := pg_current_xact_id()::TEXT::BIGINT;
+    IF TG_OP = 'INSERT' THEN
+        NEW.created_seq := NEW.seq;
+    END IF;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS todos_stamp ON todos;
+CREATE TRIGGER todos_stamp
+    BEFORE INSERT OR UPDATE
+    ON todos
+    FOR EACH ROW
+EXECUTE FUNCTION stamp_todo();
+
+-- relabeling changes the todo; todos_stamp replaces the placeholder 0
+CREATE OR REPLACE FUNCTION stamp_labeled_todo() RETURNS TRIGGER AS
+$$
+BEGIN
+    UPDATE todos SET seq = 0
+    WHERE id = CASE WHEN TG_OP = 'DELETE' THEN OLD.todo_id ELSE NEW.todo_id END;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS todo_labels_stamp ON todo_labels;
+CREATE TRIGGER todo_labels_stamp
+    AFTER INSERT OR DELETE
+    ON todo_labels
+    FOR EACH ROW
+EXECUTE FUNCTION stamp_labeled_todo();
+
+CREATE OR REPLACE FUNCTION bury_todo() RETURNS TRIGGER AS
+$$
+BEGIN
+    INSERT INTO todo_tombstones (id, seq)
+    VALUES (OLD.id, pg_current_xact_id()::TEXT::BIGINT)
+    ON CONFLICT (id) DO UPDATE SET seq = EXCLUDED.seq;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS todos_bury ON todos;
+CREATE TRIGGER todos_bury
+    AFTER DELETE
+    ON todos
+    FOR EACH ROW
+EXECUTE FUNCTION bury_todo();
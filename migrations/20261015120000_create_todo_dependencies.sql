@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS todo_dependencies
+(
+    todo_id    INTEGER NOT NULL REFERENCES todos (id) DEFERRABLE INITIALLY DEFERRED,
+    depends_on INTEGER NOT NULL REFERENCES todos (id) DEFERRABLE INITIALLY DEFERRED,
+    PRIMARY KEY (todo_id, depends_on),
+    CHECK (todo_id <> depends_on)
+);
+
+-- dependents are looked up by the todo they wait for
+CREATE INDEX IF NOT EXISTS todo_dependencies_depends_on_idx ON todo_dependencies (depends_on);
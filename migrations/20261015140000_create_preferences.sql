@@ -0,0 +1,8 @@
+-- Preferences stored by PUT /preferences, one document per owner: a SHA-256
+-- of the caller's API key, never the key itself.
+CREATE TABLE IF NOT EXISTS preferences
+(
+    owner      TEXT PRIMARY KEY,
+    document   JSONB       NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,34 @@
+-- One version for the whole todo collection, bumped by every transaction
+-- that changes todos (relabeling does, through todo_labels_stamp), for the
+-- ETag of GET /todos. The bump is deferred to commit, so the single row is
+-- only locked once a transaction's other locks are held: versions become
+-- visible in commit order, and writers never deadlock on it.
+CREATE TABLE IF NOT EXISTS todo_collection_version
+(
+    id      BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    version BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO todo_collection_version (id)
+VALUES (TRUE)
+ON CONFLICT (id) DO NOTHING;
+
+CREATE OR REPLACE FUNCTION bump_todo_collection_version() RETURNS TRIGGER AS
+$$
+BEGIN
+    -- once per transaction, however many rows it changed
+    IF current_setting('manpuku.todo_version_bumped', true) IS DISTINCT FROM 'on' THEN
+        PERFORM set_config('manpuku.todo_version_bumped', 'on', true);
+        UPDATE todo_collection_version SET version = version + 1;
+    END IF;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS todos_bump_version ON todos;
+CREATE CONSTRAINT TRIGGER todos_bump_version
+    AFTER INSERT OR UPDATE OR DELETE
+    ON todos
+    DEFERRABLE INITIALLY DEFERRED
+    FOR EACH ROW
+EXECUTE FUNCTION bump_todo_collection_version();
@@ -0,0 +1,22 @@
+-- A todo's detail lists what it depends on and what depends on it, so an
+-- edge changes both todos and moves their seq, which GET /todos/:id serves
+-- as its ETag; todos_stamp replaces the placeholder 0. GET /todos/sync
+-- reports them as updated too.
+CREATE OR REPLACE FUNCTION stamp_dependent_todos() RETURNS TRIGGER AS
+$$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        UPDATE todos SET seq = 0 WHERE id IN (OLD.todo_id, OLD.depends_on);
+    ELSE
+        UPDATE todos SET seq = 0 WHERE id IN (NEW.todo_id, NEW.depends_on);
+    END IF;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS todo_dependencies_stamp ON todo_dependencies;
+CREATE TRIGGER todo_dependencies_stamp
+    AFTER INSERT OR DELETE
+    ON todo_dependencies
+    FOR EACH ROW
+EXECUTE FUNCTION stamp_dependent_todos();
@@ -0,0 +1,25 @@
+-- Label names are unique ignoring case, so "Work" and "work" are one label
+-- however many requests create it at once. Labels that already differ only
+-- in case are merged into the oldest one first; links that end up
+-- duplicated are dropped.
+WITH keep AS (SELECT lower(name) AS key, min(id) AS id FROM labels GROUP BY lower(name))
+UPDATE todo_labels
+SET label_id = keep.id
+FROM labels,
+     keep
+WHERE todo_labels.label_id = labels.id
+  AND lower(labels.name) = keep.key
+  AND labels.id <> keep.id;
+
+DELETE
+FROM todo_labels a
+    USING todo_labels b
+WHERE a.todo_id = b.todo_id
+  AND a.label_id = b.label_id
+  AND a.id > b.id;
+
+DELETE
+FROM labels
+WHERE id NOT IN (SELECT min(id) FROM labels GROUP BY lower(name));
+
+CREATE UNIQUE INDEX IF NOT EXISTS labels_name_lower_key ON labels (lower(name));
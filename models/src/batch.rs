@@ -0,0 +1,43 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use validator::Validate;
+
+/// One call of a batch, as it would be sent on its own.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BatchItem {
+    pub method: String,
+    /// Path and query, e.g. `/todos/3?force=true`.
+    pub path: String,
+    /// Sent as the JSON body when present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    /// Sent with this call only, on top of the batch's `Authorization`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// The calls of `POST /batch`, run one after the other in order.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Validate)]
+#[serde(transparent)]
+pub struct Batch {
+    #[validate(length(max = 50, message = "at most 50 calls per batch"))]
+    pub items: Vec<BatchItem>,
+}
+
+/// With `stop_on_error=true` a batch stops at the first call answering 4xx
+/// or 5xx, so the results end there; by default every call runs.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchParams {
+    #[serde(default)]
+    pub stop_on_error: bool,
+}
+
+/// What one call of a batch answered. `body` is its JSON, the text of a body
+/// that isn't JSON, or `null` when there was none.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BatchResult {
+    pub status: u16,
+    pub body: Value,
+}
@@ -0,0 +1,164 @@
+use std::fmt;
+use std::str::FromStr;
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, NumberValidation, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize};
+
+/// An id outside `1..=i32::MAX`, or not a number at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidId {
+    kind: &'static str,
+    value: String,
+}
+
+impl fmt::Display for InvalidId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid {} id `{}`: must be an integer from 1 to {}",
+            self.kind,
+            self.value,
+            i32::MAX
+        )
+    }
+}
+
+impl std::error::Error for InvalidId {}
+
+macro_rules! id_type {
+    ($(#[$meta:meta])* $name:ident, $kind:literal) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+        #[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(transparent))]
+        #[serde(transparent)]
+        pub struct $name(i32);
+
+        impl $name {
+            pub fn new(id: i32) -> Result<Self, InvalidId> {
+                if id < 1 {
+                    return Err(Self::invalid(id));
+                }
+                Ok(Self(id))
+            }
+
+            pub fn get(self) -> i32 {
+                self.0
+            }
+
+            fn invalid(value: impl fmt::Display) -> InvalidId {
+                InvalidId {
+                    kind: $kind,
+                    value: value.to_string(),
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = InvalidId;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let id = s.parse().map_err(|_| Self::invalid(s))?;
+                Self::new(id)
+            }
+        }
+
+        impl TryFrom<i64> for $name {
+            type Error = InvalidId;
+
+            fn try_from(id: i64) -> Result<Self, Self::Error> {
+                let id = i32::try_from(id).map_err(|_| Self::invalid(id))?;
+                Self::new(id)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                struct IdVisitor;
+
+                impl<'de> Visitor<'de> for IdVisitor {
+                    type Value = $name;
+
+                    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                        write!(f, "a {} id from 1 to {}", $kind, i32::MAX)
+                    }
+
+                    fn visit_i64<E: de::Error>(self, id: i64) -> Result<$name, E> {
+                        $name::try_from(id).map_err(E::custom)
+                    }
+
+                    fn visit_u64<E: de::Error>(self, id: u64) -> Result<$name, E> {
+                        let id = i64::try_from(id).unwrap_or(i64::MAX);
+                        $name::try_from(id).map_err(E::custom)
+                    }
+                }
+
+                deserializer.deserialize_i64(IdVisitor)
+            }
+        }
+
+        impl JsonSchema for $name {
+            fn is_referenceable() -> bool {
+                false
+            }
+
+            fn schema_name() -> String {
+                stringify!($name).to_string()
+            }
+
+            fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+                SchemaObject {
+                    instance_type: Some(InstanceType::Integer.into()),
+                    format: Some("int32".to_string()),
+                    number: Some(Box::new(NumberValidation {
+                        minimum: Some(1.0),
+                        maximum: Some(i32::MAX.into()),
+                        ..Default::default()
+                    })),
+                    ..Default::default()
+                }
+                .into()
+            }
+        }
+    };
+}
+
+id_type!(
+    /// Id of a todo; always at least 1, so an invalid id can't be represented.
+    TodoId,
+    "todo"
+);
+id_type!(
+    /// Id of a label; always at least 1, so an invalid id can't be represented.
+    LabelId,
+    "label"
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_only_positive_i32() {
+        assert_eq!(TodoId::new(7).unwrap(), "7".parse().unwrap());
+        assert_eq!(Ok(i32::MAX), "2147483647".parse().map(TodoId::get));
+        for value in ["0", "-5", "99999999999", "abc", ""] {
+            let err = value.parse::<LabelId>().unwrap_err();
+            assert_eq!(
+                format!(
+                    "invalid label id `{}`: must be an integer from 1 to 2147483647",
+                    value
+                ),
+                err.to_string()
+            );
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use crate::id::LabelId;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct Label {
+    pub id: LabelId,
+    pub name: String,
+}
+
+impl Label {
+    pub fn new(id: LabelId, name: String) -> Self {
+        Self { id, name }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, JsonSchema)]
+pub struct CreateLabel {
+    #[validate(length(min = 1, message = "name is required"))]
+    #[validate(length(max = 255, message = "name is too long"))]
+    pub name: String,
+}
+
+/// Body of `POST /labels/bulk`; no names is a valid batch that creates
+/// nothing.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, JsonSchema)]
+pub struct CreateLabels {
+    #[validate(custom = "validate_names")]
+    pub names: Vec<String>,
+}
+
+/// Every name follows the same rules as [`CreateLabel::name`].
+fn validate_names(names: &[String]) -> Result<(), ValidationError> {
+    if names
+        .iter()
+        .any(|name| name.is_empty() || name.chars().count() > 255)
+    {
+        return Err(ValidationError::new("name must be 1 to 255 characters"));
+    }
+    Ok(())
+}
+
+/// Order of `GET /labels`: by `id` (the default), `name`, or `todo_count`,
+/// most used first.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+pub enum LabelSort {
+    #[default]
+    #[serde(rename = "id")]
+    Id,
+    #[serde(rename = "name")]
+    Name,
+    #[serde(rename = "todo_count")]
+    TodoCount,
+}
+
+/// Paging and search for `GET /labels`; without a limit every label is
+/// listed.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Validate, JsonSchema)]
+pub struct LabelListParams {
+    #[validate(range(min = 1, max = 100, message = "limit must be between 1 and 100"))]
+    pub limit: Option<i64>,
+    #[validate(range(min = 0, message = "offset must not be negative"))]
+    pub offset: Option<i64>,
+    pub sort: Option<LabelSort>,
+    /// Only labels whose name contains `q`, ignoring case.
+    #[validate(length(max = 255, message = "q is too long"))]
+    pub q: Option<String>,
+    /// Matches `q` despite typos instead, closest first unless `sort` is
+    /// given.
+    #[serde(default)]
+    pub fuzzy: bool,
+}
+
+impl LabelListParams {
+    /// Whether only a page was asked for, so the total is worth reporting.
+    pub fn is_paginated(&self) -> bool {
+        self.limit.is_some() || self.offset.is_some()
+    }
+
+    /// The text to search for; an empty `q` lists every label.
+    pub fn search(&self) -> Option<&str> {
+        self.q.as_deref().filter(|q| !q.is_empty())
+    }
+}
+
+/// A label from a bulk create, flagged with whether this request created it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BulkLabel {
+    #[serde(flatten)]
+    pub label: Label,
+    pub created: bool,
+}
+
+/// How many todos `POST /labels/:id/move-todos/:to` moved.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct MovedTodos {
+    pub moved: usize,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct SuggestLabel {
+    #[serde(default)]
+    pub prefix: String,
+}
@@ -0,0 +1,7 @@
+//! Request and response types of the todo API, without server dependencies.
+
+pub mod batch;
+pub mod id;
+pub mod label;
+pub mod preferences;
+pub mod todo;
@@ -0,0 +1,66 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use validator::{Validate, ValidationError};
+
+/// Order a UI lists todos in until the user picks another.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DefaultSort {
+    Newest,
+    Oldest,
+    Text,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Theme {
+    Light,
+    Dark,
+    System,
+}
+
+/// Settings a UI keeps on the server so they follow the user across devices.
+/// Keys other than these are rejected; anything else the UI wants to keep
+/// goes under `custom`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Preferences {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_sort: Option<DefaultSort>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hide_completed: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theme: Option<Theme>,
+    /// Free-form values, at most [`MAX_CUSTOM_KEYS`] keys of up to 64
+    /// characters each.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    #[validate(custom = "validate_custom")]
+    pub custom: Map<String, Value>,
+}
+
+pub const MAX_CUSTOM_KEYS: usize = 50;
+
+fn validate_custom(custom: &Map<String, Value>) -> Result<(), ValidationError> {
+    if custom.len() > MAX_CUSTOM_KEYS {
+        return Err(ValidationError::new("at most 50 custom keys"));
+    }
+    if custom
+        .keys()
+        .any(|key| key.is_empty() || key.chars().count() > 64)
+    {
+        return Err(ValidationError::new(
+            "custom keys must be 1 to 64 characters",
+        ));
+    }
+    Ok(())
+}
+
+/// What `GET` and `PUT /preferences` answer: the stored preferences and when
+/// they were last replaced, in milliseconds since the epoch; `updated_at` is
+/// `null` until the first `PUT`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct StoredPreferences {
+    pub preferences: Preferences,
+    pub updated_at: Option<i64>,
+}
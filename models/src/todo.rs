@@ -0,0 +1,355 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use crate::id::{LabelId, TodoId};
+use crate::label::Label;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Todo {
+    pub id: TodoId,
+    pub text: String,
+    pub completed: bool,
+    /// Pinned todos are listed before all others.
+    pub pinned: bool,
+    pub labels: Vec<Label>,
+    /// Id of the todo in a system it is synced with, unique among todos.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+}
+
+impl Todo {
+    pub fn new(id: TodoId, text: String) -> Self {
+        Self {
+            id,
+            text,
+            completed: false,
+            pinned: false,
+            labels: vec![],
+            external_id: None,
+        }
+    }
+}
+
+/// A todo in `GET /todos`, with its labels as `?labels=` asked for and
+/// flagged when its text was cut by `truncate_text`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ListedTodo {
+    pub id: TodoId,
+    pub text: String,
+    pub completed: bool,
+    pub pinned: bool,
+    #[serde(flatten)]
+    pub labels: ListedLabels,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+/// Serialized as `labels` or `label_ids`, depending on the variant.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum ListedLabels {
+    #[serde(rename = "labels")]
+    Full(Vec<Label>),
+    #[serde(rename = "label_ids")]
+    Ids(Vec<LabelId>),
+}
+
+impl ListedTodo {
+    pub fn new(todo: Todo, view: LabelsView) -> Self {
+        let labels = match view {
+            LabelsView::Full => ListedLabels::Full(todo.labels),
+            LabelsView::Ids => {
+                ListedLabels::Ids(todo.labels.iter().map(|label| label.id).collect())
+            }
+        };
+        Self {
+            id: todo.id,
+            text: todo.text,
+            completed: todo.completed,
+            pinned: todo.pinned,
+            labels,
+            external_id: todo.external_id,
+            truncated: false,
+        }
+    }
+
+    /// Keeps at most `max_chars` characters of the text, then an ellipsis.
+    pub fn truncate_text(mut self, max_chars: usize) -> Self {
+        if let Some((end, _)) = self.text.char_indices().nth(max_chars) {
+            self.text.truncate(end);
+            self.text.push('…');
+            self.truncated = true;
+        }
+        self
+    }
+}
+
+impl From<Todo> for ListedTodo {
+    fn from(todo: Todo) -> Self {
+        Self::new(todo, LabelsView::Full)
+    }
+}
+
+/// Starts todo text the server stored encrypted. Text sent by clients may
+/// not start with it, or it would be read back as a corrupt encrypted value.
+pub const SEALED_TEXT_PREFIX: &str = "enc:v1:";
+
+fn validate_unsealed(text: &str) -> Result<(), ValidationError> {
+    if text.starts_with(SEALED_TEXT_PREFIX) {
+        return Err(ValidationError::new("text must not start with `enc:v1:`"));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, JsonSchema)]
+pub struct CreateTodo {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over text length"))]
+    #[validate(custom = "validate_unsealed")]
+    pub text: String,
+    #[serde(default)]
+    pub labels: Vec<LabelId>,
+    #[validate(length(
+        min = 1,
+        max = 100,
+        message = "external_id must be 1 to 100 characters"
+    ))]
+    #[serde(default)]
+    pub external_id: Option<String>,
+}
+
+impl CreateTodo {
+    pub fn new(text: String) -> Self {
+        Self {
+            text,
+            labels: vec![],
+            external_id: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, JsonSchema)]
+pub struct UpdateTodo {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over text length"))]
+    #[validate(custom = "validate_unsealed")]
+    pub text: Option<String>,
+    pub completed: Option<bool>,
+    /// Replaces the external id; there is no way to remove it.
+    #[validate(length(
+        min = 1,
+        max = 100,
+        message = "external_id must be 1 to 100 characters"
+    ))]
+    #[serde(default)]
+    pub external_id: Option<String>,
+}
+
+impl UpdateTodo {
+    /// The fields this update gives a different value than `current` has,
+    /// in declaration order.
+    pub fn changed_fields(&self, current: &Todo) -> Vec<TodoField> {
+        let mut changed = Vec::new();
+        if matches!(&self.text, Some(text) if *text != current.text) {
+            changed.push(TodoField::Text);
+        }
+        if matches!(self.completed, Some(completed) if completed != current.completed) {
+            changed.push(TodoField::Completed);
+        }
+        if self.external_id.is_some() && self.external_id != current.external_id {
+            changed.push(TodoField::ExternalId);
+        }
+        changed
+    }
+}
+
+/// A field [`UpdateTodo`] sets.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoField {
+    Text,
+    Completed,
+    ExternalId,
+}
+
+/// Response of `PATCH /todos/:id`: the todo as it is now, and the fields
+/// the update changed; empty when it changed nothing and wrote nothing.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct UpdatedTodo {
+    #[serde(flatten)]
+    pub todo: Todo,
+    pub changed_fields: Vec<TodoField>,
+}
+
+/// Query of `GET /todos/sync`: `since` is the `token` of the previous sync,
+/// left out the first time.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Validate, JsonSchema)]
+pub struct SyncParams {
+    #[validate(custom = "validate_token")]
+    pub since: Option<u64>,
+}
+
+fn validate_token(since: u64) -> Result<(), ValidationError> {
+    if i64::try_from(since).is_err() {
+        return Err(ValidationError::new(
+            "since must be a token from an earlier sync",
+        ));
+    }
+    Ok(())
+}
+
+/// Response of `GET /todos/sync`: todos created, updated and deleted since
+/// the given token, and the token to pass next time. A todo changed while
+/// the sync was read may come again in the next one, so clients apply these
+/// as upserts and deletes.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct TodoSync {
+    pub created: Vec<Todo>,
+    pub updated: Vec<Todo>,
+    pub deleted: Vec<TodoId>,
+    pub token: u64,
+}
+
+/// Long polling on `GET /todos/changes`, or `GET /todos` with `wait=true`:
+/// the response holds until there are changes after `since` (the
+/// `X-Change-Cursor` of an earlier response, or now when left out), or until
+/// `timeout` seconds pass.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Validate, JsonSchema)]
+pub struct TodoChangesParams {
+    #[serde(default)]
+    pub wait: bool,
+    pub since: Option<u64>,
+    #[validate(range(min = 1, max = 30, message = "timeout must be between 1 and 30"))]
+    pub timeout: Option<u64>,
+}
+
+/// A change to a todo, as long polling returns it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TodoChangeEntry {
+    Created { todo: Todo },
+    Updated { todo: Todo },
+    Deleted { id: TodoId },
+}
+
+/// Changes after a cursor, oldest first, and the cursor to continue from.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TodoChanges {
+    pub cursor: u64,
+    pub changes: Vec<TodoChangeEntry>,
+}
+
+/// Body of `PUT /todos/:id/labels`: the todo's complete set of labels.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, JsonSchema)]
+pub struct SetLabels {
+    pub label_ids: Vec<LabelId>,
+}
+
+/// Body of `POST /todos/:id/dependencies`: the todo that has to be completed
+/// first.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Validate, JsonSchema)]
+pub struct AddDependency {
+    pub depends_on: TodoId,
+}
+
+/// A todo's dependency edges: the todos it waits for and the todos waiting
+/// for it.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct TodoDependencies {
+    pub dependencies: Vec<TodoId>,
+    pub dependents: Vec<TodoId>,
+}
+
+/// Response of `GET /todos/:id`: the todo with its dependency edges.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TodoDetail {
+    #[serde(flatten)]
+    pub todo: Todo,
+    #[serde(flatten)]
+    pub dependencies: TodoDependencies,
+}
+
+/// Query of `PATCH /todos/:id`: `force=true` completes a todo even while
+/// some of its dependencies are still open.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Validate)]
+pub struct UpdateTodoParams {
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Query of `GET /todos/random`: only todos with this label, when given.
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Validate, JsonSchema,
+)]
+pub struct RandomTodoParams {
+    pub label: Option<LabelId>,
+}
+
+/// Body of `POST /todos/exists`: the ids to look up, at most 1000.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, JsonSchema)]
+pub struct TodoIds {
+    #[validate(length(max = 1000, message = "at most 1000 ids at once"))]
+    pub ids: Vec<TodoId>,
+}
+
+/// Which todos `?completed=` lists: `true`, `false` or `any`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+pub enum CompletedFilter {
+    #[serde(rename = "true")]
+    Completed,
+    #[serde(rename = "false")]
+    Open,
+    #[serde(rename = "any")]
+    Any,
+}
+
+impl CompletedFilter {
+    /// The `completed` value to match, `None` for any.
+    pub fn completed(self) -> Option<bool> {
+        match self {
+            CompletedFilter::Completed => Some(true),
+            CompletedFilter::Open => Some(false),
+            CompletedFilter::Any => None,
+        }
+    }
+
+    pub fn matches(self, todo: &Todo) -> bool {
+        self.completed()
+            .is_none_or(|completed| todo.completed == completed)
+    }
+}
+
+/// How `GET /todos` shows labels: `full` objects (the default) or only `ids`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+pub enum LabelsView {
+    #[default]
+    #[serde(rename = "full")]
+    Full,
+    #[serde(rename = "ids")]
+    Ids,
+}
+
+/// Paging for `GET /todos`, pinned todos first, then newest first; without
+/// a limit the server lists at most its `MAX_UNPAGINATED` todos, saying so
+/// in `X-Truncated` when there are more.
+///
+/// Without `completed` every todo is listed, unless the server hides
+/// completed ones by default (`HIDE_COMPLETED_BY_DEFAULT`); an explicit
+/// `completed`, including `any`, always wins.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Validate, JsonSchema)]
+pub struct TodoListParams {
+    #[validate(range(min = 1, max = 100, message = "limit must be between 1 and 100"))]
+    pub limit: Option<i64>,
+    #[validate(range(min = 0, message = "offset must not be negative"))]
+    pub offset: Option<i64>,
+    pub completed: Option<CompletedFilter>,
+    /// With `ids`, repositories may leave label names empty, since only the
+    /// ids are returned.
+    pub labels: Option<LabelsView>,
+    /// Cuts each todo's text to this many characters; applied to the
+    /// response, repositories ignore it.
+    #[validate(range(min = 1, message = "truncate_text must be at least 1"))]
+    pub truncate_text: Option<usize>,
+}
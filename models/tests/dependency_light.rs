@@ -0,0 +1,48 @@
+//! The models must stay usable from WASM frontends, so their default build may
+//! not pull in the server stack.
+
+use std::path::Path;
+use std::process::Command;
+
+const SERVER_CRATES: [&str; 4] = ["sqlx", "tokio", "axum", "hyper"];
+
+fn cargo(args: &[&str]) -> String {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    let target_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("..")
+        .join("target")
+        .join("models-minimal");
+    let output = Command::new(env!("CARGO"))
+        .args(args)
+        .arg("--manifest-path")
+        .arg(manifest)
+        .env("CARGO_TARGET_DIR", target_dir)
+        .output()
+        .expect("failed to run cargo");
+    assert!(
+        output.status.success(),
+        "cargo {:?} failed:\n{}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn default_features_have_no_server_dependencies() {
+    let tree = cargo(&["tree", "--edges", "normal", "--prefix", "none"]);
+    for line in tree.lines() {
+        let name = line.split_whitespace().next().unwrap_or_default();
+        assert!(
+            !SERVER_CRATES.contains(&name),
+            "{} leaked into the models crate:\n{}",
+            name,
+            tree
+        );
+    }
+}
+
+#[test]
+fn default_features_compile() {
+    cargo(&["check", "--lib"]);
+}
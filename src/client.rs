@@ -0,0 +1,552 @@
+use std::collections::BTreeMap;
+
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::models::batch::{Batch, BatchItem, BatchParams, BatchResult};
+use crate::models::id::{LabelId, TodoId};
+use crate::models::label::{
+    BulkLabel, CreateLabel, CreateLabels, Label, LabelListParams, MovedTodos,
+};
+use crate::models::preferences::{Preferences, StoredPreferences};
+use crate::models::todo::{
+    AddDependency, CreateTodo, ListedTodo, RandomTodoParams, SetLabels, SyncParams, Todo,
+    TodoChanges, TodoChangesParams, TodoDependencies, TodoDetail, TodoIds, TodoListParams,
+    TodoSync, UpdateTodo, UpdatedTodo,
+};
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("request failed: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("server answered {status}: {message}")]
+    Status {
+        status: StatusCode,
+        message: String,
+        correlation_id: Option<String>,
+    },
+}
+
+impl ApiError {
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ApiError::Transport(err) => err.status(),
+            ApiError::Status { status, .. } => Some(*status),
+        }
+    }
+}
+
+/// The JSON body the server sends with a 500.
+#[derive(Debug, Deserialize)]
+struct ErrorEnvelope {
+    error: String,
+    correlation_id: Option<String>,
+}
+
+/// Typed client for this API, sharing the server's model types.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl Client {
+    /// `base_url` is the server root, e.g. `http://localhost:3000`; an
+    /// `api_key` is sent as a bearer token.
+    pub fn new(base_url: impl Into<String>, api_key: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key,
+        }
+    }
+
+    pub async fn create_todo(&self, payload: CreateTodo) -> Result<Todo, ApiError> {
+        self.send_json(self.request(Method::POST, "/todos").json(&payload))
+            .await
+    }
+
+    pub async fn find_todo(&self, id: TodoId) -> Result<TodoDetail, ApiError> {
+        self.send_json(self.request(Method::GET, &format!("/todos/{}", id)))
+            .await
+    }
+
+    pub async fn find_todo_by_external_id(&self, external_id: &str) -> Result<Todo, ApiError> {
+        // `+` only means a space in query strings, not in paths
+        let segment = form_urlencoded::byte_serialize(external_id.as_bytes())
+            .collect::<String>()
+            .replace('+', "%20");
+        self.send_json(self.request(Method::GET, &format!("/todos/by-external/{}", segment)))
+            .await
+    }
+
+    pub async fn list_todos(&self, params: TodoListParams) -> Result<Vec<ListedTodo>, ApiError> {
+        self.send_json(self.request(Method::GET, "/todos").query(&params))
+            .await
+    }
+
+    /// Waits up to `timeout` seconds for changes after `since`, or after now
+    /// when `None`; a 410 means the cursor is too old and the list must be
+    /// reloaded.
+    pub async fn poll_todos(
+        &self,
+        since: Option<u64>,
+        timeout: Option<u64>,
+    ) -> Result<TodoChanges, ApiError> {
+        let params = TodoChangesParams {
+            wait: true,
+            since,
+            timeout,
+        };
+        self.send_json(self.request(Method::GET, "/todos").query(&params))
+            .await
+    }
+
+    /// An incomplete todo picked at random, `None` when there is none.
+    pub async fn random_todo(&self, label: Option<LabelId>) -> Result<Option<Todo>, ApiError> {
+        let res = self
+            .send_json(
+                self.request(Method::GET, "/todos/random")
+                    .query(&RandomTodoParams { label }),
+            )
+            .await;
+        match res {
+            Err(err) if err.status() == Some(StatusCode::NOT_FOUND) => Ok(None),
+            res => res.map(Some),
+        }
+    }
+
+    /// Changes since the `token` of an earlier sync, or everything when
+    /// `None`.
+    pub async fn sync_todos(&self, since: Option<u64>) -> Result<TodoSync, ApiError> {
+        let params = SyncParams { since };
+        self.send_json(self.request(Method::GET, "/todos/sync").query(&params))
+            .await
+    }
+
+    pub async fn update_todo(
+        &self,
+        id: TodoId,
+        payload: UpdateTodo,
+    ) -> Result<UpdatedTodo, ApiError> {
+        self.send_json(
+            self.request(Method::PATCH, &format!("/todos/{}", id))
+                .json(&payload),
+        )
+        .await
+    }
+
+    /// Whether each of `ids` is a todo, keyed by id.
+    pub async fn todos_exist(&self, ids: Vec<TodoId>) -> Result<BTreeMap<TodoId, bool>, ApiError> {
+        let payload = TodoIds { ids };
+        self.send_json(self.request(Method::POST, "/todos/exists").json(&payload))
+            .await
+    }
+
+    pub async fn delete_todo(&self, id: TodoId) -> Result<(), ApiError> {
+        self.send(self.request(Method::DELETE, &format!("/todos/{}", id)))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn pin_todo(&self, id: TodoId) -> Result<Todo, ApiError> {
+        self.send_json(self.request(Method::POST, &format!("/todos/{}/pin", id)))
+            .await
+    }
+
+    pub async fn unpin_todo(&self, id: TodoId) -> Result<Todo, ApiError> {
+        self.send_json(self.request(Method::POST, &format!("/todos/{}/unpin", id)))
+            .await
+    }
+
+    pub async fn set_labels(&self, id: TodoId, label_ids: Vec<LabelId>) -> Result<Todo, ApiError> {
+        let payload = SetLabels { label_ids };
+        self.send_json(
+            self.request(Method::PUT, &format!("/todos/{}/labels", id))
+                .json(&payload),
+        )
+        .await
+    }
+
+    /// Makes `id` wait for `depends_on`; a 400 means that would close a cycle.
+    pub async fn add_dependency(
+        &self,
+        id: TodoId,
+        depends_on: TodoId,
+    ) -> Result<TodoDependencies, ApiError> {
+        let payload = AddDependency { depends_on };
+        self.send_json(
+            self.request(Method::POST, &format!("/todos/{}/dependencies", id))
+                .json(&payload),
+        )
+        .await
+    }
+
+    pub async fn remove_dependency(
+        &self,
+        id: TodoId,
+        depends_on: TodoId,
+    ) -> Result<TodoDependencies, ApiError> {
+        self.send_json(self.request(
+            Method::DELETE,
+            &format!("/todos/{}/dependencies/{}", id, depends_on),
+        ))
+        .await
+    }
+
+    pub async fn reset_todo(&self, id: TodoId) -> Result<Todo, ApiError> {
+        self.send_json(self.request(Method::POST, &format!("/todos/{}/reset", id)))
+            .await
+    }
+
+    pub async fn move_to_label(&self, id: TodoId, label_id: LabelId) -> Result<Todo, ApiError> {
+        self.send_json(self.request(
+            Method::POST,
+            &format!("/todos/{}/move-to-label/{}", id, label_id),
+        ))
+        .await
+    }
+
+    pub async fn create_label(&self, payload: CreateLabel) -> Result<Label, ApiError> {
+        self.send_json(self.request(Method::POST, "/labels").json(&payload))
+            .await
+    }
+
+    pub async fn create_labels(&self, payload: CreateLabels) -> Result<Vec<BulkLabel>, ApiError> {
+        self.send_json(self.request(Method::POST, "/labels/bulk").json(&payload))
+            .await
+    }
+
+    /// Returns the label named `name`, creating it if it doesn't exist.
+    pub async fn ensure_label(&self, name: &str) -> Result<Label, ApiError> {
+        self.send_json(
+            self.request(Method::PUT, "/labels")
+                .query(&[("name", name)]),
+        )
+        .await
+    }
+
+    pub async fn list_labels(&self) -> Result<Vec<Label>, ApiError> {
+        self.send_json(self.request(Method::GET, "/labels")).await
+    }
+
+    /// Labels whose name contains `q`, or is close to it when `fuzzy`.
+    pub async fn search_labels(&self, q: &str, fuzzy: bool) -> Result<Vec<Label>, ApiError> {
+        let params = LabelListParams {
+            q: Some(q.to_string()),
+            fuzzy,
+            ..LabelListParams::default()
+        };
+        self.send_json(self.request(Method::GET, "/labels").query(&params))
+            .await
+    }
+
+    pub async fn suggest_labels(&self, prefix: &str) -> Result<Vec<Label>, ApiError> {
+        self.send_json(
+            self.request(Method::GET, "/labels/suggest")
+                .query(&[("prefix", prefix)]),
+        )
+        .await
+    }
+
+    pub async fn move_todos(&self, from: LabelId, to: LabelId) -> Result<MovedTodos, ApiError> {
+        self.send_json(self.request(Method::POST, &format!("/labels/{}/move-todos/{}", from, to)))
+            .await
+    }
+
+    pub async fn delete_label(&self, id: LabelId) -> Result<(), ApiError> {
+        self.send(self.request(Method::DELETE, &format!("/labels/{}", id)))
+            .await?;
+        Ok(())
+    }
+
+    /// Sends `items` as one request; a call failing doesn't fail the batch,
+    /// it shows in its [`BatchResult::status`].
+    pub async fn batch(
+        &self,
+        items: Vec<BatchItem>,
+        stop_on_error: bool,
+    ) -> Result<Vec<BatchResult>, ApiError> {
+        self.send_json(
+            self.request(Method::POST, "/batch")
+                .query(&BatchParams { stop_on_error })
+                .json(&Batch { items }),
+        )
+        .await
+    }
+
+    pub async fn preferences(&self) -> Result<StoredPreferences, ApiError> {
+        self.send_json(self.request(Method::GET, "/preferences"))
+            .await
+    }
+
+    /// Replaces this API key's preferences as a whole.
+    pub async fn put_preferences(
+        &self,
+        preferences: &Preferences,
+    ) -> Result<StoredPreferences, ApiError> {
+        self.send_json(self.request(Method::PUT, "/preferences").json(preferences))
+            .await
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let builder = self
+            .http
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.api_key {
+            Some(key) => builder.bearer_auth(key),
+            None => builder,
+        }
+    }
+
+    async fn send(&self, builder: RequestBuilder) -> Result<Response, ApiError> {
+        let res = builder.send().await?;
+        let status = res.status();
+        if status.is_success() {
+            return Ok(res);
+        }
+
+        let body = res.text().await?;
+        let error = match serde_json::from_str::<ErrorEnvelope>(&body) {
+            Ok(envelope) => ApiError::Status {
+                status,
+                message: envelope.error,
+                correlation_id: envelope.correlation_id,
+            },
+            Err(_) => ApiError::Status {
+                status,
+                message: if body.is_empty() {
+                    status.canonical_reason().unwrap_or_default().to_string()
+                } else {
+                    body
+                },
+                correlation_id: None,
+            },
+        };
+        Err(error)
+    }
+
+    async fn send_json<T: DeserializeOwned>(&self, builder: RequestBuilder) -> Result<T, ApiError> {
+        Ok(self.send(builder).await?.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use crate::config::AppConfig;
+    use crate::create_app;
+    use crate::models::todo::{TodoChangeEntry, TodoField};
+    use crate::repositories::{
+        circuit_breaker::test_utils::FlakyTodoRepository,
+        label_repository::test_utils::LabelRepositoryForMemory,
+        preferences_repository::test_utils::PreferencesRepositoryForMemory,
+        todo_repository::{test_utils::TodoRepositoryForMemory, TodoRepository},
+    };
+
+    use super::*;
+
+    async fn spawn_server() -> Client {
+        let labels = LabelRepositoryForMemory::new();
+        spawn_app(TodoRepositoryForMemory::with_labels(labels.clone()), labels).await
+    }
+
+    async fn spawn_app<T: TodoRepository>(todos: T, labels: LabelRepositoryForMemory) -> Client {
+        let app = create_app(
+            todos,
+            labels,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        Client::new(format!("http://{}", addr), Some("secret".to_string()))
+    }
+
+    #[tokio::test]
+    async fn client_round_trips_every_endpoint() {
+        let client = spawn_server().await;
+
+        let label = client
+            .create_label(CreateLabel {
+                name: "work".to_string(),
+            })
+            .await
+            .unwrap();
+        let other = client
+            .create_label(CreateLabel {
+                name: "home".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![label.clone()],
+            client.suggest_labels("wo").await.unwrap()
+        );
+        assert_eq!(
+            vec![label.clone()],
+            client.search_labels("wrok", true).await.unwrap()
+        );
+        let bulk = client
+            .create_labels(CreateLabels {
+                names: vec!["work".to_string(), "errands".to_string()],
+            })
+            .await
+            .unwrap();
+        assert!(!bulk[0].created);
+        assert!(bulk[1].created);
+        assert_eq!(label, client.ensure_label("work").await.unwrap());
+
+        let todo = client
+            .create_todo(CreateTodo {
+                text: "client todo".to_string(),
+                labels: vec![label.id],
+                external_id: Some("crm/7 a+b".to_string()),
+            })
+            .await
+            .unwrap();
+        assert_eq!(vec![label.clone()], todo.labels);
+        assert_eq!(todo, client.find_todo(todo.id).await.unwrap().todo);
+        assert_eq!(
+            todo,
+            client.find_todo_by_external_id("crm/7 a+b").await.unwrap()
+        );
+        assert_eq!(
+            vec![ListedTodo::from(todo.clone())],
+            client.list_todos(TodoListParams::default()).await.unwrap()
+        );
+        let changes = client.poll_todos(Some(0), Some(1)).await.unwrap();
+        assert_eq!(
+            vec![TodoChangeEntry::Created { todo: todo.clone() }],
+            changes.changes
+        );
+        assert!(client
+            .poll_todos(Some(changes.cursor), Some(1))
+            .await
+            .unwrap()
+            .changes
+            .is_empty());
+
+        let UpdatedTodo {
+            todo,
+            changed_fields,
+        } = client
+            .update_todo(
+                todo.id,
+                UpdateTodo {
+                    text: None,
+                    completed: Some(true),
+                    external_id: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert!(todo.completed);
+        assert_eq!(vec![TodoField::Completed], changed_fields);
+
+        let todo = client.move_to_label(todo.id, other.id).await.unwrap();
+        assert_eq!(vec![other.clone()], todo.labels);
+
+        let todo = client
+            .set_labels(todo.id, vec![label.id, other.id])
+            .await
+            .unwrap();
+        assert_eq!(vec![label.clone(), other.clone()], todo.labels);
+        let todo = client.set_labels(todo.id, vec![other.id]).await.unwrap();
+        assert_eq!(vec![other.clone()], todo.labels);
+
+        assert!(client.pin_todo(todo.id).await.unwrap().pinned);
+        assert!(!client.unpin_todo(todo.id).await.unwrap().pinned);
+        assert!(client.reset_todo(todo.id).await.unwrap().labels.is_empty());
+
+        client.delete_todo(todo.id).await.unwrap();
+        client.delete_label(label.id).await.unwrap();
+        let labels = client.list_labels().await.unwrap();
+        assert!(labels.contains(&other));
+        assert!(!labels.contains(&label));
+
+        let results = client
+            .batch(
+                vec![
+                    BatchItem {
+                        method: "GET".to_string(),
+                        path: format!("/todos/{}", todo.id),
+                        body: None,
+                        headers: Default::default(),
+                    },
+                    BatchItem {
+                        method: "GET".to_string(),
+                        path: "/labels".to_string(),
+                        body: None,
+                        headers: Default::default(),
+                    },
+                ],
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![404, 200],
+            results.iter().map(|r| r.status).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn client_surfaces_error_statuses() {
+        let client = spawn_server().await;
+
+        let err = client
+            .find_todo(TodoId::new(999).unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(Some(StatusCode::NOT_FOUND), err.status());
+
+        let err = client
+            .create_todo(CreateTodo {
+                text: "".to_string(),
+                labels: vec![],
+                external_id: None,
+            })
+            .await
+            .unwrap_err();
+        match err {
+            ApiError::Status {
+                status, message, ..
+            } => {
+                assert_eq!(StatusCode::BAD_REQUEST, status);
+                assert!(message.starts_with("validation error"));
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn client_decodes_internal_error_envelope() {
+        let todos = FlakyTodoRepository::new();
+        todos.set_down(true);
+        let client = spawn_app(todos, LabelRepositoryForMemory::new()).await;
+
+        match client
+            .list_todos(TodoListParams::default())
+            .await
+            .unwrap_err()
+        {
+            ApiError::Status {
+                status,
+                message,
+                correlation_id,
+            } => {
+                assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+                assert_eq!("internal error", message);
+                assert!(correlation_id.is_some());
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+}
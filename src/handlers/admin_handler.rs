@@ -0,0 +1,29 @@
+use std::sync::Arc;
+
+use axum::extract::{Extension, Query};
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use serde::Deserialize;
+
+use crate::repositories::todo_repository::TodoRepository;
+
+use super::*;
+
+#[derive(Debug, Deserialize)]
+pub struct RepairParams {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+pub async fn repair<T: TodoRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    Query(params): Query<RepairParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let report = repository
+        .repair(params.dry_run)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    if !params.dry_run {
+        tracing::warn!(?report, "repaired database");
+    }
+    Ok((StatusCode::OK, Json(report)))
+}
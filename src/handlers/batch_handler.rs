@@ -0,0 +1,145 @@
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+use axum::body::Body;
+use axum::extract::{Extension, FromRequest, Query, RequestParts};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{HeaderName, HeaderValue, Method, Request, StatusCode, Uri};
+use axum::response::IntoResponse;
+use axum::{Json, Router};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use crate::models::batch::{Batch, BatchItem, BatchParams, BatchResult};
+
+use super::*;
+
+/// The routes batch calls are sent to: every extension is layered, but not
+/// the concurrency limit, whose slot the batch request already holds.
+#[derive(Clone)]
+pub struct BatchRouter(Arc<Mutex<Router>>);
+
+impl BatchRouter {
+    pub fn new(router: Router) -> Self {
+        // the router is Send but not Sync, as extensions must be
+        Self(Arc::new(Mutex::new(router)))
+    }
+
+    fn router(&self) -> Router {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// The batch request's `Authorization`, which every call is sent with, so
+/// calls act for the same caller as the batch.
+pub struct CallerAuthorization(Option<HeaderValue>);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for CallerAuthorization {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let value = req
+            .headers()
+            .and_then(|headers| headers.get(AUTHORIZATION))
+            .cloned();
+        Ok(CallerAuthorization(value))
+    }
+}
+
+/// Runs each call through the router as if it had been sent on its own, in
+/// order, and answers 200 with one result per call that ran.
+pub async fn batch(
+    Extension(router): Extension<BatchRouter>,
+    CallerAuthorization(authorization): CallerAuthorization,
+    Query(params): Query<BatchParams>,
+    ValidatedJson(batch): ValidatedJson<Batch>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut results = Vec::with_capacity(batch.items.len());
+    for item in batch.items {
+        let result = dispatch(router.router(), authorization.as_ref(), item).await;
+        let failed = result.status >= 400;
+        results.push(result);
+        if failed && params.stop_on_error {
+            break;
+        }
+    }
+    Ok((StatusCode::OK, Json(results)))
+}
+
+async fn dispatch(
+    router: Router,
+    authorization: Option<&HeaderValue>,
+    item: BatchItem,
+) -> BatchResult {
+    let req = match batch_request(authorization, item) {
+        Ok(req) => req,
+        Err(message) => {
+            return BatchResult {
+                status: StatusCode::BAD_REQUEST.as_u16(),
+                body: Value::String(message.to_string()),
+            }
+        }
+    };
+    let res = match router.oneshot(req).await {
+        Ok(res) => res,
+        Err(infallible) => match infallible {},
+    };
+    let status = res.status().as_u16();
+    // an event stream never ends, so its body can't be collected
+    let streaming = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"text/event-stream"));
+    if streaming {
+        return BatchResult {
+            status: StatusCode::BAD_REQUEST.as_u16(),
+            body: Value::String("event streams can't be batched".to_string()),
+        };
+    }
+    let body = match hyper::body::to_bytes(res.into_body()).await {
+        Ok(bytes) if bytes.is_empty() => Value::Null,
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned())),
+        Err(e) => {
+            return BatchResult {
+                status: StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                body: Value::String(e.to_string()),
+            }
+        }
+    };
+    BatchResult { status, body }
+}
+
+/// The call's own headers replace those of the batch with the same name.
+fn batch_request(
+    authorization: Option<&HeaderValue>,
+    item: BatchItem,
+) -> Result<Request<Body>, &'static str> {
+    let method = Method::from_bytes(item.method.to_ascii_uppercase().as_bytes())
+        .map_err(|_| "invalid method")?;
+    let uri = item.path.parse::<Uri>().map_err(|_| "invalid path")?;
+    if uri.scheme().is_some() || !uri.path().starts_with('/') {
+        return Err("path must be on this server");
+    }
+    if uri.path().trim_end_matches('/') == "/batch" {
+        return Err("batches can't be nested");
+    }
+    let mut builder = Request::builder().method(method).uri(uri);
+    if let Some(authorization) = authorization {
+        builder = builder.header(AUTHORIZATION, authorization);
+    }
+    let mut req = match item.body {
+        Some(body) => builder
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => builder.body(Body::empty()),
+    }
+    .map_err(|_| "invalid request")?;
+    for (name, value) in item.headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| "invalid header")?;
+        let value = HeaderValue::from_str(&value).map_err(|_| "invalid header")?;
+        req.headers_mut().insert(name, value);
+    }
+    Ok(req)
+}
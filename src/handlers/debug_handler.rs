@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::Extension,
+    http::{HeaderMap, Method, StatusCode, Uri},
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+
+use crate::routes::RouteInfo;
+
+#[cfg(feature = "console")]
+#[derive(Debug, Serialize)]
+pub struct RuntimeSummary {
+    pub workers: usize,
+    pub alive_tasks: usize,
+    pub global_queue_depth: usize,
+}
+
+#[cfg(feature = "console")]
+pub async fn debug_tasks() -> impl IntoResponse {
+    let metrics = tokio::runtime::Handle::current().metrics();
+    let summary = RuntimeSummary {
+        workers: metrics.num_workers(),
+        alive_tasks: metrics.num_alive_tasks(),
+        global_queue_depth: metrics.global_queue_depth(),
+    };
+    (StatusCode::OK, Json(summary))
+}
+
+pub async fn debug_routes(Extension(routes): Extension<Arc<Vec<RouteInfo>>>) -> impl IntoResponse {
+    (StatusCode::OK, Json(routes.as_ref().clone()))
+}
+
+#[derive(Debug, Serialize)]
+pub struct Echo {
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: BTreeMap<String, Vec<String>>,
+    pub body: String,
+}
+
+/// Reflects the request exactly as it reached the server, after any proxies.
+pub async fn debug_echo(
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let mut echoed = BTreeMap::<String, Vec<String>>::new();
+    for (name, value) in headers.iter() {
+        echoed
+            .entry(name.to_string())
+            .or_default()
+            .push(String::from_utf8_lossy(value.as_bytes()).into_owned());
+    }
+    let echo = Echo {
+        method: method.to_string(),
+        path: uri.path().to_string(),
+        query: uri.query().map(str::to_string),
+        headers: echoed,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    };
+    (StatusCode::OK, Json(echo))
+}
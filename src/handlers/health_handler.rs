@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use axum::extract::{Extension, Query};
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::load::LoadMonitor;
+use crate::repositories::todo_repository::TodoRepository;
+
+use super::*;
+
+#[derive(Debug, Deserialize)]
+pub struct HealthParams {
+    /// Also check that the database accepts writes, which a replica or a
+    /// full disk doesn't.
+    #[serde(default)]
+    pub write: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DbHealth {
+    pub status: &'static str,
+    pub write: bool,
+}
+
+/// `SELECT 1` by default; with `?write=true` a write rolled back, answering
+/// 503 when the database is read-only.
+pub async fn db_health<T: TodoRepository>(
+    Query(params): Query<HealthParams>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    repository.ping(params.write).await.map_err(|e| {
+        tracing::warn!(write = params.write, "database health check failed: {}", e);
+        ApiError::from_repository(e, StatusCode::SERVICE_UNAVAILABLE)
+    })?;
+    let health = DbHealth {
+        status: "ok",
+        write: params.write,
+    };
+    Ok((StatusCode::OK, Json(health)))
+}
+
+/// The load report for the autoscaler; 503 once pressure passes
+/// [`ReadinessConfig::max_pressure`](crate::load::ReadinessConfig), until it
+/// drops back to the recover level.
+pub async fn readiness<T: TodoRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    Extension(monitor): Extension<LoadMonitor>,
+) -> impl IntoResponse {
+    let report = monitor.report(repository.pool_usage());
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
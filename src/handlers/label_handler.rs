@@ -1,39 +1,109 @@
 use std::sync::Arc;
 
-use axum::extract::Path;
+use axum::extract::Query;
+use axum::http::HeaderName;
 use axum::{extract::Extension, http::StatusCode, response::IntoResponse, Json};
 
-use crate::models::label::CreateLabel;
+use crate::models::id::LabelId;
+use crate::models::label::{CreateLabel, CreateLabels, LabelListParams, MovedTodos, SuggestLabel};
 use crate::repositories::label_repository::LabelRepository;
+use crate::repositories::todo_repository::TodoRepository;
 
 use super::*;
 
+pub const X_TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+
 pub async fn create_label<T: LabelRepository>(
-    ValidatedJson(payload): ValidatedJson<CreateLabel>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
+    ValidatedJson(payload): ValidatedJson<CreateLabel>,
+) -> Result<impl IntoResponse, ApiError> {
     let label = repository
         .create(payload.name)
         .await
-        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+        .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
 
     Ok((StatusCode::CREATED, Json(label)))
 }
 
+pub async fn create_labels<T: LabelRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    ValidatedJson(payload): ValidatedJson<CreateLabels>,
+) -> Result<impl IntoResponse, ApiError> {
+    let labels = repository
+        .create_many(payload.names)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok((StatusCode::OK, Json(labels)))
+}
+
+pub async fn ensure_label<T: LabelRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    ValidatedQuery(query): ValidatedQuery<CreateLabel>,
+) -> Result<impl IntoResponse, ApiError> {
+    let label = repository
+        .ensure(query.name)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok((StatusCode::OK, Json(label)))
+}
+
+/// Every label by id unless a search, page or sort is asked for; a page also
+/// reports the number of matching labels in `X-Total-Count`.
 pub async fn all_label<T: LabelRepository>(
+    ValidatedQuery(params): ValidatedQuery<LabelListParams>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut headers = Vec::new();
+    if params.is_paginated() {
+        let total = repository
+            .count(&params)
+            .await
+            .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+        headers.push((X_TOTAL_COUNT, total.to_string()));
+    }
+    let labels = repository
+        .all(params)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Headers(headers), Json(labels)))
+}
+
+pub async fn suggest_label<T: LabelRepository>(
+    Query(query): Query<SuggestLabel>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let labels = repository.all().await.unwrap();
+) -> Result<impl IntoResponse, ApiError> {
+    let labels = repository
+        .suggest(&query.prefix)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
     Ok((StatusCode::OK, Json(labels)))
 }
 
+/// Moves every todo labeled `from` to `to`, keeping the `from` label itself.
+pub async fn move_todos<T: TodoRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    ValidatedPath((from, to)): ValidatedPath<(LabelId, LabelId)>,
+) -> Result<impl IntoResponse, ApiError> {
+    if from == to {
+        return Err(ApiError::Status(StatusCode::BAD_REQUEST));
+    }
+    let todos = repository
+        .move_label(from, to)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))?;
+
+    Ok((StatusCode::OK, Json(MovedTodos { moved: todos.len() })))
+}
+
 pub async fn delete_label<T: LabelRepository>(
-    Path(id): Path<i32>,
     Extension(repository): Extension<Arc<T>>,
-) -> StatusCode {
+    ValidatedPath(id): ValidatedPath<LabelId>,
+) -> Result<StatusCode, ApiError> {
     repository
         .delete(id)
         .await
         .map(|_| StatusCode::NO_CONTENT)
-        .unwrap_or(StatusCode::NOT_FOUND)
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))
 }
@@ -1,13 +1,30 @@
+use std::time::Duration;
+
 use axum::extract::{FromRequest, Path, RequestParts};
-use axum::{
-    async_trait, extract::Extension, http::StatusCode, response::IntoResponse, BoxError, Json,
-};
+use axum::http::header::RETRY_AFTER;
+use axum::response::{Headers, IntoResponse, Response};
+use axum::{async_trait, http::StatusCode, BoxError, Json};
 use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tower::load_shed::error::Overloaded;
 use validator::Validate;
 
-use crate::models::todo::{CreateTodo, UpdateTodo};
+use crate::middlewares::current_request_id;
+use crate::models::id::{InvalidId, LabelId, TodoId};
+use crate::repositories::circuit_breaker::CircuitOpen;
+use crate::repositories::deadline::is_deadline_exceeded;
+use crate::repositories::RepositoryError;
+
+/// How long clients shed by the concurrency limit should wait before retrying.
+pub const OVERLOAD_RETRY_AFTER: Duration = Duration::from_secs(1);
 
+pub mod admin_handler;
+pub mod batch_handler;
+pub mod debug_handler;
+pub mod health_handler;
 pub mod label_handler;
+pub mod preferences_handler;
+pub mod schema_handler;
 pub mod todo_handler;
 
 #[derive(Debug)]
@@ -24,14 +41,417 @@ where
     type Rejection = (StatusCode, String);
 
     async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
-        let Json(value) = Json::<T>::from_request(req).await.map_err(|rejection| {
-            let message = format!("json parse error: {}", rejection);
+        let value = parse_json(req).await?;
+        validate(&value)?;
+        Ok(ValidatedJson(value))
+    }
+}
+
+async fn parse_json<T, B>(req: &mut RequestParts<B>) -> Result<T, (StatusCode, String)>
+where
+    T: DeserializeOwned,
+    B: http_body::Body + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    let Json(value) = Json::<T>::from_request(req).await.map_err(|rejection| {
+        let message = format!("json parse error: {}", rejection);
+        (StatusCode::BAD_REQUEST, message)
+    })?;
+    Ok(value)
+}
+
+fn validate<T: Validate>(value: &T) -> Result<(), (StatusCode, String)> {
+    value.validate().map_err(|rejection| {
+        let message = format!("validation error: [{}]", rejection).replace('\n', ", ");
+        (StatusCode::BAD_REQUEST, message)
+    })
+}
+
+#[derive(Debug)]
+pub struct ValidatedQuery<T>(T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    B: Send,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let query = req.uri().query().unwrap_or_default();
+        let deserializer =
+            serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        // the path names the offending parameter, which serde_urlencoded leaves out
+        let value: T = serde_path_to_error::deserialize(deserializer).map_err(|rejection| {
+            let message = match rejection.path().to_string().as_str() {
+                "." => format!("query parse error: {}", rejection.inner()),
+                path => format!("query parse error: {}: {}", path, rejection.inner()),
+            };
             (StatusCode::BAD_REQUEST, message)
         })?;
-        value.validate().map_err(|rejection| {
-            let message = format!("validation error: [{}]", rejection).replace('\n', ", ");
-            (StatusCode::BAD_REQUEST, message)
+        validate(&value)?;
+        Ok(ValidatedQuery(value))
+    }
+}
+
+/// Path parameters made only of ids, in route order.
+pub trait PathIds: Sized {
+    fn from_params(params: &[String]) -> Result<Self, InvalidId>;
+}
+
+fn param(params: &[String], index: usize) -> &str {
+    params.get(index).map(String::as_str).unwrap_or_default()
+}
+
+impl PathIds for TodoId {
+    fn from_params(params: &[String]) -> Result<Self, InvalidId> {
+        param(params, 0).parse()
+    }
+}
+
+impl PathIds for LabelId {
+    fn from_params(params: &[String]) -> Result<Self, InvalidId> {
+        param(params, 0).parse()
+    }
+}
+
+impl PathIds for (TodoId, LabelId) {
+    fn from_params(params: &[String]) -> Result<Self, InvalidId> {
+        Ok((param(params, 0).parse()?, param(params, 1).parse()?))
+    }
+}
+
+impl PathIds for (TodoId, TodoId) {
+    fn from_params(params: &[String]) -> Result<Self, InvalidId> {
+        Ok((param(params, 0).parse()?, param(params, 1).parse()?))
+    }
+}
+
+impl PathIds for (LabelId, LabelId) {
+    fn from_params(params: &[String]) -> Result<Self, InvalidId> {
+        Ok((param(params, 0).parse()?, param(params, 1).parse()?))
+    }
+}
+
+/// Like `Path`, but a bad id answers 400 naming the value and the valid range.
+#[derive(Debug)]
+pub struct ValidatedPath<T>(T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatedPath<T>
+where
+    T: PathIds,
+    B: Send,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Path(params) = Path::<Vec<String>>::from_request(req)
+            .await
+            .map_err(|rejection| (StatusCode::BAD_REQUEST, rejection.to_string()))?;
+        let value = T::from_params(&params).map_err(|rejection| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("path error: {}", rejection),
+            )
         })?;
-        Ok(ValidatedJson(value))
+        Ok(ValidatedPath(value))
+    }
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    Status(StatusCode),
+    Unavailable(Duration),
+    /// The request's deadline passed before the database answered.
+    DeadlineExceeded,
+    /// Labels named in the request that don't exist.
+    LabelsNotFound(Vec<i32>),
+    /// Dependencies still open on a todo being completed.
+    DependenciesOpen(Vec<TodoId>),
+    /// The request duplicates a resource that already exists.
+    Conflict(Conflict),
+    Internal(anyhow::Error),
+}
+
+/// Body of a 500 or a missed deadline: the cause stays in the logs under
+/// `correlation_id`.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub error: &'static str,
+    pub correlation_id: String,
+}
+
+/// Body of a 404 for labels that don't exist, listing every missing id.
+#[derive(Debug, Serialize)]
+pub struct LabelsNotFoundBody {
+    pub error: &'static str,
+    pub missing: Vec<i32>,
+}
+
+/// Body of a 409 for completing a todo that still waits for others.
+#[derive(Debug, Serialize)]
+pub struct DependenciesOpenBody {
+    pub error: &'static str,
+    pub open: Vec<TodoId>,
+}
+
+/// How a client can get past a duplicate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    /// The existing resource is the one asked for.
+    UseExisting,
+    /// The new resource needs a different value for the matched fields,
+    /// like another name for a label.
+    Rename,
+    /// Both describe the same thing and should become one.
+    Merge,
+}
+
+/// The existing resource a request collided with, as a `GET` would return it.
+#[derive(Debug, Serialize)]
+pub struct Conflict {
+    pub resource: serde_json::Value,
+    /// Fields of the request that matched `resource`.
+    pub matched: Vec<&'static str>,
+    pub resolution: Resolution,
+}
+
+impl Conflict {
+    pub fn new<T: Serialize>(
+        resource: &T,
+        matched: Vec<&'static str>,
+        resolution: Resolution,
+    ) -> serde_json::Result<Self> {
+        Ok(Self {
+            resource: serde_json::to_value(resource)?,
+            matched,
+            resolution,
+        })
+    }
+}
+
+/// Body of a 409 for a duplicate, embedding what it duplicates.
+#[derive(Debug, Serialize)]
+pub struct ConflictBody {
+    pub error: &'static str,
+    pub conflict: Conflict,
+}
+
+impl ApiError {
+    /// A 409 embedding `resource`, or a 500 if it can't be serialized,
+    /// rather than a conflict pointing at nothing.
+    pub fn conflict<T: Serialize>(
+        resource: &T,
+        matched: Vec<&'static str>,
+        resolution: Resolution,
+    ) -> Self {
+        match Conflict::new(resource, matched, resolution) {
+            Ok(conflict) => ApiError::Conflict(conflict),
+            Err(e) => ApiError::Internal(e.into()),
+        }
+    }
+
+    /// Maps a repository error, answering with `status` unless the database
+    /// is known to be unavailable, the error is unexpected or it has a
+    /// status of its own, like missing labels.
+    pub fn from_repository(err: anyhow::Error, status: StatusCode) -> Self {
+        if let Some(open) = err.downcast_ref::<CircuitOpen>() {
+            return ApiError::Unavailable(open.retry_after);
+        }
+        if is_deadline_exceeded(&err) {
+            return ApiError::DeadlineExceeded;
+        }
+        match err.downcast_ref::<RepositoryError>() {
+            Some(RepositoryError::Unexpected(_)) => ApiError::Internal(err),
+            Some(RepositoryError::Duplicate(label)) => {
+                ApiError::conflict(label, vec!["name"], Resolution::Rename)
+            }
+            Some(RepositoryError::DuplicateExternalId(_) | RepositoryError::CompletedLocked(_)) => {
+                ApiError::Status(StatusCode::CONFLICT)
+            }
+            Some(RepositoryError::DependencyCycle(..) | RepositoryError::DependencyTooDeep(..)) => {
+                ApiError::Status(StatusCode::BAD_REQUEST)
+            }
+            Some(RepositoryError::LabelsNotFound(missing)) => {
+                ApiError::LabelsNotFound(missing.clone())
+            }
+            _ if status == StatusCode::INTERNAL_SERVER_ERROR => ApiError::Internal(err),
+            _ => ApiError::Status(status),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self {
+            ApiError::Status(status) => status.into_response(),
+            ApiError::Unavailable(retry_after) => {
+                // round up so clients never retry before the cool-down ends
+                let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    Headers(vec![(RETRY_AFTER, secs.to_string())]),
+                    (),
+                )
+                    .into_response()
+            }
+            ApiError::DeadlineExceeded => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ErrorBody {
+                    error: "deadline exceeded",
+                    correlation_id: current_request_id().unwrap_or_default(),
+                }),
+            )
+                .into_response(),
+            ApiError::LabelsNotFound(missing) => (
+                StatusCode::NOT_FOUND,
+                Json(LabelsNotFoundBody {
+                    error: "labels not found",
+                    missing,
+                }),
+            )
+                .into_response(),
+            ApiError::DependenciesOpen(open) => (
+                StatusCode::CONFLICT,
+                Json(DependenciesOpenBody {
+                    error: "dependencies still open",
+                    open,
+                }),
+            )
+                .into_response(),
+            ApiError::Conflict(conflict) => (
+                StatusCode::CONFLICT,
+                Json(ConflictBody {
+                    error: "duplicate",
+                    conflict,
+                }),
+            )
+                .into_response(),
+            ApiError::Internal(err) => {
+                let correlation_id = current_request_id().unwrap_or_default();
+                tracing::error!(%correlation_id, "internal error: {:?}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorBody {
+                        error: "internal error",
+                        correlation_id,
+                    }),
+                )
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// Error handler for the concurrency limit layers.
+pub async fn handle_overload(err: BoxError) -> ApiError {
+    if err.is::<Overloaded>() {
+        ApiError::Unavailable(OVERLOAD_RETRY_AFTER)
+    } else {
+        ApiError::Internal(anyhow::anyhow!(err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    use crate::models::todo::TodoListParams;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Required {
+        #[allow(dead_code)]
+        q: String,
+    }
+
+    async fn list(ValidatedQuery(params): ValidatedQuery<TodoListParams>) -> String {
+        format!("{:?} {:?}", params.limit, params.offset)
+    }
+
+    async fn search(ValidatedQuery(_params): ValidatedQuery<Required>) {}
+
+    async fn send(uri: &str) -> (StatusCode, String) {
+        let app = Router::new()
+            .route("/todos", get(list))
+            .route("/search", get(search));
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let status = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn validated_query_accepts_valid_params() {
+        assert_eq!(
+            (StatusCode::OK, "Some(10) Some(20)".to_string()),
+            send("/todos?limit=10&offset=20").await
+        );
+        assert_eq!(
+            (StatusCode::OK, "None None".to_string()),
+            send("/todos").await
+        );
+    }
+
+    #[tokio::test]
+    async fn validated_query_rejects_missing_params() {
+        let (status, message) = send("/search").await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!("query parse error: missing field `q`", message);
+    }
+
+    #[tokio::test]
+    async fn validated_query_rejects_malformed_params() {
+        let (status, message) = send("/todos?limit=ten").await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!(
+            "query parse error: limit: invalid digit found in string",
+            message
+        );
+    }
+
+    #[tokio::test]
+    async fn validated_query_rejects_out_of_range_params() {
+        for uri in ["/todos?limit=0", "/todos?limit=101", "/todos?offset=-1"] {
+            let (status, message) = send(uri).await;
+            assert_eq!(StatusCode::BAD_REQUEST, status, "{}", uri);
+            assert!(message.starts_with("validation error"), "{}", message);
+        }
+        let (_, message) = send("/todos?limit=101").await;
+        assert!(
+            message.contains("limit must be between 1 and 100"),
+            "{}",
+            message
+        );
+    }
+
+    #[tokio::test]
+    async fn missed_deadline_is_a_distinct_503() {
+        let err = ApiError::from_repository(
+            crate::repositories::deadline::DeadlineExceeded.into(),
+            StatusCode::NOT_FOUND,
+        );
+        let res = err.into_response();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+        assert!(res.headers().get(RETRY_AFTER).is_none());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("deadline exceeded", body["error"]);
+    }
+
+    #[test]
+    fn unserializable_conflict_is_an_internal_error() {
+        // maps with non-string keys have no JSON form
+        let resource = std::collections::HashMap::from([((1, 2), "pair")]);
+        let err = ApiError::conflict(&resource, vec!["name"], Resolution::Rename);
+        assert!(matches!(err, ApiError::Internal(_)), "{:?}", err);
     }
 }
@@ -0,0 +1,58 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::extract::{Extension, FromRequest, RequestParts};
+use axum::http::header::AUTHORIZATION;
+use axum::{async_trait, http::StatusCode, response::IntoResponse, Json};
+use sha2::{Digest, Sha256};
+
+use crate::models::preferences::Preferences;
+use crate::repositories::preferences_repository::PreferencesRepository;
+
+use super::*;
+
+/// Whose preferences a request reads and writes: a SHA-256 of its bearer
+/// token, so the key itself is never stored. Requests without one share the
+/// preferences of the empty token.
+#[derive(Debug)]
+pub struct Owner(String);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for Owner {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let token = req
+            .headers()
+            .and_then(|headers| headers.get(AUTHORIZATION))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default()
+            .trim();
+        Ok(Owner(format!("{:x}", Sha256::digest(token.as_bytes()))))
+    }
+}
+
+pub async fn get_preferences<T: PreferencesRepository>(
+    Owner(owner): Owner,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let preferences = repository
+        .get(&owner)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(preferences)))
+}
+
+/// Replaces the caller's preferences with the body and echoes them back.
+pub async fn put_preferences<T: PreferencesRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    Owner(owner): Owner,
+    ValidatedJson(payload): ValidatedJson<Preferences>,
+) -> Result<impl IntoResponse, ApiError> {
+    let preferences = repository
+        .put(&owner, payload)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(preferences)))
+}
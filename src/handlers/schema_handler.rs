@@ -0,0 +1,29 @@
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use schemars::{schema::RootSchema, schema_for};
+use serde::Serialize;
+
+use crate::models::label::CreateLabel;
+use crate::models::todo::{CreateTodo, UpdateTodo};
+
+#[derive(Debug, Serialize)]
+pub struct PayloadSchemas {
+    pub create: RootSchema,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update: Option<RootSchema>,
+}
+
+pub async fn todo_schema() -> impl IntoResponse {
+    let schemas = PayloadSchemas {
+        create: schema_for!(CreateTodo),
+        update: Some(schema_for!(UpdateTodo)),
+    };
+    (StatusCode::OK, Json(schemas))
+}
+
+pub async fn label_schema() -> impl IntoResponse {
+    let schemas = PayloadSchemas {
+        create: schema_for!(CreateLabel),
+        update: None,
+    };
+    (StatusCode::OK, Json(schemas))
+}
@@ -1,59 +1,632 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
 use std::sync::Arc;
 
 use super::*;
-use axum::extract::Path;
+use axum::extract::{FromRequest, Path, RequestParts};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use axum::http::HeaderName;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::{async_trait, BoxError};
 use axum::{extract::Extension, http::StatusCode, response::IntoResponse, Json};
+use futures_util::stream;
+use serde::de::DeserializeOwned;
+use tokio::sync::broadcast::error::RecvError;
+use validator::Validate;
 
-use crate::models::todo::{CreateTodo, UpdateTodo};
+use crate::models::id::{LabelId, TodoId};
+use crate::models::todo::{
+    AddDependency, CompletedFilter, CreateTodo, ListedTodo, RandomTodoParams, SetLabels,
+    SyncParams, Todo, TodoChangesParams, TodoDetail, TodoIds, TodoListParams, UpdateTodo,
+    UpdateTodoParams,
+};
+use crate::repositories::change_feed::{ChangeFeed, TodoChange};
+use crate::repositories::label_repository::LabelRepository;
 use crate::repositories::todo_repository::TodoRepository;
+use crate::repositories::RepositoryError;
 
-pub async fn create_todo<T: TodoRepository>(
-    ValidatedJson(payload): ValidatedJson<CreateTodo>,
+/// Label given to todos created without any, from `DEFAULT_LABEL`.
+#[derive(Debug, Clone, Default)]
+pub struct DefaultLabel(pub Option<String>);
+
+/// Whether `GET /todos` without `completed` lists only open todos, from
+/// `HIDE_COMPLETED_BY_DEFAULT`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HideCompletedByDefault(pub bool);
+
+/// Status `POST /todos` answers with, from `CREATE_STATUS`.
+#[derive(Debug, Clone, Copy)]
+pub struct CreateStatus(pub StatusCode);
+
+impl Default for CreateStatus {
+    fn default() -> Self {
+        Self(StatusCode::CREATED)
+    }
+}
+
+/// Most todos `GET /todos` lists without `limit`, from `MAX_UNPAGINATED`.
+#[derive(Debug, Clone, Copy)]
+pub struct MaxUnpaginated(pub usize);
+
+/// Whether todo text is trimmed before validation, from `TRIM_TODO_TEXT`.
+#[derive(Debug, Clone, Copy)]
+pub struct TrimTodoText(pub bool);
+
+impl Default for TrimTodoText {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+/// Todo payloads carrying user-entered text.
+pub trait TodoText {
+    fn trim_text(&mut self);
+}
+
+impl TodoText for CreateTodo {
+    fn trim_text(&mut self) {
+        self.text = self.text.trim().to_string();
+    }
+}
+
+impl TodoText for UpdateTodo {
+    fn trim_text(&mut self) {
+        if let Some(text) = &mut self.text {
+            *text = text.trim().to_string();
+        }
+    }
+}
+
+/// [`ValidatedJson`] for todo payloads: the text is trimmed first (unless
+/// [`TrimTodoText`] is off), so whitespace-only text fails validation.
+#[derive(Debug)]
+pub struct ValidatedTodoJson<T>(T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatedTodoJson<T>
+where
+    T: DeserializeOwned + Validate + TodoText,
+    B: http_body::Body + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let trim = Extension::<TrimTodoText>::from_request(req)
+            .await
+            .map(|Extension(trim)| trim)
+            .unwrap_or_default();
+        let mut value: T = parse_json(req).await?;
+        if trim.0 {
+            value.trim_text();
+        }
+        validate(&value)?;
+        Ok(ValidatedTodoJson(value))
+    }
+}
+
+pub async fn create_todo<T: TodoRepository, L: LabelRepository>(
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
+    Extension(label_repository): Extension<Arc<L>>,
+    Extension(DefaultLabel(default_label)): Extension<DefaultLabel>,
+    Extension(CreateStatus(status)): Extension<CreateStatus>,
+    ValidatedTodoJson(mut payload): ValidatedTodoJson<CreateTodo>,
+) -> Result<impl IntoResponse, ApiError> {
+    // explicit labels win; a default that can't be resolved doesn't fail the create
+    if let (true, Some(name)) = (payload.labels.is_empty(), default_label) {
+        match label_repository.ensure(name.clone()).await {
+            Ok(label) => payload.labels.push(label.id),
+            Err(e) => tracing::warn!("default label {:?} could not be resolved: {}", name, e),
+        }
+    }
+    let todo = match create_labeled(&*repository, payload).await {
+        Ok(todo) => todo,
+        Err(e) => {
+            return Err(external_id_conflict(
+                &*repository,
+                e,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Resolution::UseExisting,
+            )
+            .await)
+        }
+    };
+
+    Ok((status, Json(todo)))
+}
+
+/// Creates the todo, then labels it, in one unit of work, so a label that
+/// can't be attached leaves no todo behind.
+async fn create_labeled<T: TodoRepository>(
+    repository: &T,
+    mut payload: CreateTodo,
+) -> anyhow::Result<Todo> {
+    let labels = std::mem::take(&mut payload.labels);
+    let mut work = repository.begin().await?;
+    let mut todo = work.create(payload).await?;
+    if !labels.is_empty() {
+        todo = work.set_labels(todo.id, labels).await?;
+    }
+    work.commit().await?;
+    Ok(todo)
+}
+
+/// Maps `err` like [`ApiError::from_repository`], except that a clash on
+/// `external_id` embeds the todo already holding it.
+async fn external_id_conflict<T: TodoRepository>(
+    repository: &T,
+    err: anyhow::Error,
+    status: StatusCode,
+    resolution: Resolution,
+) -> ApiError {
+    if let Some(RepositoryError::DuplicateExternalId(external_id)) = err.downcast_ref() {
+        // the holder may be gone again by now, leaving nothing to embed
+        if let Ok(existing) = repository.find_by_external_id(external_id).await {
+            return ApiError::conflict(&existing, vec!["external_id"], resolution);
+        }
+    }
+    ApiError::from_repository(err, status)
+}
+
+async fn todo_detail<T: TodoRepository>(
+    repository: &T,
+    id: TodoId,
+) -> Result<TodoDetail, ApiError> {
     let todo = repository
-        .create(payload)
+        .find(id)
         .await
-        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))?;
+    let dependencies = repository
+        .dependencies(id)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))?;
+    Ok(TodoDetail { todo, dependencies })
+}
 
-    Ok((StatusCode::CREATED, Json(todo)))
+/// `ETag` of the todo `id`, from its [`TodoRepository::seq`]; read it before
+/// the todo.
+async fn todo_etag<T: TodoRepository>(repository: &T, id: TodoId) -> Result<String, ApiError> {
+    let seq = repository
+        .seq(id)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))?;
+    Ok(format!("\"{}\"", seq))
 }
 
+/// A todo with its dependencies, carrying an `ETag` that changes with
+/// either; answers 304 without loading them when `If-None-Match` already
+/// has it.
 pub async fn find_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
+    ValidatedPath(id): ValidatedPath<TodoId>,
+    if_none_match: IfNoneMatch,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
+) -> Result<Response, ApiError> {
+    let etag = todo_etag(&*repository, id).await?;
+    if if_none_match.matches(&etag) {
+        let headers = Headers(vec![(ETAG, etag)]);
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+    let detail = todo_detail(&*repository, id).await?;
+    let headers = Headers(vec![(ETAG, etag)]);
+    Ok((StatusCode::OK, headers, Json(detail)).into_response())
+}
+
+/// The headers `GET /todos/:id` would send, `ETag` and `Content-Length`
+/// included, for clients checking a todo without downloading it. axum would
+/// answer `HEAD` from the `GET` handler too, but without the length.
+pub async fn head_todo<T: TodoRepository>(
+    ValidatedPath(id): ValidatedPath<TodoId>,
+    if_none_match: IfNoneMatch,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<Response, ApiError> {
+    let etag = todo_etag(&*repository, id).await?;
+    if if_none_match.matches(&etag) {
+        let headers = Headers(vec![(ETAG, etag)]);
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+    let detail = todo_detail(&*repository, id).await?;
+    let length = serde_json::to_vec(&detail)
+        .map_err(|e| ApiError::Internal(e.into()))?
+        .len();
+    let headers = Headers(vec![
+        (CONTENT_TYPE, mime::APPLICATION_JSON.to_string()),
+        (CONTENT_LENGTH, length.to_string()),
+        (ETAG, etag),
+    ]);
+    Ok((StatusCode::OK, headers).into_response())
+}
+
+/// Looks a todo up by the id another system knows it by.
+pub async fn find_todo_by_external_id<T: TodoRepository>(
+    Path(external_id): Path<String>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let todo = repository
+        .find_by_external_id(&external_id)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+/// An incomplete todo picked at random, optionally among those with a label;
+/// 404 when there is none.
+pub async fn random_todo<T: TodoRepository>(
+    ValidatedQuery(params): ValidatedQuery<RandomTodoParams>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let todo = repository
+        .random(params.label)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?
+        .ok_or(ApiError::Status(StatusCode::NOT_FOUND))?;
     Ok((StatusCode::OK, Json(todo)))
 }
 
+/// Whether each of the given ids is a todo, keyed by id, without loading
+/// the todos themselves.
+pub async fn todos_exist<T: TodoRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    ValidatedJson(payload): ValidatedJson<TodoIds>,
+) -> Result<impl IntoResponse, ApiError> {
+    let existing = repository
+        .existing(&payload.ids)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    let exists: BTreeMap<TodoId, bool> = payload
+        .ids
+        .into_iter()
+        .map(|id| (id, existing.contains(&id)))
+        .collect();
+    Ok((StatusCode::OK, Json(exists)))
+}
+
+/// Todos created, updated and deleted since the `token` of an earlier sync,
+/// for clients keeping an offline copy.
+pub async fn sync_todos<T: TodoRepository>(
+    ValidatedQuery(params): ValidatedQuery<SyncParams>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let sync = repository
+        .sync(params.since)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(sync)))
+}
+
+/// Entity tags of `If-None-Match`, as sent; `*` matches any.
+#[derive(Debug, Default)]
+pub struct IfNoneMatch(Vec<String>);
+
+impl IfNoneMatch {
+    /// Whether `etag` is among them, ignoring `W/` as `GET` may.
+    fn matches(&self, etag: &str) -> bool {
+        self.0
+            .iter()
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+    }
+}
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for IfNoneMatch {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let tags = req
+            .headers()
+            .into_iter()
+            .flat_map(|headers| headers.get_all(IF_NONE_MATCH))
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        Ok(IfNoneMatch(tags))
+    }
+}
+
+/// Lists todos, with the current change cursor in `X-Change-Cursor`; with
+/// `wait=true` long polls for changes instead, see [`TodoChangesParams`].
+///
+/// Lists carry an `ETag` of the collection's version, answering 304 without
+/// listing when `If-None-Match` already has it. Without `limit`, whatever
+/// the `offset`, at most [`MaxUnpaginated`] todos are listed.
 pub async fn all_todo<T: TodoRepository>(
+    ValidatedQuery(mut params): ValidatedQuery<TodoListParams>,
+    ValidatedQuery(poll): ValidatedQuery<TodoChangesParams>,
+    if_none_match: IfNoneMatch,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todos = repository.all().await.unwrap();
-    Ok((StatusCode::OK, Json(todos)))
+    Extension(HideCompletedByDefault(hide_completed)): Extension<HideCompletedByDefault>,
+    Extension(MaxUnpaginated(max_unpaginated)): Extension<MaxUnpaginated>,
+    Extension(feed): Extension<ChangeFeed>,
+) -> Result<Response, ApiError> {
+    if poll.wait {
+        return wait_for_changes(&feed, poll)
+            .await
+            .map(IntoResponse::into_response);
+    }
+    // read before listing, so a change made meanwhile is polled again
+    // rather than missed
+    let cursor = feed.cursor();
+    let version = repository
+        .version()
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    let etag = format!("\"{}\"", version);
+    if if_none_match.matches(&etag) {
+        let headers = Headers(vec![(ETAG, etag)]);
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+    if hide_completed && params.completed.is_none() {
+        params.completed = Some(CompletedFilter::Open);
+    }
+    let (view, truncate_text) = (params.labels.unwrap_or_default(), params.truncate_text);
+    // one more than the cap, to tell a cut list from one that just fits
+    let unpaginated = params.limit.is_none();
+    if unpaginated {
+        params.limit = Some(i64::try_from(max_unpaginated.saturating_add(1)).unwrap_or(i64::MAX));
+    }
+    let mut todos = repository
+        .all(params)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    let truncated = unpaginated && todos.len() > max_unpaginated;
+    if truncated {
+        todos.truncate(max_unpaginated);
+    }
+    let todos = todos
+        .into_iter()
+        .map(|todo| {
+            let todo = ListedTodo::new(todo, view);
+            match truncate_text {
+                Some(max_chars) => todo.truncate_text(max_chars),
+                None => todo,
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut headers = vec![
+        (
+            HeaderName::from_static(CHANGE_CURSOR_HEADER),
+            cursor.to_string(),
+        ),
+        (ETAG, etag),
+    ];
+    if truncated {
+        headers.push((
+            HeaderName::from_static(TRUNCATED_HEADER),
+            "true".to_string(),
+        ));
+    }
+    Ok((StatusCode::OK, Headers(headers), Json(todos)).into_response())
+}
+
+/// Longest a long poll is held, and the default.
+pub const LONG_POLL_TIMEOUT: Duration = Duration::from_secs(30);
+pub const CHANGE_CURSOR_HEADER: &str = "x-change-cursor";
+/// Sent as `true` when `GET /todos` without `limit` was cut at
+/// [`MaxUnpaginated`].
+pub const TRUNCATED_HEADER: &str = "x-truncated";
+
+async fn wait_for_changes(
+    feed: &ChangeFeed,
+    poll: TodoChangesParams,
+) -> Result<impl IntoResponse, ApiError> {
+    // subscribe before looking, so a change in between wakes us up
+    let mut changes = feed.subscribe();
+    let cursor = poll.since.unwrap_or_else(|| feed.cursor());
+    let page = feed
+        .since(cursor)
+        .ok_or(ApiError::Status(StatusCode::GONE))?;
+    if !page.changes.is_empty() {
+        return Ok((StatusCode::OK, Json(page)));
+    }
+
+    let timeout = poll.timeout.map_or(LONG_POLL_TIMEOUT, Duration::from_secs);
+    // a lagged receiver still means something changed
+    let _ = tokio::time::timeout(timeout, changes.recv()).await;
+    let page = feed
+        .since(cursor)
+        .ok_or(ApiError::Status(StatusCode::GONE))?;
+    Ok((StatusCode::OK, Json(page)))
+}
+
+/// Long polls for changes like `GET /todos?wait=true`, for clients behind
+/// networks that strip SSE and WebSockets; `wait` is implied.
+pub async fn todo_changes(
+    ValidatedQuery(poll): ValidatedQuery<TodoChangesParams>,
+    Extension(feed): Extension<ChangeFeed>,
+) -> Result<impl IntoResponse, ApiError> {
+    wait_for_changes(&feed, poll).await
 }
 
+/// Answers 202 with the todo and the fields that changed, or 200 with none
+/// when the payload changed nothing.
+/// Completing a todo whose dependencies are still open answers 409 listing
+/// them, unless `force=true`.
 pub async fn update_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
-    ValidatedJson(payload): ValidatedJson<UpdateTodo>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
+    ValidatedPath(id): ValidatedPath<TodoId>,
+    ValidatedQuery(params): ValidatedQuery<UpdateTodoParams>,
+    ValidatedTodoJson(payload): ValidatedTodoJson<UpdateTodo>,
+) -> Result<impl IntoResponse, ApiError> {
+    if payload.completed == Some(true) && !params.force {
+        let open = repository
+            .open_dependencies(id)
+            .await
+            .map_err(|e| ApiError::from_repository(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+        if !open.is_empty() {
+            return Err(ApiError::DependenciesOpen(open));
+        }
+    }
+    let updated = match repository.update(id, payload).await {
+        Ok(updated) => updated,
+        Err(e) => {
+            return Err(external_id_conflict(
+                &*repository,
+                e,
+                StatusCode::NOT_FOUND,
+                Resolution::Merge,
+            )
+            .await)
+        }
+    };
+
+    // nothing to accept when the payload matched the todo already
+    let status = if updated.changed_fields.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::ACCEPTED
+    };
+    Ok((status, Json(updated)))
+}
+
+pub async fn pin_todo<T: TodoRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    ValidatedPath(id): ValidatedPath<TodoId>,
+) -> Result<impl IntoResponse, ApiError> {
+    set_pinned(&*repository, id, true).await
+}
+
+pub async fn unpin_todo<T: TodoRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    ValidatedPath(id): ValidatedPath<TodoId>,
+) -> Result<impl IntoResponse, ApiError> {
+    set_pinned(&*repository, id, false).await
+}
+
+async fn set_pinned<T: TodoRepository>(
+    repository: &T,
+    id: TodoId,
+    pinned: bool,
+) -> Result<(StatusCode, Json<Todo>), ApiError> {
+    let todo = repository
+        .set_pinned(id, pinned)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))?;
+
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+/// Clears completion, pin and labels, keeping the todo's id and text.
+pub async fn reset_todo<T: TodoRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    ValidatedPath(id): ValidatedPath<TodoId>,
+) -> Result<impl IntoResponse, ApiError> {
+    let todo = repository
+        .reset(id)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))?;
+
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+pub async fn move_to_label<T: TodoRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    ValidatedPath((id, label_id)): ValidatedPath<(TodoId, LabelId)>,
+) -> Result<impl IntoResponse, ApiError> {
+    let todo = repository
+        .set_labels(id, vec![label_id])
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))?;
+
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+/// Replaces the todo's labels with exactly `label_ids`; if any of them is
+/// unknown nothing changes and the 404 lists every missing id.
+pub async fn set_labels<T: TodoRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    ValidatedPath(id): ValidatedPath<TodoId>,
+    ValidatedJson(payload): ValidatedJson<SetLabels>,
+) -> Result<impl IntoResponse, ApiError> {
     let todo = repository
-        .update(id, payload)
+        .set_labels(id, payload.label_ids)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))?;
+
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+/// Makes the todo wait for `depends_on`; 400 if that would close a cycle.
+pub async fn add_dependency<T: TodoRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    ValidatedPath(id): ValidatedPath<TodoId>,
+    ValidatedJson(payload): ValidatedJson<AddDependency>,
+) -> Result<impl IntoResponse, ApiError> {
+    let dependencies = repository
+        .add_dependency(id, payload.depends_on)
         .await
-        .or(Err(StatusCode::NOT_FOUND))?;
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(dependencies)))
+}
 
-    Ok((StatusCode::ACCEPTED, Json(todo)))
+pub async fn remove_dependency<T: TodoRepository>(
+    Extension(repository): Extension<Arc<T>>,
+    ValidatedPath((id, depends_on)): ValidatedPath<(TodoId, TodoId)>,
+) -> Result<impl IntoResponse, ApiError> {
+    let dependencies = repository
+        .remove_dependency(id, depends_on)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(dependencies)))
 }
 
 pub async fn delete_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
     Extension(repository): Extension<Arc<T>>,
-) -> StatusCode {
+    ValidatedPath(id): ValidatedPath<TodoId>,
+) -> Result<StatusCode, ApiError> {
     repository
         .delete(id)
         .await
         .map(|_| StatusCode::NO_CONTENT)
-        .unwrap_or(StatusCode::NOT_FOUND)
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))
+}
+
+/// Streams changes of a single todo as server-sent events: `updated` with the
+/// todo, then `deleted` with its id, after which the stream ends.
+pub async fn watch_todo<T: TodoRepository>(
+    ValidatedPath(id): ValidatedPath<TodoId>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(feed): Extension<ChangeFeed>,
+) -> Result<impl IntoResponse, ApiError> {
+    // subscribe before the lookup so a change in between is not lost
+    let changes = feed.subscribe();
+    repository
+        .find(id)
+        .await
+        .map_err(|e| ApiError::from_repository(e, StatusCode::NOT_FOUND))?;
+
+    let events = stream::unfold(Some(changes), move |changes| {
+        let repository = repository.clone();
+        async move {
+            let mut changes = changes?;
+            loop {
+                let change = match changes.recv().await {
+                    Ok(change) if change.id() == id => change,
+                    Ok(_) => continue,
+                    // missed changes: send the current state instead
+                    Err(RecvError::Lagged(_)) => match repository.find(id).await {
+                        Ok(todo) => TodoChange::Updated(todo),
+                        Err(_) => TodoChange::Deleted(id),
+                    },
+                    Err(RecvError::Closed) => return None,
+                };
+                let deleted = matches!(change, TodoChange::Deleted(_));
+                return Some((change_event(&change), (!deleted).then_some(changes)));
+            }
+        }
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+fn change_event(change: &TodoChange) -> serde_json::Result<Event> {
+    match change {
+        TodoChange::Created(todo) | TodoChange::Updated(todo) => {
+            Event::default().event("updated").json_data::<&Todo>(todo)
+        }
+        TodoChange::Deleted(id) => Event::default()
+            .event("deleted")
+            .json_data(serde_json::json!({ "id": id })),
+    }
 }
@@ -0,0 +1,3053 @@
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use axum::{
+    error_handling::HandleErrorLayer, extract::Extension, middleware, routing::MethodFilter, Router,
+};
+use hyper::header::{HeaderName, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use tower::{limit::GlobalConcurrencyLimitLayer, load_shed::LoadShedLayer, ServiceBuilder};
+use tower_http::cors::{Any, CorsLayer, Origin};
+
+use handlers::{
+    admin_handler::*, batch_handler::*, debug_handler::*, handle_overload, health_handler::*,
+    label_handler::*, preferences_handler::*, schema_handler::*, todo_handler::*,
+};
+
+use crate::config::{AppConfig, OverloadMode};
+use crate::load::LoadMonitor;
+use crate::middlewares::{BodyLogger, ConsistencyTokens, Deprecation};
+use crate::repositories::{
+    change_feed::{ChangeFeed, Notifying},
+    label_repository::LabelRepository,
+    preferences_repository::PreferencesRepository,
+    todo_repository::TodoRepository,
+};
+use crate::routes::{RouteInfo, RouteTable};
+
+#[cfg(feature = "client")]
+pub mod client;
+pub mod config;
+pub mod handlers;
+pub mod load;
+pub mod middlewares;
+pub mod preflight;
+pub mod repositories;
+pub mod routes;
+pub mod startup;
+
+pub use todo_api_models as models;
+
+pub const CORS_ORIGINS: [&str; 1] = ["http://localhost:3001"];
+
+pub fn create_app<Todo: TodoRepository, Label: LabelRepository, Prefs: PreferencesRepository>(
+    todo_repository: Todo,
+    label_repository: Label,
+    preferences_repository: Prefs,
+    config: &AppConfig,
+) -> Router {
+    create_app_with_routes(
+        todo_repository,
+        label_repository,
+        preferences_repository,
+        config,
+    )
+    .0
+}
+
+/// [`create_app`], refusing to start if [`startup::validate_app`] finds a
+/// route whose handler is missing an extension.
+pub async fn create_validated_app<
+    Todo: TodoRepository,
+    Label: LabelRepository,
+    Prefs: PreferencesRepository,
+>(
+    todo_repository: Todo,
+    label_repository: Label,
+    preferences_repository: Prefs,
+    config: &AppConfig,
+) -> Result<Router, String> {
+    let (app, routes) = create_app_with_routes(
+        todo_repository,
+        label_repository,
+        preferences_repository,
+        config,
+    );
+    startup::validate_app(&app, &routes).await?;
+    Ok(app)
+}
+
+fn create_app_with_routes<
+    Todo: TodoRepository,
+    Label: LabelRepository,
+    Prefs: PreferencesRepository,
+>(
+    todo_repository: Todo,
+    label_repository: Label,
+    preferences_repository: Prefs,
+    config: &AppConfig,
+) -> (Router, Arc<Vec<RouteInfo>>) {
+    let feed = ChangeFeed::default();
+    build_router(
+        Notifying::new(todo_repository, feed.clone()),
+        label_repository,
+        preferences_repository,
+        feed,
+        config,
+    )
+}
+
+/// `POST /labels` gives way to the idempotent `PUT /labels?name=` on
+/// 2027-06-30 (seconds since the epoch).
+const CREATE_LABEL_SUNSET: Duration = Duration::from_secs(1_814_313_600);
+
+fn build_router<Todo: TodoRepository, Label: LabelRepository, Prefs: PreferencesRepository>(
+    todo_repository: Todo,
+    label_repository: Label,
+    preferences_repository: Prefs,
+    feed: ChangeFeed,
+    config: &AppConfig,
+) -> (Router, Arc<Vec<RouteInfo>>) {
+    let table = RouteTable::new()
+        .route("/", MethodFilter::GET, root)
+        .route("/health/db", MethodFilter::GET, db_health::<Todo>)
+        .route("/readyz", MethodFilter::GET, readiness::<Todo>)
+        .route("/todos", MethodFilter::POST, create_todo::<Todo, Label>)
+        .route("/todos", MethodFilter::GET, all_todo::<Todo>)
+        .route("/todos/sync", MethodFilter::GET, sync_todos::<Todo>)
+        .route("/todos/changes", MethodFilter::GET, todo_changes)
+        .route("/todos/exists", MethodFilter::POST, todos_exist::<Todo>)
+        .route("/todos/random", MethodFilter::GET, random_todo::<Todo>)
+        .route("/todos/:id", MethodFilter::GET, find_todo::<Todo>)
+        .route("/todos/:id", MethodFilter::HEAD, head_todo::<Todo>)
+        .route(
+            "/todos/by-external/:external_id",
+            MethodFilter::GET,
+            find_todo_by_external_id::<Todo>,
+        )
+        .route("/todos/:id", MethodFilter::DELETE, delete_todo::<Todo>)
+        .route("/todos/:id", MethodFilter::PATCH, update_todo::<Todo>)
+        .route("/todos/:id/watch", MethodFilter::GET, watch_todo::<Todo>)
+        .route("/todos/:id/pin", MethodFilter::POST, pin_todo::<Todo>)
+        .route("/todos/:id/unpin", MethodFilter::POST, unpin_todo::<Todo>)
+        .route("/todos/:id/labels", MethodFilter::PUT, set_labels::<Todo>)
+        .route("/todos/:id/reset", MethodFilter::POST, reset_todo::<Todo>)
+        .route(
+            "/todos/:id/dependencies",
+            MethodFilter::POST,
+            add_dependency::<Todo>,
+        )
+        .route(
+            "/todos/:id/dependencies/:depends_on",
+            MethodFilter::DELETE,
+            remove_dependency::<Todo>,
+        )
+        .route(
+            "/todos/:id/move-to-label/:label_id",
+            MethodFilter::POST,
+            move_to_label::<Todo>,
+        )
+        .deprecated_route(
+            "/labels",
+            MethodFilter::POST,
+            create_label::<Label>,
+            Deprecation::new(UNIX_EPOCH + CREATE_LABEL_SUNSET, "/labels"),
+        )
+        .route("/labels", MethodFilter::GET, all_label::<Label>)
+        .route("/labels", MethodFilter::PUT, ensure_label::<Label>)
+        .route("/labels/bulk", MethodFilter::POST, create_labels::<Label>)
+        .route("/labels/suggest", MethodFilter::GET, suggest_label::<Label>)
+        .route("/labels/:id", MethodFilter::DELETE, delete_label::<Label>)
+        .route(
+            "/labels/:id/move-todos/:to",
+            MethodFilter::POST,
+            move_todos::<Todo>,
+        )
+        .route("/preferences", MethodFilter::GET, get_preferences::<Prefs>)
+        .route("/preferences", MethodFilter::PUT, put_preferences::<Prefs>)
+        .route("/batch", MethodFilter::POST, batch)
+        .route("/schema/todo", MethodFilter::GET, todo_schema)
+        .route("/schema/label", MethodFilter::GET, label_schema);
+
+    #[cfg(feature = "console")]
+    let table = table.route("/debug/tasks", MethodFilter::GET, debug_tasks);
+
+    let table = if config.debug_endpoints {
+        table
+            .route("/debug/routes", MethodFilter::GET, debug_routes)
+            .route("/debug/echo", MethodFilter::all(), debug_echo)
+    } else {
+        table
+    };
+
+    let table = if config.admin_endpoints {
+        table.route("/admin/repair", MethodFilter::POST, repair::<Todo>)
+    } else {
+        table
+    };
+
+    let routes = Arc::new(table.info());
+    let request_timeout = config.request_timeout;
+    let monitor = LoadMonitor::new(
+        config.concurrency.max_in_flight,
+        config.pool.max_connections,
+        config.readiness,
+    );
+    let todo_repository = Arc::new(todo_repository);
+    let tokens = match &config.consistency_token_key {
+        Some(key) => ConsistencyTokens::new(key.expose().as_bytes()),
+        None => ConsistencyTokens::random(),
+    };
+    let router = table
+        .into_router()
+        .layer(Extension(routes.clone()))
+        .layer(Extension(DefaultLabel(config.default_label.clone())))
+        .layer(Extension(TrimTodoText(config.trim_todo_text)))
+        .layer(Extension(CreateStatus(config.create_status)))
+        .layer(Extension(MaxUnpaginated(config.max_unpaginated)))
+        .layer(Extension(HideCompletedByDefault(
+            config.hide_completed_by_default,
+        )))
+        .layer(Extension(feed))
+        .layer(Extension(monitor.clone()))
+        .layer(Extension(todo_repository.clone()))
+        .layer(Extension(Arc::new(label_repository)))
+        .layer(Extension(Arc::new(preferences_repository)));
+    let router = router
+        .clone()
+        .layer(Extension(BatchRouter::new(router)))
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_overload))
+                .option_layer(
+                    (config.concurrency.mode == OverloadMode::Reject).then(LoadShedLayer::new),
+                )
+                .layer(GlobalConcurrencyLimitLayer::new(
+                    config.concurrency.max_in_flight,
+                )),
+        )
+        .layer(middleware::from_fn(move |req, next| {
+            monitor.clone().track(req, next)
+        }))
+        .layer(middleware::from_fn(move |req, next| {
+            middlewares::read_consistency(tokens.clone(), todo_repository.clone(), req, next)
+        }))
+        .layer(middleware::from_fn(move |req, next| {
+            middlewares::request_deadline(request_timeout, req, next)
+        }));
+    // not layered at all when off, so bodies are never buffered
+    let router = match config.log_bodies {
+        Some(log_bodies) => {
+            let logger = BodyLogger::new(log_bodies);
+            router.layer(middleware::from_fn(move |req, next| {
+                logger.clone().run(req, next)
+            }))
+        }
+        None => router,
+    };
+    let router = router
+        // outside the body logger, so it logs the inflated JSON
+        .layer(middleware::from_fn({
+            let max_bytes = config.max_decompressed_bytes;
+            move |req, next| middlewares::decompress_body(max_bytes, req, next)
+        }))
+        .layer(middleware::from_fn({
+            let trusted = Arc::new(config.trusted_proxies.clone());
+            move |req, next| middlewares::client_info(trusted.clone(), req, next)
+        }))
+        .layer(middleware::from_fn({
+            let header = config.request_id_header.clone();
+            move |req, next| middlewares::request_id(header.clone(), req, next)
+        }))
+        .layer(
+            CorsLayer::new()
+                .allow_origin(Origin::list(
+                    CORS_ORIGINS.iter().map(|origin| origin.parse().unwrap()),
+                ))
+                .allow_methods(Any)
+                .allow_headers(vec![
+                    CONTENT_TYPE,
+                    IF_NONE_MATCH,
+                    HeaderName::from_static(middlewares::READ_CONSISTENCY_HEADER),
+                    HeaderName::from_static(middlewares::CONSISTENCY_TOKEN_HEADER),
+                    HeaderName::from_static(middlewares::REQUEST_DEADLINE_HEADER),
+                    config.request_id_header.clone(),
+                ])
+                .expose_headers(vec![
+                    HeaderName::from_static(middlewares::CONSISTENCY_TOKEN_HEADER),
+                    X_TOTAL_COUNT,
+                    HeaderName::from_static(TRUNCATED_HEADER),
+                    HeaderName::from_static(CHANGE_CURSOR_HEADER),
+                    ETAG,
+                ]),
+        );
+    (router, routes)
+}
+
+async fn root() -> &'static str {
+    "Hello, World!"
+}
+
+#[cfg(test)]
+mod test {
+    use axum::response::Response;
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use crate::config::ConcurrencyConfig;
+    use crate::load::ReadinessConfig;
+    use crate::models::batch::BatchResult;
+    use crate::models::id::{LabelId, TodoId};
+    use crate::models::label::{Label, LabelListParams};
+    use crate::models::todo::{
+        CreateTodo, ListedTodo, Todo, TodoChangeEntry, TodoChanges, TodoDetail, TodoListParams,
+        TodoSync, UpdateTodo,
+    };
+    use crate::repositories::{
+        circuit_breaker::{
+            test_utils::FlakyTodoRepository, Breaker, CircuitBreaker, CircuitBreakerConfig,
+        },
+        label_repository::test_utils::LabelRepositoryForMemory,
+        preferences_repository::test_utils::PreferencesRepositoryForMemory,
+        todo_repository::test_utils::TodoRepositoryForMemory,
+    };
+
+    use super::*;
+
+    fn build_todo_req_with_json(path: &str, method: Method, json_body: String) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .method(method)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(json_body))
+            .unwrap()
+    }
+
+    fn build_todo_req_with_empty(method: Method, path: &str) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .method(method)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn res_to_todo(res: Response) -> Todo {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
+        let todo: Todo = serde_json::from_str(&body)
+            .unwrap_or_else(|_| panic!("cannot convert Todo instance. body: {}", body));
+        todo
+    }
+
+    #[tokio::test]
+    async fn should_created_todo() {
+        let expected = Todo::new(
+            TodoId::new(1).unwrap(),
+            "should_return_created_todo".to_string(),
+        );
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "should_return_created_todo" }"#.to_string(),
+        );
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(expected, todo);
+    }
+
+    #[tokio::test]
+    async fn should_answer_create_with_configured_status() {
+        for (create_status, expected) in [
+            (None, StatusCode::CREATED),
+            (Some(StatusCode::OK), StatusCode::OK),
+        ] {
+            let default = AppConfig::default();
+            let config = AppConfig {
+                create_status: create_status.unwrap_or(default.create_status),
+                ..default
+            };
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                r#"{ "text": "created" }"#.to_string(),
+            );
+            let res = create_app(
+                TodoRepositoryForMemory::new(),
+                LabelRepositoryForMemory::new(),
+                PreferencesRepositoryForMemory::new(),
+                &config,
+            )
+            .oneshot(req)
+            .await
+            .unwrap();
+            assert_eq!(expected, res.status());
+            assert_eq!("created", res_to_todo(res).await.text);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_created_todo_with_labels() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository
+            .create("should_created_todo_with_labels".to_string())
+            .await
+            .expect("failed create label");
+        let expected = Todo {
+            labels: vec![label.clone()],
+            ..Todo::new(
+                TodoId::new(1).unwrap(),
+                "should_created_todo_with_labels".to_string(),
+            )
+        };
+
+        let app = create_app(
+            TodoRepositoryForMemory::with_labels(label_repository.clone()),
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        // a label named twice is attached once
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            format!(
+                r#"{{ "text": "should_created_todo_with_labels", "labels": [{0}, {0}] }}"#,
+                label.id
+            ),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(expected, todo);
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            format!(
+                r#"{{ "text": "unknown label", "labels": [{}, 99] }}"#,
+                label.id
+            ),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(
+            r#"{"error":"labels not found","missing":[99]}"#,
+            String::from_utf8(bytes.to_vec()).unwrap()
+        );
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec![expected], todos);
+    }
+
+    #[tokio::test]
+    async fn should_apply_default_label_without_explicit_labels() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let work = label_repository.create("work".to_string()).await.unwrap();
+        let config = AppConfig {
+            default_label: Some("inbox".to_string()),
+            ..AppConfig::default()
+        };
+        let app = create_app(
+            TodoRepositoryForMemory::with_labels(label_repository.clone()),
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &config,
+        );
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "unlabeled" }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        let inbox = Label::new(LabelId::new(2).unwrap(), "inbox".to_string());
+        assert_eq!(vec![inbox], todo.labels);
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            format!(r#"{{ "text": "labeled", "labels": [{}] }}"#, work.id),
+        );
+        let todo = res_to_todo(app.oneshot(req).await.unwrap()).await;
+        assert_eq!(vec![work], todo.labels);
+    }
+
+    #[tokio::test]
+    async fn should_trim_todo_text() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": " buy milk\n" }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!("buy milk", todo.text);
+
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "text": "\tbuy oat milk " }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!("buy oat milk", todo.text);
+
+        // trimmed before validation, so only whitespace is empty text
+        for (method, path) in [(Method::POST, "/todos"), (Method::PATCH, "/todos/1")] {
+            let req = build_todo_req_with_json(path, method, r#"{ "text": "   " }"#.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reject_text_that_looks_encrypted() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "buy milk" }"#.to_string(),
+        );
+        res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+
+        for (method, path) in [(Method::POST, "/todos"), (Method::PATCH, "/todos/1")] {
+            let req =
+                build_todo_req_with_json(path, method, r#"{ "text": "enc:v1:abc" }"#.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        }
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let todo = res_to_todo(app.oneshot(req).await.unwrap()).await;
+        assert_eq!("buy milk", todo.text);
+    }
+
+    #[tokio::test]
+    async fn should_keep_todo_text_when_trimming_is_off() {
+        let config = AppConfig {
+            trim_todo_text: false,
+            ..AppConfig::default()
+        };
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &config,
+        );
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": " buy milk " }"#.to_string(),
+        );
+        let todo = res_to_todo(app.oneshot(req).await.unwrap()).await;
+        assert_eq!(" buy milk ", todo.text);
+    }
+
+    #[tokio::test]
+    async fn should_find_todo() {
+        let expected = Todo::new(TodoId::new(1).unwrap(), "should_find_todo".to_string());
+
+        let todo_repository = TodoRepositoryForMemory::new();
+        todo_repository
+            .create(CreateTodo::new("should_find_todo".to_string()))
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(expected, todo);
+    }
+
+    #[tokio::test]
+    async fn should_find_todo_by_external_id() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let body = r#"{ "text": "synced", "external_id": "jira 42" }"#;
+        let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(Some("jira 42".to_string()), todo.external_id);
+
+        let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/by-external/jira%2042");
+        assert_eq!(
+            todo,
+            res_to_todo(app.clone().oneshot(req).await.unwrap()).await
+        );
+        let req = build_todo_req_with_empty(Method::GET, "/todos/by-external/jira%2043");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_accept_gzipped_bodies() {
+        use std::io::Write;
+
+        use flate2::{write::GzEncoder, Compression};
+
+        let gzip = |bytes: &[u8]| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(bytes).unwrap();
+            encoder.finish().unwrap()
+        };
+        let gzipped_req = |body: Vec<u8>| {
+            Request::builder()
+                .uri("/todos")
+                .method(Method::POST)
+                .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header(header::CONTENT_ENCODING, "gzip")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig {
+                max_decompressed_bytes: 4096,
+                ..AppConfig::default()
+            },
+        );
+
+        let body = gzip(br#"{ "text": "should_accept_gzipped_bodies" }"#);
+        let res = app.clone().oneshot(gzipped_req(body)).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo = res_to_todo(res).await;
+        assert_eq!("should_accept_gzipped_bodies", todo.text);
+
+        // about a kilobyte on the wire, well under the cap, but a megabyte
+        // inflated
+        let bomb = gzip(&vec![b' '; 1024 * 1024]);
+        assert!(bomb.len() < 4096);
+        let res = app.oneshot(gzipped_req(bomb)).await.unwrap();
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_guard_completion_with_dependencies() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        for text in ["design", "build"] {
+            let body = format!(r#"{{ "text": "{}" }}"#, text);
+            let req = build_todo_req_with_json("/todos", Method::POST, body);
+            app.clone().oneshot(req).await.unwrap();
+        }
+        let send = |req: Request<Body>| {
+            let app = app.clone();
+            async move {
+                let res = app.oneshot(req).await.unwrap();
+                let status = res.status();
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                (status, String::from_utf8(bytes.to_vec()).unwrap())
+            }
+        };
+
+        // build waits for design
+        let body = r#"{ "depends_on": 1 }"#.to_string();
+        let req = build_todo_req_with_json("/todos/2/dependencies", Method::POST, body);
+        let (status, body) = send(req).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(r#"{"dependencies":[1],"dependents":[]}"#, body);
+        let (_, body) = send(build_todo_req_with_empty(Method::GET, "/todos/1")).await;
+        let detail: TodoDetail = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            vec![TodoId::new(2).unwrap()],
+            detail.dependencies.dependents
+        );
+
+        // design waiting for build would be a cycle
+        let body = r#"{ "depends_on": 2 }"#.to_string();
+        let req = build_todo_req_with_json("/todos/1/dependencies", Method::POST, body);
+        assert_eq!(StatusCode::BAD_REQUEST, send(req).await.0);
+
+        let body = r#"{ "completed": true }"#.to_string();
+        let req = build_todo_req_with_json("/todos/2", Method::PATCH, body.clone());
+        let (status, res) = send(req).await;
+        assert_eq!(StatusCode::CONFLICT, status);
+        assert_eq!(r#"{"error":"dependencies still open","open":[1]}"#, res);
+        let req = build_todo_req_with_json("/todos/2?force=yes", Method::PATCH, body.clone());
+        let (status, res) = send(req).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert!(res.starts_with("query parse error: force:"));
+        let req = build_todo_req_with_json("/todos/2?force=true", Method::PATCH, body);
+        assert_eq!(StatusCode::ACCEPTED, send(req).await.0);
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/2/dependencies/1");
+        let (status, body) = send(req).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(r#"{"dependencies":[],"dependents":[]}"#, body);
+    }
+
+    #[tokio::test]
+    async fn should_answer_head_with_get_headers() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let body = r#"{ "text": "should_answer_head_with_get_headers" }"#.to_string();
+        let req = build_todo_req_with_json("/todos", Method::POST, body);
+        app.clone().oneshot(req).await.unwrap();
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+
+        let req = build_todo_req_with_empty(Method::HEAD, "/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            mime::APPLICATION_JSON.as_ref(),
+            res.headers()[header::CONTENT_TYPE]
+        );
+        assert_eq!(
+            bytes.len().to_string(),
+            res.headers()[header::CONTENT_LENGTH]
+        );
+        assert!(hyper::body::to_bytes(res.into_body())
+            .await
+            .unwrap()
+            .is_empty());
+
+        let req = build_todo_req_with_empty(Method::HEAD, "/todos/2");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        assert!(hyper::body::to_bytes(res.into_body())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_tag_each_todo_with_its_seq() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        for text in ["tagged", "other"] {
+            let body = format!(r#"{{ "text": "{}" }}"#, text);
+            let req = build_todo_req_with_json("/todos", Method::POST, body);
+            app.clone().oneshot(req).await.unwrap();
+        }
+        let send = |method: Method, if_none_match: Option<String>| {
+            let app = app.clone();
+            async move {
+                let mut req = build_todo_req_with_empty(method, "/todos/1");
+                if let Some(tags) = if_none_match {
+                    req.headers_mut()
+                        .insert(header::IF_NONE_MATCH, tags.parse().unwrap());
+                }
+                let res = app.oneshot(req).await.unwrap();
+                let status = res.status();
+                let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                (status, etag, bytes.len())
+            }
+        };
+
+        let (status, etag, _) = send(Method::GET, None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(
+            (StatusCode::OK, etag.clone(), 0),
+            send(Method::HEAD, None).await
+        );
+        for method in [Method::GET, Method::HEAD] {
+            let res = send(method, Some(etag.clone())).await;
+            assert_eq!((StatusCode::NOT_MODIFIED, etag.clone(), 0), res);
+        }
+
+        // another todo changing leaves this one's tag alone
+        let req = build_todo_req_with_json(
+            "/todos/2",
+            Method::PATCH,
+            r#"{ "completed": true }"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        assert_eq!(
+            StatusCode::NOT_MODIFIED,
+            send(Method::GET, Some(etag.clone())).await.0
+        );
+
+        // a dependency shows in the todo's detail, so it changes the tag
+        let req = build_todo_req_with_json(
+            "/todos/2/dependencies",
+            Method::POST,
+            r#"{ "depends_on": 1 }"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let (status, changed, len) = send(Method::GET, Some(etag.clone())).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_ne!(etag, changed);
+        assert!(len > 0);
+    }
+
+    #[tokio::test]
+    async fn should_check_which_todos_exist() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        for text in ["first", "second"] {
+            let body = format!(r#"{{ "text": "{}" }}"#, text);
+            let req = build_todo_req_with_json("/todos", Method::POST, body);
+            app.clone().oneshot(req).await.unwrap();
+        }
+
+        let body = r#"{ "ids": [3, 1, 2] }"#.to_string();
+        let req = build_todo_req_with_json("/todos/exists", Method::POST, body);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(r#"{"1":true,"2":true,"3":false}"#.as_bytes(), &bytes[..]);
+
+        let body = r#"{ "ids": [0] }"#.to_string();
+        let req = build_todo_req_with_json("/todos/exists", Method::POST, body);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_sync_changes() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let sync = |path: String| {
+            let app = app.clone();
+            async move {
+                let req = build_todo_req_with_empty(Method::GET, &path);
+                let res = app.oneshot(req).await.unwrap();
+                assert_eq!(StatusCode::OK, res.status());
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                serde_json::from_slice::<TodoSync>(&bytes).unwrap()
+            }
+        };
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "should_sync_changes" }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        let first = sync("/todos/sync".to_string()).await;
+        assert_eq!(vec![todo.clone()], first.created);
+
+        let req = build_todo_req_with_empty(Method::DELETE, &format!("/todos/{}", todo.id));
+        app.clone().oneshot(req).await.unwrap();
+        let second = sync(format!("/todos/sync?since={}", first.token)).await;
+        assert!(second.created.is_empty());
+        assert_eq!(vec![todo.id], second.deleted);
+
+        let req =
+            build_todo_req_with_empty(Method::GET, &format!("/todos/sync?since={}", u64::MAX));
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_get_all_todos() {
+        let expected = Todo::new(TodoId::new(1).unwrap(), "should_get_all_todos".to_string());
+
+        let todo_repository = TodoRepositoryForMemory::new();
+        todo_repository
+            .create(CreateTodo::new("should_get_all_todos".to_string()))
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
+        let todo: Vec<Todo> = serde_json::from_str(&body)
+            .unwrap_or_else(|_| panic!("cannot convert Todo instance. body: {}", body));
+        assert_eq!(vec![expected], todo);
+    }
+
+    #[tokio::test]
+    async fn should_page_todos() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        for text in ["first", "second", "third"] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string()))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?limit=1&offset=1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            vec![Todo::new(TodoId::new(2).unwrap(), "second".to_string())],
+            todos
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?limit=500");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_hide_completed_todos_by_default_when_configured() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        for text in ["done", "open"] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string()))
+                .await
+                .expect("failed create todo");
+        }
+        let done = TodoId::new(1).unwrap();
+        todo_repository
+            .update(
+                done,
+                UpdateTodo {
+                    text: None,
+                    completed: Some(true),
+                    external_id: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let list = |config: AppConfig, path: &'static str| {
+            let app = create_app(
+                todo_repository.clone(),
+                LabelRepositoryForMemory::new(),
+                PreferencesRepositoryForMemory::new(),
+                &config,
+            );
+            async move {
+                let req = build_todo_req_with_empty(Method::GET, path);
+                let res = app.oneshot(req).await.unwrap();
+                assert_eq!(StatusCode::OK, res.status());
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                let todos: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+                todos.into_iter().map(|todo| todo.text).collect::<Vec<_>>()
+            }
+        };
+        let hiding = || AppConfig {
+            hide_completed_by_default: true,
+            ..AppConfig::default()
+        };
+
+        assert_eq!(
+            vec!["open", "done"],
+            list(AppConfig::default(), "/todos").await
+        );
+        assert_eq!(vec!["open"], list(hiding(), "/todos").await);
+        // an explicit filter always wins over the default
+        assert_eq!(vec!["done"], list(hiding(), "/todos?completed=true").await);
+        assert_eq!(
+            vec!["open", "done"],
+            list(hiding(), "/todos?completed=any").await
+        );
+        assert_eq!(
+            vec!["open"],
+            list(AppConfig::default(), "/todos?completed=false").await
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?completed=maybe");
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_list_pinned_todos_first() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        for text in ["first", "second", "third"] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string()))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let list = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = build_todo_req_with_empty(Method::GET, path);
+                let bytes = hyper::body::to_bytes(app.oneshot(req).await.unwrap().into_body())
+                    .await
+                    .unwrap();
+                let todos: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+                todos.into_iter().map(|todo| todo.text).collect::<Vec<_>>()
+            }
+        };
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/pin");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(res_to_todo(res).await.pinned);
+        assert_eq!(vec!["first", "third", "second"], list("/todos").await);
+        // pinned todos stay on top of every page and filter
+        assert_eq!(vec!["first"], list("/todos?limit=1").await);
+        assert_eq!(
+            vec!["first", "third"],
+            list("/todos?completed=false&limit=2").await
+        );
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/unpin");
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert!(!todo.pinned);
+        assert_eq!(vec!["third", "second", "first"], list("/todos").await);
+
+        for path in ["/todos/99/pin", "/todos/99/unpin"] {
+            let req = build_todo_req_with_empty(Method::POST, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::NOT_FOUND, res.status());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_cap_unpaginated_lists() {
+        // the largest cap, plus the one more that tells a cut list, must fit
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig {
+                max_unpaginated: usize::MAX,
+                ..AppConfig::default()
+            },
+        );
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        assert_eq!(StatusCode::OK, app.oneshot(req).await.unwrap().status());
+
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig {
+                max_unpaginated: 2,
+                ..AppConfig::default()
+            },
+        );
+        let list = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = build_todo_req_with_empty(Method::GET, path);
+                let res = app.oneshot(req).await.unwrap();
+                let truncated = res.headers().get(TRUNCATED_HEADER).cloned();
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                let todos: Vec<Todo> = serde_json::from_slice(&bytes).unwrap();
+                (todos.len(), truncated)
+            }
+        };
+
+        for text in ["first", "second"] {
+            let body = format!(r#"{{ "text": "{}" }}"#, text);
+            let req = build_todo_req_with_json("/todos", Method::POST, body);
+            app.clone().oneshot(req).await.unwrap();
+        }
+        // exactly at the cap is not cut
+        assert_eq!((2, None), list("/todos").await);
+
+        let req =
+            build_todo_req_with_json("/todos", Method::POST, r#"{ "text": "third" }"#.to_string());
+        app.clone().oneshot(req).await.unwrap();
+        let (len, truncated) = list("/todos").await;
+        assert_eq!(2, len);
+        assert_eq!("true", truncated.unwrap());
+
+        // an explicit limit is not capped, an offset alone is
+        assert_eq!((3, None), list("/todos?limit=3").await);
+        let (len, truncated) = list("/todos?offset=0").await;
+        assert_eq!(2, len);
+        assert_eq!("true", truncated.unwrap());
+        assert_eq!((2, None), list("/todos?offset=1").await);
+    }
+
+    #[tokio::test]
+    async fn should_answer_empty_page_for_absurd_offsets() {
+        let label_repository = LabelRepositoryForMemory::new();
+        label_repository.create("label".to_string()).await.unwrap();
+        let app = create_app(
+            TodoRepositoryForMemory::with_labels(label_repository.clone()),
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "only todo" }"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let get = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = build_todo_req_with_empty(Method::GET, path);
+                let res = app.oneshot(req).await.unwrap();
+                let status = res.status();
+                let total = res
+                    .headers()
+                    .get("x-total-count")
+                    .map(|total| total.to_str().unwrap().to_string());
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                let body = serde_json::from_slice::<serde_json::Value>(&bytes).ok();
+                (status, body, total)
+            }
+        };
+
+        let (status, body, _) = get("/todos?limit=100&offset=9223372036854775807").await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(Some(serde_json::json!([])), body);
+        let (status, body, total) = get("/labels?limit=100&offset=9223372036854775807").await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(Some(serde_json::json!([])), body);
+        assert_eq!(Some("1".to_string()), total);
+        // past i64 is a bad request, never a wrapped offset
+        for path in [
+            "/todos?offset=9223372036854775808",
+            "/labels?offset=9223372036854775808",
+        ] {
+            let (status, _, _) = get(path).await;
+            assert_eq!(StatusCode::BAD_REQUEST, status, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_page_labels_with_total_count() {
+        let label_repository = LabelRepositoryForMemory::new();
+        for name in ["b", "c", "a"] {
+            label_repository.create(name.to_string()).await.unwrap();
+        }
+        let app = create_app(
+            TodoRepositoryForMemory::with_labels(label_repository.clone()),
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let list = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = build_todo_req_with_empty(Method::GET, path);
+                let res = app.oneshot(req).await.unwrap();
+                let total = res
+                    .headers()
+                    .get("x-total-count")
+                    .map(|total| total.to_str().unwrap().to_string());
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+                let names = labels
+                    .into_iter()
+                    .map(|label| label.name)
+                    .collect::<Vec<_>>();
+                (names, total)
+            }
+        };
+
+        let (names, total) = list("/labels").await;
+        assert_eq!(vec!["b", "c", "a"], names);
+        assert_eq!(None, total);
+        let (names, total) = list("/labels?limit=1&offset=1").await;
+        assert_eq!(vec!["c"], names);
+        assert_eq!(Some("3".to_string()), total);
+        let (names, total) = list("/labels?sort=name&limit=2").await;
+        assert_eq!(vec!["a", "b"], names);
+        assert_eq!(Some("3".to_string()), total);
+
+        for path in ["/labels?sort=created_at", "/labels?limit=0"] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_check_database_health() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        for (path, expected) in [
+            ("/health/db", r#"{"status":"ok","write":false}"#),
+            ("/health/db?write=true", r#"{"status":"ok","write":true}"#),
+        ] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(expected, String::from_utf8(bytes.to_vec()).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_pass_wiring_check() {
+        let config = AppConfig {
+            debug_endpoints: true,
+            admin_endpoints: true,
+            ..AppConfig::default()
+        };
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository.create("probed".to_string()).await.unwrap();
+        let todo_repository = TodoRepositoryForMemory::with_labels(label_repository.clone());
+        let todo = todo_repository
+            .create(CreateTodo {
+                text: "probed".to_string(),
+                labels: vec![label.id],
+                external_id: None,
+            })
+            .await
+            .unwrap();
+        let app = create_validated_app(
+            todo_repository.clone(),
+            label_repository.clone(),
+            PreferencesRepositoryForMemory::new(),
+            &config,
+        )
+        .await;
+        assert!(app.is_ok(), "{:?}", app.err());
+
+        // the write routes were probed without writing
+        let todos = todo_repository
+            .all(TodoListParams::default())
+            .await
+            .unwrap();
+        assert_eq!(vec![todo], todos);
+        let labels = label_repository.all(LabelListParams::default()).await;
+        assert_eq!(vec![label], labels.unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_replace_todo_labels() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let mut labels = Vec::new();
+        for name in ["home", "work", "errand"] {
+            labels.push(label_repository.create(name.to_string()).await.unwrap());
+        }
+        let (home, work, errand) = (&labels[0], &labels[1], &labels[2]);
+        let todo_repository = TodoRepositoryForMemory::with_labels(label_repository.clone());
+        let todo = todo_repository
+            .create(CreateTodo {
+                text: "relabeled".to_string(),
+                labels: vec![home.id],
+                external_id: None,
+            })
+            .await
+            .unwrap();
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let put = |label_ids: Vec<i32>| {
+            let app = app.clone();
+            let path = format!("/todos/{}/labels", todo.id);
+            async move {
+                let body = format!(r#"{{ "label_ids": {:?} }}"#, label_ids);
+                let req = build_todo_req_with_json(&path, Method::PUT, body);
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        // adding and removing in one call
+        let res = put(vec![errand.id.get(), work.id.get()]).await;
+        assert_eq!(StatusCode::OK, res.status());
+        let relabeled = res_to_todo(res).await;
+        assert_eq!(vec![work.clone(), errand.clone()], relabeled.labels);
+
+        // the same set again changes nothing
+        let res = put(vec![work.id.get(), errand.id.get(), work.id.get()]).await;
+        assert_eq!(relabeled, res_to_todo(res).await);
+
+        let res = put(vec![]).await;
+        assert!(res_to_todo(res).await.labels.is_empty());
+        let res = put(vec![home.id.get()]).await;
+        assert_eq!(vec![home.clone()], res_to_todo(res).await.labels);
+
+        // one unknown label rejects the whole set
+        let res = put(vec![work.id.get(), 98, 99]).await;
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(
+            r#"{"error":"labels not found","missing":[98,99]}"#,
+            String::from_utf8(bytes.to_vec()).unwrap()
+        );
+        let req = build_todo_req_with_empty(Method::GET, &format!("/todos/{}", todo.id));
+        let unchanged = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(vec![home.clone()], unchanged.labels);
+
+        let req = build_todo_req_with_json(
+            "/todos/99/labels",
+            Method::PUT,
+            r#"{ "label_ids": [] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_reset_todo() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository.create("label".to_string()).await.unwrap();
+        let todo_repository = TodoRepositoryForMemory::with_labels(label_repository.clone());
+        let created = todo_repository
+            .create(CreateTodo {
+                text: "recurring".to_string(),
+                labels: vec![label.id],
+                external_id: None,
+            })
+            .await
+            .unwrap();
+        todo_repository
+            .update(
+                created.id,
+                UpdateTodo {
+                    text: None,
+                    completed: Some(true),
+                    external_id: None,
+                },
+            )
+            .await
+            .unwrap();
+        todo_repository.set_pinned(created.id, true).await.unwrap();
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/reset");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let expected = Todo::new(created.id, "recurring".to_string());
+        assert_eq!(expected, res_to_todo(res).await);
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        assert_eq!(
+            expected,
+            res_to_todo(app.clone().oneshot(req).await.unwrap()).await
+        );
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/99/reset");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_list_label_ids_when_asked() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository.create("label".to_string()).await.unwrap();
+        let todo_repository = TodoRepositoryForMemory::with_labels(label_repository.clone());
+        todo_repository
+            .create(CreateTodo {
+                text: "labeled".to_string(),
+                labels: vec![label.id],
+                external_id: None,
+            })
+            .await
+            .unwrap();
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let list = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = build_todo_req_with_empty(Method::GET, path);
+                let res = app.oneshot(req).await.unwrap();
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                String::from_utf8(bytes.to_vec()).unwrap()
+            }
+        };
+
+        let full = format!(
+            r#"[{{"id":1,"text":"labeled","completed":false,"pinned":false,"labels":[{{"id":{},"name":"label"}}]}}]"#,
+            label.id
+        );
+        assert_eq!(full, list("/todos").await);
+        assert_eq!(full, list("/todos?labels=full").await);
+        assert_eq!(
+            format!(
+                r#"[{{"id":1,"text":"labeled","completed":false,"pinned":false,"label_ids":[{}]}}]"#,
+                label.id
+            ),
+            list("/todos?labels=ids").await
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?labels=names");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_truncate_text_in_list_view() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        for text in ["short", "日本語のテキスト"] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string()))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let list = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = build_todo_req_with_empty(Method::GET, path);
+                let bytes = hyper::body::to_bytes(app.oneshot(req).await.unwrap().into_body())
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<Vec<ListedTodo>>(&bytes).unwrap()
+            }
+        };
+
+        let todos = list("/todos?truncate_text=5").await;
+        assert_eq!("日本語のテ…", todos[0].text);
+        assert!(todos[0].truncated);
+        assert_eq!("short", todos[1].text);
+        assert!(!todos[1].truncated);
+
+        let todos = list("/todos").await;
+        assert_eq!("日本語のテキスト", todos[0].text);
+        assert!(todos.iter().all(|todo| !todo.truncated));
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?truncate_text=0");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        // a single todo is always returned in full
+        let req = build_todo_req_with_empty(Method::GET, "/todos/2");
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!("日本語のテキスト", todo.text);
+    }
+
+    #[tokio::test]
+    async fn should_watch_a_single_todo() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        for text in ["watched", "other"] {
+            todo_repository
+                .create(CreateTodo::new(text.to_string()))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/999/watch");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1/watch");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let events = res.into_body();
+
+        for (path, method, json) in [
+            ("/todos/2", Method::PATCH, r#"{ "completed": true }"#),
+            ("/todos/1", Method::PATCH, r#"{ "text": "watched!" }"#),
+        ] {
+            let req = build_todo_req_with_json(path, method, json.to_string());
+            app.clone().oneshot(req).await.unwrap();
+        }
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        app.oneshot(req).await.unwrap();
+
+        // the change to todo 2 is filtered out and the stream ends on delete
+        let bytes = hyper::body::to_bytes(events).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        assert_eq!(
+            "event: updated\n\
+             data:{\"id\":1,\"text\":\"watched!\",\"completed\":false,\"pinned\":false,\"labels\":[]}\n\n\
+             event: deleted\n\
+             data:{\"id\":1}\n\n",
+            body
+        );
+    }
+
+    #[tokio::test]
+    async fn should_long_poll_for_changes() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!("0", res.headers()[CHANGE_CURSOR_HEADER]);
+
+        // nothing changes, so the poll times out empty
+        let req = build_todo_req_with_empty(Method::GET, "/todos?wait=true&since=0&timeout=1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let changes: TodoChanges = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(0, changes.cursor);
+        assert!(changes.changes.is_empty());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?wait=true&since=0");
+        let poll = tokio::spawn(app.clone().oneshot(req));
+        tokio::task::yield_now().await;
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "polled" }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        let res = poll.await.unwrap().unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let changes: TodoChanges = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(1, changes.cursor);
+        assert_eq!(vec![TodoChangeEntry::Created { todo }], changes.changes);
+
+        // a cursor this server never handed out
+        let req = build_todo_req_with_empty(Method::GET, "/todos?wait=true&since=5");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::GONE, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_wake_every_parked_change_poll() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let polls = (0..5)
+            .map(|_| {
+                let req = build_todo_req_with_empty(Method::GET, "/todos/changes?since=0");
+                tokio::spawn(app.clone().oneshot(req))
+            })
+            .collect::<Vec<_>>();
+        tokio::task::yield_now().await;
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "parked" }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+
+        for poll in polls {
+            let res = tokio::time::timeout(std::time::Duration::from_secs(1), poll)
+                .await
+                .expect("parked poll was not woken")
+                .unwrap()
+                .unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let changes: TodoChanges = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(1, changes.cursor);
+            assert_eq!(
+                vec![TodoChangeEntry::Created { todo: todo.clone() }],
+                changes.changes
+            );
+        }
+
+        // already behind, so it answers without parking
+        let req = build_todo_req_with_empty(Method::GET, "/todos/changes?since=0&timeout=30");
+        let res = tokio::time::timeout(std::time::Duration::from_secs(1), app.clone().oneshot(req))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/changes?since=1&timeout=1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let changes: TodoChanges = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(1, changes.cursor);
+        assert!(changes.changes.is_empty());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/changes?timeout=3600");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_update_todo() {
+        let expected = Todo::new(TodoId::new(1).unwrap(), "should_update_todo".to_string());
+
+        let todo_repository = TodoRepositoryForMemory::new();
+        todo_repository
+            .create(CreateTodo::new("before_update_todo".to_string()))
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{
+    "id": 1,
+    "text": "should_update_todo",
+    "completed": false
+}"#
+            .to_string(),
+        );
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(expected, todo);
+    }
+
+    #[tokio::test]
+    async fn should_refuse_editing_completed_todo_when_locked() {
+        let todo_repository = TodoRepositoryForMemory::new().lock_completed(true);
+        todo_repository
+            .create(CreateTodo::new("locked".to_string()))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        for (body, status) in [
+            (r#"{ "completed": true }"#, StatusCode::ACCEPTED),
+            (r#"{ "text": "edited" }"#, StatusCode::CONFLICT),
+            (
+                r#"{ "text": "edited", "completed": true }"#,
+                StatusCode::CONFLICT,
+            ),
+            // changes nothing, so there is nothing to refuse
+            (r#"{ "completed": true }"#, StatusCode::OK),
+            (
+                r#"{ "text": "edited", "completed": false }"#,
+                StatusCode::ACCEPTED,
+            ),
+            (r#"{ "text": "edited again" }"#, StatusCode::ACCEPTED),
+        ] {
+            let req = build_todo_req_with_json("/todos/1", Method::PATCH, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(status, res.status(), "{}", body);
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todo: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("edited again", todo["text"]);
+        assert_eq!(false, todo["completed"]);
+    }
+
+    #[tokio::test]
+    async fn should_embed_existing_resource_in_duplicate_conflicts() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let app = create_app(
+            TodoRepositoryForMemory::with_labels(label_repository.clone()),
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let body_of = |res: Response| async move {
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+
+        let create_label = || {
+            build_todo_req_with_json("/labels", Method::POST, r#"{ "name": "work" }"#.to_string())
+        };
+        let res = app.clone().oneshot(create_label()).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let res = app.clone().oneshot(create_label()).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let conflict = body_of(res).await;
+        let req = build_todo_req_with_empty(Method::GET, "/labels");
+        let labels = body_of(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!("duplicate", conflict["error"]);
+        assert_eq!(labels[0], conflict["conflict"]["resource"]);
+        assert_eq!(serde_json::json!(["name"]), conflict["conflict"]["matched"]);
+        assert_eq!("rename", conflict["conflict"]["resolution"]);
+
+        let create_todo = |text: &str| {
+            let body = serde_json::json!({ "text": text, "external_id": "ticket-1" }).to_string();
+            build_todo_req_with_json("/todos", Method::POST, body)
+        };
+        let res = app.clone().oneshot(create_todo("first")).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let res = app.clone().oneshot(create_todo("retried")).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let conflict = body_of(res).await;
+        let req = build_todo_req_with_empty(Method::GET, "/todos/by-external/ticket-1");
+        let existing = body_of(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(existing, conflict["conflict"]["resource"]);
+        assert_eq!(
+            serde_json::json!(["external_id"]),
+            conflict["conflict"]["matched"]
+        );
+        assert_eq!("use_existing", conflict["conflict"]["resolution"]);
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "second" }"#.to_string(),
+        );
+        let second = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}", second.id),
+            Method::PATCH,
+            r#"{ "external_id": "ticket-1" }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let conflict = body_of(res).await;
+        assert_eq!(existing, conflict["conflict"]["resource"]);
+        assert_eq!("merge", conflict["conflict"]["resolution"]);
+    }
+
+    #[tokio::test]
+    async fn should_answer_not_modified_while_todos_are_unchanged() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let list = |if_none_match: Option<String>| {
+            let app = app.clone();
+            async move {
+                let mut req = build_todo_req_with_empty(Method::GET, "/todos");
+                if let Some(tags) = if_none_match {
+                    req.headers_mut()
+                        .insert(header::IF_NONE_MATCH, tags.parse().unwrap());
+                }
+                let res = app.oneshot(req).await.unwrap();
+                let status = res.status();
+                let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                (status, etag, bytes.len())
+            }
+        };
+
+        let (status, etag, _) = list(None).await;
+        assert_eq!(StatusCode::OK, status);
+        for tags in [
+            etag.clone(),
+            format!("W/{}", etag),
+            format!("\"other\", {}", etag),
+            "*".to_string(),
+        ] {
+            let (status, same, len) = list(Some(tags)).await;
+            assert_eq!(StatusCode::NOT_MODIFIED, status);
+            assert_eq!(etag, same);
+            assert_eq!(0, len);
+        }
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "changed" }"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let (status, changed, len) = list(Some(etag.clone())).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_ne!(etag, changed);
+        assert!(len > 2);
+    }
+
+    #[tokio::test]
+    async fn should_pick_random_open_todo() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let todo_repository = TodoRepositoryForMemory::with_labels(label_repository.clone());
+        let label = label_repository.create("random".to_string()).await.unwrap();
+        for (text, labels) in [("open", vec![]), ("labeled", vec![label.id])] {
+            let payload = CreateTodo {
+                labels,
+                ..CreateTodo::new(text.to_string())
+            };
+            todo_repository.create(payload).await.unwrap();
+        }
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let pick = |path: String| {
+            let app = app.clone();
+            async move {
+                let req = build_todo_req_with_empty(Method::GET, &path);
+                let res = app.oneshot(req).await.unwrap();
+                match res.status() {
+                    StatusCode::OK => Some(res_to_todo(res).await.text),
+                    status => {
+                        assert_eq!(StatusCode::NOT_FOUND, status);
+                        None
+                    }
+                }
+            }
+        };
+
+        let picked = pick("/todos/random".to_string()).await.unwrap();
+        assert!(["open", "labeled"].contains(&picked.as_str()));
+        let labeled = format!("/todos/random?label={}", label.id);
+        assert_eq!(Some("labeled".to_string()), pick(labeled.clone()).await);
+
+        let req = build_todo_req_with_json(
+            "/todos/2",
+            Method::PATCH,
+            r#"{ "completed": true }"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        assert_eq!(None, pick(labeled).await);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/random?label=0");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(String::from_utf8(bytes.to_vec())
+            .unwrap()
+            .starts_with("query parse error: label: invalid label id `0`"));
+    }
+
+    #[tokio::test]
+    async fn should_run_batched_calls_in_order() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let run = |path: &'static str, items: serde_json::Value| {
+            let app = app.clone();
+            async move {
+                let req = build_todo_req_with_json(path, Method::POST, items.to_string());
+                let res = app.oneshot(req).await.unwrap();
+                let status = res.status();
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<Vec<BatchResult>>(&bytes).ok(),
+                )
+            }
+        };
+
+        let (status, results) = run(
+            "/batch",
+            serde_json::json!([
+                { "method": "POST", "path": "/todos", "body": { "text": "batched" } },
+                { "method": "PATCH", "path": "/todos/1", "body": { "completed": true } },
+                { "method": "POST", "path": "/todos", "body": { "text": "" } },
+                { "method": "GET", "path": "/todos/99" },
+                { "method": "get", "path": "/todos/1" },
+                { "method": "POST", "path": "/batch", "body": [] },
+            ]),
+        )
+        .await;
+        assert_eq!(StatusCode::OK, status);
+        let results = results.unwrap();
+        let statuses = results.iter().map(|r| r.status).collect::<Vec<_>>();
+        assert_eq!(vec![201, 202, 400, 404, 200, 400], statuses);
+        assert_eq!("batched", results[0].body["text"]);
+        assert!(results[2].body.is_string());
+        assert_eq!(serde_json::Value::Null, results[3].body);
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let found: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(found, results[4].body);
+        assert_eq!(true, found["completed"]);
+
+        // stops at the 404, so the create after it never runs
+        let (_, results) = run(
+            "/batch?stop_on_error=true",
+            serde_json::json!([
+                { "method": "GET", "path": "/todos/99" },
+                { "method": "POST", "path": "/todos", "body": { "text": "skipped" } },
+            ]),
+        )
+        .await;
+        assert_eq!(
+            vec![404],
+            results
+                .unwrap()
+                .iter()
+                .map(|r| r.status)
+                .collect::<Vec<_>>()
+        );
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<serde_json::Value> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(1, todos.len());
+
+        let too_many = vec![serde_json::json!({ "method": "GET", "path": "/" }); 51];
+        let (status, _) = run("/batch", serde_json::Value::from(too_many)).await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+    }
+
+    #[tokio::test]
+    async fn should_round_trip_preferences_per_api_key() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let send = |method: Method, key: &'static str, body: Option<serde_json::Value>| {
+            let app = app.clone();
+            async move {
+                let req = Request::builder()
+                    .uri("/preferences")
+                    .method(method)
+                    .header(header::AUTHORIZATION, format!("Bearer {}", key))
+                    .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+                    .unwrap();
+                let res = app.oneshot(req).await.unwrap();
+                let status = res.status();
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&bytes).ok(),
+                )
+            }
+        };
+
+        let (status, body) = send(Method::GET, "key-1", None).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(
+            serde_json::json!({ "preferences": {}, "updated_at": null }),
+            body.unwrap()
+        );
+
+        let preferences = serde_json::json!({
+            "default_sort": "oldest",
+            "hide_completed": true,
+            "theme": "dark",
+            "custom": { "density": "compact" },
+        });
+        let (status, body) = send(Method::PUT, "key-1", Some(preferences.clone())).await;
+        assert_eq!(StatusCode::OK, status);
+        let body = body.unwrap();
+        assert_eq!(preferences, body["preferences"]);
+        assert!(body["updated_at"].is_i64());
+        let (_, found) = send(Method::GET, "key-1", None).await;
+        assert_eq!(body, found.unwrap());
+
+        // another key has its own preferences
+        let (_, other) = send(Method::GET, "key-2", None).await;
+        assert_eq!(serde_json::json!({}), other.unwrap()["preferences"]);
+
+        for invalid in [
+            serde_json::json!({ "accent": "red" }),
+            serde_json::json!({ "theme": "sepia" }),
+            serde_json::json!({ "hide_completed": "yes" }),
+            serde_json::json!({ "custom": { "": 1 } }),
+        ] {
+            let (status, _) = send(Method::PUT, "key-1", Some(invalid.clone())).await;
+            assert_eq!(StatusCode::BAD_REQUEST, status, "{}", invalid);
+        }
+        let (_, kept) = send(Method::GET, "key-1", None).await;
+        assert_eq!(preferences, kept.unwrap()["preferences"]);
+    }
+
+    #[tokio::test]
+    async fn should_send_batched_calls_as_the_caller() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let preferences = |key: Option<&'static str>| {
+            let app = app.clone();
+            async move {
+                let mut req = Request::builder().uri("/preferences");
+                if let Some(key) = key {
+                    req = req.header(header::AUTHORIZATION, format!("Bearer {}", key));
+                }
+                let res = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()["preferences"].clone()
+            }
+        };
+
+        let items = serde_json::json!([
+            { "method": "PUT", "path": "/preferences", "body": { "theme": "dark" } },
+            {
+                "method": "PUT",
+                "path": "/preferences",
+                "body": { "theme": "light" },
+                "headers": { "authorization": "Bearer key-2" },
+            },
+            { "method": "GET", "path": "/preferences", "headers": { "bad header": "x" } },
+        ]);
+        let req = Request::builder()
+            .uri("/batch")
+            .method(Method::POST)
+            .header(header::AUTHORIZATION, "Bearer key-1")
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(items.to_string()))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let results: Vec<BatchResult> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            vec![200, 200, 400],
+            results.iter().map(|r| r.status).collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            serde_json::json!({ "theme": "dark" }),
+            preferences(Some("key-1")).await
+        );
+        assert_eq!(
+            serde_json::json!({ "theme": "light" }),
+            preferences(Some("key-2")).await
+        );
+        assert_eq!(serde_json::json!({}), preferences(None).await);
+    }
+
+    #[tokio::test]
+    async fn should_report_changed_fields_on_update() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        todo_repository
+            .create(CreateTodo::new("unchanged".to_string()))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        for (body, status, changed_fields) in [
+            (
+                r#"{ "text": "unchanged", "completed": true }"#,
+                StatusCode::ACCEPTED,
+                serde_json::json!(["completed"]),
+            ),
+            // trimmed to the current text, so nothing to do
+            (
+                r#"{ "text": " unchanged ", "completed": true }"#,
+                StatusCode::OK,
+                serde_json::json!([]),
+            ),
+        ] {
+            let req = build_todo_req_with_json("/todos/1", Method::PATCH, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(status, res.status(), "{}", body);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let updated: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(changed_fields, updated["changed_fields"], "{}", body);
+            assert_eq!(true, updated["completed"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_delete_todo() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        todo_repository
+            .create(CreateTodo::new("should_delete_todo".to_string()))
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_move_todos_between_labels() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let mut labels = Vec::new();
+        for name in ["from", "to", "other"] {
+            labels.push(label_repository.create(name.to_string()).await.unwrap());
+        }
+        let (from, to, other) = (&labels[0], &labels[1], &labels[2]);
+        let todo_repository = TodoRepositoryForMemory::with_labels(label_repository.clone());
+        for label_ids in [vec![from.id], vec![from.id, to.id], vec![other.id]] {
+            todo_repository
+                .create(CreateTodo {
+                    text: "labeled".to_string(),
+                    labels: label_ids,
+                    external_id: None,
+                })
+                .await
+                .unwrap();
+        }
+        let app = create_app(
+            todo_repository.clone(),
+            label_repository.clone(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let path = format!("/labels/{}/move-todos/{}", from.id, to.id);
+        for expected in [2, 0] {
+            let req = build_todo_req_with_empty(Method::POST, &path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(serde_json::json!({ "moved": expected }), body);
+        }
+        let todos = todo_repository
+            .all(TodoListParams::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|todo| todo.labels)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![vec![other.clone()], vec![to.clone()], vec![to.clone()]],
+            todos
+        );
+        // unlike a merge, the source label stays
+        assert!(label_repository
+            .all(LabelListParams::default())
+            .await
+            .unwrap()
+            .contains(from));
+
+        for (path, status) in [
+            (
+                format!("/labels/{}/move-todos/{}", to.id, to.id),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                format!("/labels/{}/move-todos/99", to.id),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                format!("/labels/99/move-todos/{}", to.id),
+                StatusCode::NOT_FOUND,
+            ),
+        ] {
+            let req = build_todo_req_with_empty(Method::POST, &path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(status, res.status(), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_move_todo_to_label() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let before = label_repository
+            .create("before".to_string())
+            .await
+            .expect("failed create label");
+        let after = label_repository
+            .create("after".to_string())
+            .await
+            .expect("failed create label");
+        let todo_repository = TodoRepositoryForMemory::with_labels(label_repository.clone());
+        todo_repository
+            .create(CreateTodo {
+                text: "should_move_todo_to_label".to_string(),
+                labels: vec![before.id],
+                external_id: None,
+            })
+            .await
+            .expect("failed create todo");
+        let expected = Todo {
+            labels: vec![after.clone()],
+            ..Todo::new(
+                TodoId::new(1).unwrap(),
+                "should_move_todo_to_label".to_string(),
+            )
+        };
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let req = build_todo_req_with_empty(
+            Method::POST,
+            &format!("/todos/1/move-to-label/{}", after.id),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todo = res_to_todo(res).await;
+        assert_eq!(expected, todo);
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/move-to-label/999");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = build_todo_req_with_empty(
+            Method::POST,
+            &format!("/todos/999/move-to-label/{}", after.id),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_reject_out_of_range_ids() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        for (method, path, value) in [
+            (Method::GET, "/todos/0", "todo id `0`"),
+            (Method::GET, "/todos/-5", "todo id `-5`"),
+            (Method::PATCH, "/todos/99999999999", "todo id `99999999999`"),
+            (Method::DELETE, "/todos/abc", "todo id `abc`"),
+            (Method::GET, "/todos/0/watch", "todo id `0`"),
+            (Method::POST, "/todos/1/move-to-label/0", "label id `0`"),
+            (Method::DELETE, "/labels/-1", "label id `-1`"),
+        ] {
+            let req = build_todo_req_with_empty(method, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", path);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body = String::from_utf8(bytes.to_vec()).unwrap();
+            assert!(body.contains(value), "{}", body);
+            assert!(body.contains("from 1 to 2147483647"), "{}", body);
+        }
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "labeled", "labels": [0] }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_suggest_labels_by_usage() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let work = label_repository.create("work".to_string()).await.unwrap();
+        let workout = label_repository
+            .create("workout".to_string())
+            .await
+            .unwrap();
+        label_repository.create("home".to_string()).await.unwrap();
+        let todo_repository = TodoRepositoryForMemory::with_labels(label_repository.clone());
+        todo_repository
+            .create(CreateTodo {
+                text: "should_suggest_labels_by_usage".to_string(),
+                labels: vec![workout.id],
+                external_id: None,
+            })
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels/suggest?prefix=WO");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec![workout, work], labels);
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels/suggest");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_search_labels_despite_typos() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let work = label_repository.create("work".to_string()).await.unwrap();
+        label_repository.create("home".to_string()).await.unwrap();
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels?q=wrok&fuzzy=true&limit=10");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("1", res.headers()["x-total-count"]);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec![work], labels);
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels?q=wrok");
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+        assert!(labels.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_fail_fast_when_circuit_is_open() {
+        let inner = FlakyTodoRepository::new();
+        let breaker = Breaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            cool_down: std::time::Duration::from_secs(30),
+        });
+        let todo_repository = CircuitBreaker::new(inner.clone(), breaker);
+        inner.set_down(true);
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+        assert_eq!("30", res.headers()[header::RETRY_AFTER]);
+    }
+
+    #[tokio::test]
+    async fn should_return_correlation_id_on_internal_error() {
+        let todo_repository = FlakyTodoRepository::new();
+        todo_repository.set_down(true);
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let req = Request::builder()
+            .uri("/todos")
+            .header(middlewares::REQUEST_ID_HEADER, "support-42")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+        assert_eq!("support-42", res.headers()[middlewares::REQUEST_ID_HEADER]);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "error": "internal error",
+                "correlation_id": "support-42",
+            }),
+            body
+        );
+    }
+
+    #[tokio::test]
+    async fn should_use_configured_request_id_header() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig {
+                request_id_header: header::HeaderName::from_static("x-correlation-id"),
+                ..AppConfig::default()
+            },
+        );
+
+        let req = Request::builder()
+            .uri("/todos")
+            .header("x-correlation-id", "trace-7")
+            .header(middlewares::REQUEST_ID_HEADER, "ignored")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!("trace-7", res.headers()["x-correlation-id"]);
+        assert!(res.headers().get(middlewares::REQUEST_ID_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn should_let_the_ui_origin_use_the_custom_headers() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig {
+                request_id_header: header::HeaderName::from_static("x-correlation-id"),
+                ..AppConfig::default()
+            },
+        );
+
+        let req = Request::builder()
+            .uri("/todos")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, CORS_ORIGINS[0])
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let allowed = res.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap()
+            .to_string();
+        for name in [
+            middlewares::READ_CONSISTENCY_HEADER,
+            middlewares::CONSISTENCY_TOKEN_HEADER,
+            middlewares::REQUEST_DEADLINE_HEADER,
+            "x-correlation-id",
+        ] {
+            assert!(allowed.split(',').any(|h| h.trim() == name), "{}", allowed);
+        }
+
+        let req = Request::builder()
+            .uri("/todos")
+            .header(header::ORIGIN, CORS_ORIGINS[0])
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let exposed = res.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap();
+        for name in [
+            middlewares::CONSISTENCY_TOKEN_HEADER,
+            "x-total-count",
+            TRUNCATED_HEADER,
+            CHANGE_CURSOR_HEADER,
+            "etag",
+        ] {
+            assert!(exposed.split(',').any(|h| h.trim() == name), "{}", exposed);
+        }
+    }
+
+    #[cfg(not(feature = "console"))]
+    #[tokio::test]
+    async fn should_not_route_debug_tasks_by_default() {
+        let req = build_todo_req_with_empty(Method::GET, "/debug/tasks");
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[cfg(feature = "console")]
+    #[tokio::test]
+    async fn should_summarize_runtime_tasks() {
+        let req = build_todo_req_with_empty(Method::GET, "/debug/tasks");
+        let res = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert!(summary["workers"].as_u64().unwrap() >= 1);
+    }
+
+    fn debug_app() -> Router {
+        create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig {
+                debug_endpoints: true,
+                ..AppConfig::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn should_not_route_debug_endpoints_by_default() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        for (method, path) in [
+            (Method::GET, "/debug/routes"),
+            (Method::POST, "/debug/echo"),
+        ] {
+            let req = build_todo_req_with_empty(method, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::NOT_FOUND, res.status());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_repair_orphaned_label_links() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let kept = label_repository.create("kept".to_string()).await.unwrap();
+        let dropped = label_repository
+            .create("dropped".to_string())
+            .await
+            .unwrap();
+        let todo_repository = TodoRepositoryForMemory::with_labels(label_repository.clone());
+        let todo = todo_repository
+            .create(CreateTodo {
+                text: "labeled".to_string(),
+                labels: vec![kept.id, dropped.id],
+                external_id: None,
+            })
+            .await
+            .unwrap();
+        // corrupt the links the way manual deletes would
+        label_repository.delete(dropped.id).await.unwrap();
+        let ghost = TodoId::new(99).unwrap();
+        label_repository
+            .write_todo_labels_ref()
+            .insert((ghost, kept.id));
+
+        let app = create_app(
+            todo_repository.clone(),
+            label_repository.clone(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let req = build_todo_req_with_empty(Method::POST, "/admin/repair");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let app = create_app(
+            todo_repository,
+            label_repository.clone(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig {
+                admin_endpoints: true,
+                ..AppConfig::default()
+            },
+        );
+        let expected = |dry_run: bool| {
+            serde_json::json!({
+                "dry_run": dry_run,
+                "orphaned_links": [
+                    { "todo_id": todo.id, "label_id": dropped.id },
+                    { "todo_id": 99, "label_id": kept.id },
+                ],
+                "duplicate_links": 0,
+            })
+        };
+        for (path, report) in [
+            ("/admin/repair?dry_run=true", expected(true)),
+            ("/admin/repair", expected(false)),
+        ] {
+            let req = build_todo_req_with_empty(Method::POST, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(report, body);
+        }
+
+        let links = label_repository.read_todo_labels_ref().clone();
+        assert_eq!(
+            vec![(todo.id, kept.id)],
+            links.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_list_registered_routes() {
+        let req = build_todo_req_with_empty(Method::GET, "/debug/routes");
+        let res = debug_app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let routes: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let routes = routes.as_array().unwrap();
+        assert!(routes.contains(&serde_json::json!({
+            "path": "/todos/:id",
+            "methods": ["GET", "HEAD", "PATCH", "DELETE"],
+        })));
+        assert!(routes.contains(&serde_json::json!({
+            "path": "/debug/echo",
+            "methods": ["ANY"],
+        })));
+    }
+
+    #[tokio::test]
+    async fn should_echo_request() {
+        let req = Request::builder()
+            .uri("/debug/echo?verbose=1")
+            .method(Method::PUT)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header("x-forwarded-for", "10.0.0.1")
+            .header("x-forwarded-for", "10.0.0.2")
+            .body(Body::from(r#"{ "text": "echo" }"#))
+            .unwrap();
+        let res = debug_app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let echo: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "method": "PUT",
+                "path": "/debug/echo",
+                "query": "verbose=1",
+                "headers": {
+                    "content-type": ["application/json"],
+                    "x-forwarded-for": ["10.0.0.1", "10.0.0.2"],
+                },
+                "body": r#"{ "text": "echo" }"#,
+            }),
+            echo
+        );
+    }
+
+    fn limited_app(mode: OverloadMode) -> Router {
+        create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig {
+                concurrency: ConcurrencyConfig {
+                    max_in_flight: 1,
+                    mode,
+                },
+                ..AppConfig::default()
+            },
+        )
+    }
+
+    /// A create request that holds its slot until the body sender is dropped.
+    fn stalled_create() -> (hyper::body::Sender, Request<Body>) {
+        let (sender, body) = Body::channel();
+        let req = Request::builder()
+            .uri("/todos")
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(body)
+            .unwrap();
+        (sender, req)
+    }
+
+    #[tokio::test]
+    async fn should_reject_requests_over_the_concurrency_limit() {
+        let app = limited_app(OverloadMode::Reject);
+        let (mut sender, req) = stalled_create();
+        let stalled = tokio::spawn(app.clone().oneshot(req));
+        tokio::task::yield_now().await;
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+        assert_eq!("1", res.headers()[header::RETRY_AFTER]);
+
+        sender
+            .send_data(r#"{ "text": "stalled" }"#.into())
+            .await
+            .unwrap();
+        drop(sender);
+        let res = stalled.await.unwrap().unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_queue_requests_over_the_concurrency_limit() {
+        let app = limited_app(OverloadMode::Queue);
+        let (mut sender, req) = stalled_create();
+        let stalled = tokio::spawn(app.clone().oneshot(req));
+        tokio::task::yield_now().await;
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let queued = tokio::spawn(app.oneshot(req));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!queued.is_finished());
+
+        sender
+            .send_data(r#"{ "text": "stalled" }"#.into())
+            .await
+            .unwrap();
+        drop(sender);
+        assert_eq!(
+            StatusCode::CREATED,
+            stalled.await.unwrap().unwrap().status()
+        );
+        assert_eq!(StatusCode::OK, queued.await.unwrap().unwrap().status());
+    }
+
+    async fn load_report(app: &Router) -> (StatusCode, serde_json::Value) {
+        let req = build_todo_req_with_empty(Method::GET, "/readyz");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_shed_readiness_under_pressure() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig {
+                concurrency: ConcurrencyConfig {
+                    max_in_flight: 4,
+                    mode: OverloadMode::Queue,
+                },
+                readiness: ReadinessConfig {
+                    max_pressure: 0.5,
+                    recover_pressure: 0.25,
+                },
+                ..AppConfig::default()
+            },
+        );
+        let (status, report) = load_report(&app).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(
+            serde_json::json!({
+                "ready": true,
+                "in_flight": 0,
+                "max_in_flight": 4,
+                "pool": null,
+                "pressure": 0.0,
+            }),
+            report
+        );
+
+        let mut stalled = Vec::new();
+        for _ in 0..3 {
+            let (sender, req) = stalled_create();
+            stalled.push((sender, tokio::spawn(app.clone().oneshot(req))));
+            tokio::task::yield_now().await;
+        }
+        let (status, report) = load_report(&app).await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!(3, report["in_flight"]);
+        assert_eq!(0.75, report["pressure"]);
+
+        // below the max but above the recover level: still out of rotation
+        let (sender, request) = stalled.pop().unwrap();
+        drop(sender);
+        request.await.unwrap().unwrap();
+        let (status, report) = load_report(&app).await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!(0.5, report["pressure"]);
+
+        let (sender, request) = stalled.pop().unwrap();
+        drop(sender);
+        request.await.unwrap().unwrap();
+        let (status, report) = load_report(&app).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(0.25, report["pressure"]);
+    }
+
+    #[tokio::test]
+    async fn should_describe_payload_schemas() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/schema/todo");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let schema: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let text = &schema["create"]["properties"]["text"];
+        assert_eq!(1, text["minLength"]);
+        assert_eq!(100, text["maxLength"]);
+        assert_eq!(serde_json::json!(["text"]), schema["create"]["required"]);
+        assert_eq!(100, schema["update"]["properties"]["text"]["maxLength"]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/schema/label");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let schema: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(255, schema["create"]["properties"]["name"]["maxLength"]);
+        assert!(schema.get("update").is_none());
+    }
+
+    #[tokio::test]
+    async fn should_bulk_create_labels() {
+        let label_repository = LabelRepositoryForMemory::new();
+        label_repository.create("b".to_string()).await.unwrap();
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let req = build_todo_req_with_json(
+            "/labels/bulk",
+            Method::POST,
+            r#"{ "names": ["a", "b", "A"] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let labels: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            serde_json::json!([
+                { "id": 2, "name": "a", "created": true },
+                { "id": 1, "name": "b", "created": false },
+            ]),
+            labels
+        );
+
+        let req = build_todo_req_with_json(
+            "/labels/bulk",
+            Method::POST,
+            r#"{ "names": ["a", ""] }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_accept_empty_bulk_operations() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        for (path, body, expected) in [
+            ("/labels/bulk", r#"{ "names": [] }"#, "[]"),
+            ("/todos/exists", r#"{ "ids": [] }"#, "{}"),
+            ("/batch", "[]", "[]"),
+        ] {
+            let req = build_todo_req_with_json(path, Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status(), "{}", path);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(expected.as_bytes(), &bytes[..], "{}", path);
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels");
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(b"[]", &bytes[..]);
+    }
+
+    #[tokio::test]
+    async fn should_mark_deprecated_routes() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        let req =
+            build_todo_req_with_json("/labels", Method::POST, r#"{ "name": "work" }"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!("true", res.headers()["deprecation"]);
+        assert_eq!("Wed, 30 Jun 2027 00:00:00 GMT", res.headers()["sunset"]);
+        assert_eq!(
+            r#"</labels>; rel="successor-version""#,
+            res.headers()[header::LINK]
+        );
+
+        // other methods on the same path are not deprecated
+        let req = build_todo_req_with_empty(Method::GET, "/labels");
+        let res = app.oneshot(req).await.unwrap();
+        assert!(res.headers().get("deprecation").is_none());
+    }
+
+    #[tokio::test]
+    async fn should_ensure_label() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+
+        for _ in 0..2 {
+            let req = build_todo_req_with_empty(Method::PUT, "/labels?name=work");
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let label: Label = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(
+                Label::new(LabelId::new(1).unwrap(), "work".to_string()),
+                label
+            );
+        }
+
+        let req = build_todo_req_with_empty(Method::PUT, "/labels?name=");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let req = build_todo_req_with_empty(Method::PUT, "/labels");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_round_trip_unicode_label_names() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let todo_repository = TodoRepositoryForMemory::with_labels(label_repository.clone());
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            PreferencesRepositoryForMemory::new(),
+            &AppConfig::default(),
+        );
+        let names = ["C++ / systems", "日本語", "emoji 🎉", "a%20b", "q?x=1&y#z"];
+        let encode =
+            |name: &str| form_urlencoded::byte_serialize(name.as_bytes()).collect::<String>();
+
+        let mut created = Vec::new();
+        for name in names {
+            let req = build_todo_req_with_json(
+                "/labels",
+                Method::POST,
+                serde_json::json!({ "name": name }).to_string(),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let label: Label = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(name, label.name);
+
+            let req =
+                build_todo_req_with_empty(Method::PUT, &format!("/labels?name={}", encode(name)));
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(label, serde_json::from_slice::<Label>(&bytes).unwrap());
+            created.push(label);
+        }
+
+        let req = build_todo_req_with_empty(
+            Method::GET,
+            &format!("/labels/suggest?prefix={}", encode("日本")),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let suggested: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec![created[1].clone()], suggested);
+
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            serde_json::json!({
+                "text": "should_round_trip_unicode_label_names",
+                "labels": created.iter().map(|label| label.id).collect::<Vec<_>>(),
+            })
+            .to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let todo = res_to_todo(res).await;
+        let mut labels = todo.labels.clone();
+        labels.sort_by_key(|label| label.id);
+        assert_eq!(created, labels);
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels");
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let listed: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+        for label in &created {
+            assert!(
+                listed.contains(label),
+                "{:?} missing from {:?}",
+                label,
+                listed
+            );
+        }
+    }
+}
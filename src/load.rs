@@ -0,0 +1,189 @@
+use std::env;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use serde::Serialize;
+
+use crate::repositories::todo_repository::PoolUsage;
+
+const DEFAULT_MAX_PRESSURE: f64 = 0.9;
+const DEFAULT_RECOVER_PRESSURE: f64 = 0.7;
+
+/// When `/readyz` tells the load balancer to stop sending traffic.
+///
+/// Readiness is lost once pressure goes above `max_pressure` and only comes
+/// back once it is down to `recover_pressure`, so a server hovering around
+/// the threshold doesn't flap in and out of rotation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadinessConfig {
+    pub max_pressure: f64,
+    pub recover_pressure: f64,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            max_pressure: DEFAULT_MAX_PRESSURE,
+            recover_pressure: DEFAULT_RECOVER_PRESSURE,
+        }
+    }
+}
+
+impl ReadinessConfig {
+    /// Reads `READY_MAX_PRESSURE` and `READY_RECOVER_PRESSURE`, each from 0
+    /// to 1; the recover level is capped at the max.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let pressure = |name: &str| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<f64>().ok())
+                .filter(|pressure| (0.0..=1.0).contains(pressure))
+        };
+        let max_pressure = pressure("READY_MAX_PRESSURE").unwrap_or(default.max_pressure);
+        let recover_pressure = pressure("READY_RECOVER_PRESSURE")
+            .unwrap_or(default.recover_pressure)
+            .min(max_pressure);
+        Self {
+            max_pressure,
+            recover_pressure,
+        }
+    }
+}
+
+/// Database connections in use out of the most the pool opens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PoolLoad {
+    pub in_use: u32,
+    pub max: u32,
+}
+
+/// What `/readyz` reports to the autoscaler.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LoadReport {
+    pub ready: bool,
+    /// Requests being handled or queued for a slot, not counting the one
+    /// asking.
+    pub in_flight: usize,
+    pub max_in_flight: usize,
+    /// `None` without a database pool, e.g. in memory.
+    pub pool: Option<PoolLoad>,
+    /// The fuller of requests and pool, from 0 to 1.
+    pub pressure: f64,
+}
+
+/// Counts requests in flight and decides readiness from them and the pool.
+#[derive(Debug, Clone)]
+pub struct LoadMonitor {
+    in_flight: Arc<AtomicUsize>,
+    shedding: Arc<AtomicBool>,
+    max_in_flight: usize,
+    max_connections: u32,
+    readiness: ReadinessConfig,
+}
+
+/// Takes a request off the count however it ends, including the client
+/// going away mid-request.
+struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl LoadMonitor {
+    pub fn new(max_in_flight: usize, max_connections: u32, readiness: ReadinessConfig) -> Self {
+        Self {
+            in_flight: Arc::default(),
+            shedding: Arc::default(),
+            max_in_flight,
+            max_connections,
+            readiness,
+        }
+    }
+
+    /// Middleware keeping the in-flight count; layered outside the
+    /// concurrency limit so requests queued for a slot count too.
+    pub async fn track<B>(self, req: Request<B>, next: Next<B>) -> impl IntoResponse {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let _in_flight = InFlight(self.in_flight);
+        next.run(req).await
+    }
+
+    /// Called from within a tracked request, which is left out of the count.
+    pub fn report(&self, pool: Option<PoolUsage>) -> LoadReport {
+        let in_flight = self.in_flight.load(Ordering::Relaxed).saturating_sub(1);
+        let pool = pool.map(|usage| PoolLoad {
+            in_use: usage.open.saturating_sub(usage.idle),
+            max: self.max_connections,
+        });
+        let pool_pressure = pool.map_or(0.0, |pool| {
+            f64::from(pool.in_use) / f64::from(pool.max.max(1))
+        });
+        let pressure = (in_flight as f64 / self.max_in_flight.max(1) as f64)
+            .max(pool_pressure)
+            .min(1.0);
+        LoadReport {
+            ready: self.ready(pressure),
+            in_flight,
+            max_in_flight: self.max_in_flight,
+            pool,
+            pressure,
+        }
+    }
+
+    fn ready(&self, pressure: f64) -> bool {
+        let shedding = if self.shedding.load(Ordering::Relaxed) {
+            pressure > self.readiness.recover_pressure
+        } else {
+            pressure > self.readiness.max_pressure
+        };
+        let was_shedding = self.shedding.swap(shedding, Ordering::Relaxed);
+        if shedding != was_shedding {
+            tracing::warn!(pressure, ready = !shedding, "readiness changed");
+        }
+        !shedding
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn readiness_flips_with_hysteresis() {
+        let monitor = LoadMonitor::new(
+            10,
+            10,
+            ReadinessConfig {
+                max_pressure: 0.8,
+                recover_pressure: 0.5,
+            },
+        );
+        let ready = |pressure| monitor.ready(pressure);
+        assert!(ready(0.8));
+        assert!(!ready(0.9));
+        // still shedding between the two levels
+        assert!(!ready(0.7));
+        assert!(ready(0.5));
+        assert!(ready(0.7));
+    }
+
+    #[test]
+    fn pressure_is_the_fuller_of_requests_and_pool() {
+        let monitor = LoadMonitor::new(10, 4, ReadinessConfig::default());
+        monitor.in_flight.store(3, Ordering::Relaxed);
+        let report = monitor.report(None);
+        assert_eq!(2, report.in_flight);
+        assert_eq!(0.2, report.pressure);
+
+        let report = monitor.report(Some(PoolUsage { open: 4, idle: 1 }));
+        assert_eq!(Some(PoolLoad { in_use: 3, max: 4 }), report.pool);
+        assert_eq!(0.75, report.pressure);
+        assert!(report.ready);
+    }
+}
@@ -1,221 +1,142 @@
+use std::env;
 use std::net::SocketAddr;
-use std::{env, sync::Arc};
 
-use axum::{
-    extract::Extension,
-    routing::{delete, get, post},
-    Router,
-};
 use dotenv::dotenv;
-use hyper::header::CONTENT_TYPE;
-use sqlx::PgPool;
-use tower_http::cors::{Any, CorsLayer, Origin};
-
-use handlers::{label_handler::*, todo_handler::*};
-
-use crate::repositories::{label_repository::*, todo_repository::*};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+use todo_api::config::AppConfig;
+use todo_api::preflight;
+use todo_api::repositories::{
+    circuit_breaker::{Breaker, CircuitBreaker},
+    encryption::TextCipher,
+    label_repository::LabelRepositoryForDB,
+    preferences_repository::PreferencesRepositoryForDb,
+    todo_repository::TodoRepositoryForDb,
+};
+use todo_api::startup::{self, StartupInfo};
+use todo_api::{create_validated_app, CORS_ORIGINS};
 
-mod handlers;
-mod models;
-mod repositories;
+/// Rows `manpuku rotate-key` re-encrypts per transaction.
+const ROTATE_KEY_BATCH_SIZE: i64 = 500;
 
 #[tokio::main]
 async fn main() {
     // logging
     let log_level = env::var("RUST_LOG").unwrap_or("info".to_string());
     env::set_var("RUST_LOG", log_level);
-    tracing_subscriber::fmt::init();
+    init_tracing();
     dotenv().ok();
 
-    let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+    let config = AppConfig::from_env();
+    // `manpuku check`: preflight the database without serving anything
+    if env::args().nth(1).as_deref() == Some("check") {
+        let ok = preflight::run(&config).await;
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
     tracing::debug!("start connect database...");
-    let pool = PgPool::connect(database_url)
+    let pool = config
+        .pool
+        .options()
+        .connect(config.database_url.expose())
         .await
-        .expect(&format!("fail connect database, url is [{}]", database_url));
-
-    let todo_repository = TodoRepositoryForDb::new(pool.clone());
-    let label_repository = LabelRepositoryForDB::new(pool.clone());
-    let app = create_app(todo_repository, label_repository);
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
-    tracing::debug!("listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", config.database_url));
+    startup::ensure_tables(&pool, config.auto_migrate)
         .await
-        .unwrap();
-}
-
-fn create_app<Todo: TodoRepository, Label: LabelRepository>(
-    todo_repository: Todo,
-    label_repository: Label,
-) -> Router {
-    Router::new()
-        .route("/", get(root))
-        .route("/todos", post(create_todo::<Todo>).get(all_todo::<Todo>))
-        .route(
-            "/todos/:id",
-            get(find_todo::<Todo>)
-                .delete(delete_todo::<Todo>)
-                .patch(update_todo::<Todo>),
-        )
-        .route(
-            "/labels",
-            post(create_label::<Label>).get(all_label::<Label>),
-        )
-        .route("/labels/:id", delete(delete_label::<Label>))
-        .layer(Extension(Arc::new(todo_repository)))
-        .layer(Extension(Arc::new(label_repository)))
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Origin::exact("http://localhost:3001".parse().unwrap()))
-                .allow_methods(Any)
-                .allow_headers(vec![CONTENT_TYPE]),
-        )
-}
-
-async fn root() -> &'static str {
-    "Hello, World!"
-}
-
-#[cfg(test)]
-mod test {
-    use axum::response::Response;
-    use axum::{
-        body::Body,
-        http::{header, Method, Request, StatusCode},
-    };
-    use tower::ServiceExt;
-
-    use crate::models::todo::{CreateTodo, Todo};
-    use crate::repositories::{
-        label_repository::test_utils::LabelRepositoryForMemory,
-        todo_repository::test_utils::TodoRepositoryForMemory,
+        .unwrap_or_else(|e| panic!("database not ready: {}", e));
+
+    let (todo_repository, backend) = match &config.database_replica_url {
+        Some(replica_url) => {
+            let replica = config
+                .pool
+                .options()
+                .connect(replica_url.expose())
+                .await
+                .unwrap_or_else(|_| {
+                    panic!("fail connect replica database, url is [{}]", replica_url)
+                });
+            (
+                TodoRepositoryForDb::with_replica(pool.clone(), replica),
+                "postgres (primary + replica)",
+            )
+        }
+        None => (TodoRepositoryForDb::new(pool.clone()), "postgres"),
     };
-
-    use super::*;
-
-    fn build_todo_req_with_json(path: &str, method: Method, json_body: String) -> Request<Body> {
-        Request::builder()
-            .uri(path)
-            .method(method)
-            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
-            .body(Body::from(json_body))
-            .unwrap()
-    }
-
-    fn build_todo_req_with_empty(method: Method, path: &str) -> Request<Body> {
-        Request::builder()
-            .uri(path)
-            .method(method)
-            .body(Body::empty())
-            .unwrap()
-    }
-
-    async fn res_to_todo(res: Response) -> Todo {
-        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
-        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
-        let todo: Todo = serde_json::from_str(&body)
-            .expect(&format!("cannot convert Todo instance. body: {}", body));
-        todo
+    let cipher = config.encryption_key.as_ref().map(|key| {
+        let previous: Vec<&str> = config
+            .previous_encryption_keys
+            .iter()
+            .map(|key| key.expose())
+            .collect();
+        TextCipher::new(key.expose(), &previous)
+            .unwrap_or_else(|e| panic!("invalid encryption keys: {}", e))
+    });
+    let todo_repository = todo_repository
+        .lock_completed(config.lock_completed)
+        .encrypt_text(cipher);
+
+    // `manpuku rotate-key`: re-encrypt all todo text under ENCRYPTION_KEY
+    if env::args().nth(1).as_deref() == Some("rotate-key") {
+        match todo_repository
+            .reencrypt_text(ROTATE_KEY_BATCH_SIZE, true)
+            .await
+        {
+            Ok(report) => {
+                println!(
+                    "re-encrypted {} todos, skipped {} under unknown keys",
+                    report.rewritten, report.skipped
+                );
+                std::process::exit(if report.skipped == 0 { 0 } else { 1 });
+            }
+            Err(e) => {
+                eprintln!("error: rotate-key failed: {}", e);
+                std::process::exit(1);
+            }
+        }
     }
 
-    #[tokio::test]
-    async fn should_created_todo() {
-        let expected = Todo::new(1, "should_return_created_todo".to_string());
-
-        let req = build_todo_req_with_json(
-            "/todos",
-            Method::POST,
-            r#"{ "text": "should_return_created_todo" }"#.to_string(),
-        );
-        let res = create_app(
-            TodoRepositoryForMemory::new(),
-            LabelRepositoryForMemory::new(),
-        )
-        .oneshot(req)
+    startup::log_banner(
+        &config,
+        &StartupInfo {
+            backend,
+            migrations: startup::migration_status(&pool).await,
+            cors_origins: CORS_ORIGINS.len(),
+            console: cfg!(feature = "console"),
+        },
+    );
+
+    let breaker = Breaker::new(config.circuit_breaker);
+    let todo_repository = CircuitBreaker::new(todo_repository, breaker.clone());
+    let label_repository =
+        CircuitBreaker::new(LabelRepositoryForDB::new(pool.clone()), breaker.clone());
+    let preferences_repository =
+        CircuitBreaker::new(PreferencesRepositoryForDb::new(pool.clone()), breaker);
+    let app = create_validated_app(
+        todo_repository,
+        label_repository,
+        preferences_repository,
+        &config,
+    )
+    .await
+    .unwrap_or_else(|e| panic!("route wiring check failed: {}", e));
+    tracing::debug!("listening on {}", config.bind_address);
+    axum::Server::bind(&config.bind_address)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr, _>())
         .await
         .unwrap();
-        let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
-    }
-
-    #[tokio::test]
-    async fn should_find_todo() {
-        let expected = Todo::new(1, "should_find_todo".to_string());
-
-        let todo_repository = TodoRepositoryForMemory::new();
-        todo_repository
-            .create(CreateTodo::new("should_find_todo".to_string()))
-            .await
-            .expect("failed create todo");
-        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
-        let res = create_app(todo_repository, LabelRepositoryForMemory::new())
-            .oneshot(req)
-            .await
-            .unwrap();
-        let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
-    }
-
-    #[tokio::test]
-    async fn should_get_all_todos() {
-        let expected = Todo::new(1, "should_get_all_todos".to_string());
-
-        let todo_repository = TodoRepositoryForMemory::new();
-        todo_repository
-            .create(CreateTodo::new("should_get_all_todos".to_string()))
-            .await
-            .expect("failed create todo");
-        let req = build_todo_req_with_empty(Method::GET, "/todos");
-        let res = create_app(todo_repository, LabelRepositoryForMemory::new())
-            .oneshot(req)
-            .await
-            .unwrap();
-        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
-        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
-        let todo: Vec<Todo> = serde_json::from_str(&body)
-            .expect(&format!("cannot convert Todo instance. body: {}", body));
-        assert_eq!(vec![expected], todo);
-    }
+}
 
-    #[tokio::test]
-    async fn should_update_todo() {
-        let expected = Todo::new(1, "should_update_todo".to_string());
+fn init_tracing() {
+    let registry = tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(EnvFilter::from_default_env()));
 
-        let todo_repository = TodoRepositoryForMemory::new();
-        todo_repository
-            .create(CreateTodo::new("before_update_todo".to_string()))
-            .await
-            .expect("failed create todo");
-        let req = build_todo_req_with_json(
-            "/todos/1",
-            Method::PATCH,
-            r#"{
-    "id": 1,
-    "text": "should_update_todo",
-    "completed": false
-}"#
-            .to_string(),
-        );
-        let res = create_app(todo_repository, LabelRepositoryForMemory::new())
-            .oneshot(req)
-            .await
-            .unwrap();
-        let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
+    // the console layer needs tokio's own trace events, so it sits beside the
+    // filtered fmt layer instead of replacing it
+    #[cfg(feature = "console")]
+    if env::var("TOKIO_CONSOLE").as_deref() == Ok("1") {
+        registry.with(console_subscriber::spawn()).init();
+        return;
     }
 
-    #[tokio::test]
-    async fn should_delete_todo() {
-        let todo_repository = TodoRepositoryForMemory::new();
-        todo_repository
-            .create(CreateTodo::new("should_delete_todo".to_string()))
-            .await
-            .expect("failed create todo");
-        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
-        let res = create_app(todo_repository, LabelRepositoryForMemory::new())
-            .oneshot(req)
-            .await
-            .unwrap();
-        assert_eq!(StatusCode::NO_CONTENT, res.status());
-    }
+    registry.init();
 }
@@ -0,0 +1,958 @@
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use axum::body::{self, Body, Full};
+use axum::extract::ConnectInfo;
+use axum::http::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, LINK};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use hmac::{Hmac, Mac};
+use hyper::body::HttpBody;
+use ipnet::IpNet;
+use serde_json::Value;
+use sha2::Sha256;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::config::BodyLogConfig;
+use crate::repositories::deadline::DEADLINE;
+use crate::repositories::todo_repository::{ReadConsistency, TodoRepository, READ_CONSISTENCY};
+
+pub const READ_CONSISTENCY_HEADER: &str = "x-read-consistency";
+pub const CONSISTENCY_TOKEN_HEADER: &str = "x-consistency-token";
+/// Default for [`AppConfig::request_id_header`](crate::config::AppConfig).
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const REQUEST_DEADLINE_HEADER: &str = "x-request-deadline";
+pub const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+pub const FORWARDED_PROTO_HEADER: &str = "x-forwarded-proto";
+
+tokio::task_local! {
+    /// Id of the request being handled, as sent back in the request id header.
+    pub static REQUEST_ID: String;
+}
+
+/// Reuses the caller's id from `header` when it is a sane token, otherwise
+/// generates one; either way it is logged with every event of the request
+/// and returned on the response under the same header.
+pub async fn request_id<B>(
+    header: HeaderName,
+    req: Request<B>,
+    next: Next<B>,
+) -> impl IntoResponse {
+    let id = req
+        .headers()
+        .get(&header)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| {
+            !value.is_empty() && value.len() <= 128 && value.bytes().all(|b| b.is_ascii_graphic())
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        client_ip = tracing::field::Empty
+    );
+    let mut res = REQUEST_ID
+        .scope(id.clone(), next.run(req).instrument(span))
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(header, value);
+    }
+    res
+}
+
+/// The current request id, if called within [`request_id`].
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// The client a request came from, looking through trusted proxies; put in
+/// the request extensions by [`client_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub ip: IpAddr,
+    /// `http` or `https` as the client used it, when a trusted proxy said so.
+    pub scheme: Option<&'static str>,
+}
+
+impl ClientInfo {
+    /// Believes the forwarding headers only when `peer` is one of the
+    /// `trusted` proxies. The client is the rightmost `X-Forwarded-For`
+    /// entry that isn't a trusted proxy itself, since anything left of it
+    /// was written by the client and may be made up. Each proxy appends to
+    /// `X-Forwarded-Proto` as well, so the scheme is the entry the same
+    /// number of hops from the right, or the rightmost one when there are
+    /// fewer.
+    pub fn resolve(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> Self {
+        let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+        if !is_trusted(&peer) {
+            return Self {
+                ip: peer,
+                scheme: None,
+            };
+        }
+
+        let entries = |name| {
+            headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .collect::<Vec<_>>()
+        };
+        let mut ip = peer;
+        let mut hop = 0;
+        for (i, entry) in entries(FORWARDED_FOR_HEADER).into_iter().rev().enumerate() {
+            match entry.trim().parse() {
+                Ok(addr) => (ip, hop) = (addr, i),
+                // the closest hop we could verify is as far as we can see
+                Err(_) => break,
+            }
+            if !is_trusted(&ip) {
+                break;
+            }
+        }
+        // the proxy that saw the client appended its scheme at the same hop;
+        // anything left of that was written by the client
+        let protos = entries(FORWARDED_PROTO_HEADER);
+        let scheme = protos
+            .iter()
+            .rev()
+            .nth(hop)
+            .or_else(|| protos.last())
+            .and_then(|scheme| match scheme.trim().to_ascii_lowercase().as_str() {
+                "http" => Some("http"),
+                "https" => Some("https"),
+                _ => None,
+            });
+        Self { ip, scheme }
+    }
+}
+
+/// Resolves the [`ClientInfo`] of requests served with connect info, and
+/// logs its address with the request.
+pub async fn client_info<B>(
+    trusted: Arc<Vec<IpNet>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> impl IntoResponse {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let Some(peer) = peer {
+        let client = ClientInfo::resolve(peer, req.headers(), &trusted);
+        tracing::Span::current().record("client_ip", tracing::field::display(client.ip));
+        req.extensions_mut().insert(client);
+    }
+    next.run(req).await
+}
+
+/// Signs and checks the write positions handed out in
+/// `X-Consistency-Token`, so clients can't make reads wait on made-up ones.
+#[derive(Clone)]
+pub struct ConsistencyTokens {
+    key: Arc<[u8]>,
+}
+
+impl ConsistencyTokens {
+    pub fn new(key: &[u8]) -> Self {
+        Self { key: key.into() }
+    }
+
+    /// A key only this process knows, for when none is configured.
+    pub fn random() -> Self {
+        let key = [Uuid::new_v4(), Uuid::new_v4()].map(|uuid| uuid.into_bytes());
+        Self::new(&key.concat())
+    }
+
+    fn mac(&self, position: u64) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        mac.update(&position.to_be_bytes());
+        mac
+    }
+
+    pub fn issue(&self, position: u64) -> String {
+        let signature = self.mac(position).finalize().into_bytes();
+        format!("{:x}.{}", position, hex::encode(signature))
+    }
+
+    /// The position `token` was issued for, unless it wasn't issued with
+    /// this key.
+    pub fn verify(&self, token: &str) -> Option<u64> {
+        let (position, signature) = token.split_once('.')?;
+        let position = u64::from_str_radix(position, 16).ok()?;
+        let signature = hex::decode(signature).ok()?;
+        self.mac(position).verify_slice(&signature).ok()?;
+        Some(position)
+    }
+}
+
+/// Lets a client force primary reads (`X-Read-Consistency: primary`) for
+/// read-after-write flows; every other request may be served by a replica.
+///
+/// Successful writes are answered with an `X-Consistency-Token` when reads
+/// may lag behind them; presenting it on later reads guarantees they see
+/// those writes. A token that doesn't verify sends the read to the primary.
+pub async fn read_consistency<B, T: TodoRepository>(
+    tokens: ConsistencyTokens,
+    repository: Arc<T>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let headers = req.headers();
+    let consistency = match (
+        headers.get(READ_CONSISTENCY_HEADER),
+        headers.get(CONSISTENCY_TOKEN_HEADER),
+    ) {
+        (Some(value), _) if value.as_bytes().eq_ignore_ascii_case(b"primary") => {
+            ReadConsistency::Primary
+        }
+        (_, Some(token)) => match token.to_str().ok().and_then(|token| tokens.verify(token)) {
+            Some(position) => ReadConsistency::AtLeast(position),
+            None => {
+                tracing::debug!("invalid consistency token, reading from primary");
+                ReadConsistency::Primary
+            }
+        },
+        _ => ReadConsistency::Eventual,
+    };
+    let writes = !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+
+    let mut res = READ_CONSISTENCY.scope(consistency, next.run(req)).await;
+    if writes && res.status().is_success() {
+        match repository.write_position().await {
+            Ok(Some(position)) => {
+                let token = HeaderValue::from_str(&tokens.issue(position))
+                    .expect("tokens are hex and a dot");
+                res.headers_mut().insert(CONSISTENCY_TOKEN_HEADER, token);
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!("no consistency token, write position unknown: {}", e),
+        }
+    }
+    res
+}
+
+/// Longest budget a request gets; longer ones are cut to it, which keeps the
+/// `statement_timeout` derived from it in range.
+pub const MAX_REQUEST_DEADLINE: Duration = Duration::from_secs(60 * 60);
+
+/// Takes the milliseconds the client is still willing to wait from
+/// `X-Request-Deadline`, or `timeout` when it sends none, so database work is
+/// cancelled once it gives up. The header can shorten `timeout` but not
+/// extend it, and a value that isn't a number of milliseconds is ignored.
+pub async fn request_deadline<B>(
+    timeout: Option<Duration>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let requested = req
+        .headers()
+        .get(REQUEST_DEADLINE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_millis);
+    let budget = match (requested, timeout) {
+        (Some(requested), Some(timeout)) => Some(requested.min(timeout)),
+        (requested, timeout) => requested.or(timeout),
+    };
+    let deadline = budget
+        .map(|budget| budget.min(MAX_REQUEST_DEADLINE))
+        .and_then(|budget| Instant::now().checked_add(budget));
+    match deadline {
+        Some(deadline) => DEADLINE.scope(deadline, next.run(req)).await,
+        None => next.run(req).await,
+    }
+}
+
+/// Inflates gzip and deflate request bodies so extractors see plain JSON.
+/// Answers 413 once a body passes `max_bytes`, compressed or inflated, so a
+/// small zip bomb can't exhaust memory, and 415 naming any other encoding.
+pub async fn decompress_body(max_bytes: usize, req: Request<Body>, next: Next<Body>) -> Response {
+    let encoding = match req.headers().get(CONTENT_ENCODING) {
+        Some(value) => String::from_utf8_lossy(value.as_bytes())
+            .trim()
+            .to_ascii_lowercase(),
+        None => return next.run(req).await,
+    };
+    match encoding.as_str() {
+        "identity" => return next.run(req).await,
+        "gzip" | "x-gzip" | "deflate" => {}
+        _ => {
+            let message = format!("unsupported content encoding `{}`", encoding);
+            return (StatusCode::UNSUPPORTED_MEDIA_TYPE, message).into_response();
+        }
+    }
+    let too_large = || {
+        let message = format!("body is larger than {} bytes decompressed", max_bytes);
+        (StatusCode::PAYLOAD_TOO_LARGE, message).into_response()
+    };
+
+    let (mut parts, mut body) = req.into_parts();
+    let mut compressed = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                let message = format!("failed to read body: {}", e);
+                return (StatusCode::BAD_REQUEST, message).into_response();
+            }
+        };
+        if compressed.len() + chunk.len() > max_bytes {
+            return too_large();
+        }
+        compressed.extend_from_slice(&chunk);
+    }
+    // HTTP's "deflate" is the zlib format
+    let decoder: Box<dyn Read> = match encoding.as_str() {
+        "deflate" => Box::new(ZlibDecoder::new(&compressed[..])),
+        _ => Box::new(GzDecoder::new(&compressed[..])),
+    };
+    let mut inflated = Vec::new();
+    // one byte past the cap is enough to know the body is too large
+    if let Err(e) = decoder
+        .take(max_bytes as u64 + 1)
+        .read_to_end(&mut inflated)
+    {
+        let message = format!("invalid {} body: {}", encoding, e);
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    if inflated.len() > max_bytes {
+        return too_large();
+    }
+    parts.headers.remove(CONTENT_ENCODING);
+    parts
+        .headers
+        .insert(CONTENT_LENGTH, HeaderValue::from(inflated.len()));
+    next.run(Request::from_parts(parts, Body::from(inflated)))
+        .await
+}
+
+/// How often hits on a deprecated route are summarized in the log.
+pub const DEPRECATION_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A route that still works but is going away: responses carry
+/// `Deprecation`, `Sunset` and a `successor-version` link, and hits are
+/// counted so we can tell when it's safe to remove.
+#[derive(Debug, Clone)]
+pub struct Deprecation {
+    /// When the route stops being served.
+    pub sunset: SystemTime,
+    /// Where clients should move to.
+    pub successor: &'static str,
+    hits: Arc<DeprecatedHits>,
+}
+
+#[derive(Debug)]
+struct DeprecatedHits {
+    total: AtomicU64,
+    /// Hits since `last_logged`, which is `None` until the first hit.
+    unlogged: Mutex<(u64, Option<Instant>)>,
+}
+
+impl Deprecation {
+    pub fn new(sunset: SystemTime, successor: &'static str) -> Self {
+        Self {
+            sunset,
+            successor,
+            hits: Arc::new(DeprecatedHits {
+                total: AtomicU64::new(0),
+                unlogged: Mutex::new((0, None)),
+            }),
+        }
+    }
+
+    /// Requests served by the route so far.
+    pub fn hits(&self) -> u64 {
+        self.hits.total.load(Ordering::Relaxed)
+    }
+
+    /// Counts a hit on `route`, logging the hits since the last summary at
+    /// most once per [`DEPRECATION_LOG_INTERVAL`].
+    fn record(&self, route: &str) {
+        self.hits.total.fetch_add(1, Ordering::Relaxed);
+        let mut unlogged = self.hits.unlogged.lock().unwrap();
+        let (count, last_logged) = &mut *unlogged;
+        *count += 1;
+        if last_logged.is_none_or(|at| at.elapsed() >= DEPRECATION_LOG_INTERVAL) {
+            tracing::warn!(
+                route,
+                hits = *count,
+                total = self.hits(),
+                sunset = %httpdate::fmt_http_date(self.sunset),
+                "deprecated route called"
+            );
+            *count = 0;
+            *last_logged = Some(Instant::now());
+        }
+    }
+
+    /// Runs the request, then marks the response as deprecated.
+    pub async fn run<B>(
+        self,
+        route: &'static str,
+        req: Request<B>,
+        next: Next<B>,
+    ) -> impl IntoResponse {
+        self.record(route);
+        let mut res = next.run(req).await;
+        let headers = res.headers_mut();
+        headers.insert("deprecation", HeaderValue::from_static("true"));
+        if let Ok(sunset) = HeaderValue::from_str(&httpdate::fmt_http_date(self.sunset)) {
+            headers.insert("sunset", sunset);
+        }
+        let link = format!("<{}>; rel=\"successor-version\"", self.successor);
+        if let Ok(link) = HeaderValue::from_str(&link) {
+            headers.append(LINK, link);
+        }
+        res
+    }
+}
+
+/// Parts of field names, matched case-insensitively, whose values are never
+/// logged.
+const REDACTED_FIELDS: [&str; 2] = ["password", "token"];
+
+/// Logs JSON request and response bodies at debug level with the request id,
+/// redacted and truncated as configured. Only layered when `LOG_BODIES` is
+/// on, and requests over the per-second cap aren't buffered at all.
+#[derive(Debug, Clone)]
+pub struct BodyLogger {
+    config: BodyLogConfig,
+    /// Start of the current one-second window and the requests logged in it.
+    window: Arc<Mutex<(Instant, u32)>>,
+}
+
+impl BodyLogger {
+    pub fn new(config: BodyLogConfig) -> Self {
+        Self {
+            config,
+            window: Arc::new(Mutex::new((Instant::now(), 0))),
+        }
+    }
+
+    /// Whether this request's bodies fit in the current second's budget.
+    fn admit(&self) -> bool {
+        let mut window = self.window.lock().unwrap();
+        let (started, logged) = &mut *window;
+        if started.elapsed() >= Duration::from_secs(1) {
+            *started = Instant::now();
+            *logged = 0;
+        }
+        if *logged >= self.config.per_second {
+            return false;
+        }
+        *logged += 1;
+        true
+    }
+
+    pub async fn run(self, req: Request<Body>, next: Next<Body>) -> Response {
+        if !self.admit() {
+            return next.run(req).await;
+        }
+        let request_id = current_request_id().unwrap_or_default();
+
+        let (parts, req_body) = req.into_parts();
+        let req_body = if is_json(&parts.headers) {
+            let bytes = match hyper::body::to_bytes(req_body).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    let message = format!("failed to read body: {}", e);
+                    return (StatusCode::BAD_REQUEST, message).into_response();
+                }
+            };
+            tracing::debug!(
+                request_id = %request_id,
+                method = %parts.method,
+                uri = %parts.uri,
+                body = %render_body(&bytes, self.config.max_bytes),
+                "request body"
+            );
+            Body::from(bytes)
+        } else {
+            req_body
+        };
+
+        let res = next.run(Request::from_parts(parts, req_body)).await;
+        if !is_json(res.headers()) {
+            return res;
+        }
+        let (parts, res_body) = res.into_parts();
+        let bytes = match hyper::body::to_bytes(res_body).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                tracing::warn!("failed to read response body: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        tracing::debug!(
+            request_id = %request_id,
+            status = %parts.status,
+            body = %render_body(&bytes, self.config.max_bytes),
+            "response body"
+        );
+        Response::from_parts(parts, body::boxed(Full::from(bytes)))
+    }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .is_some_and(|mime| mime.subtype() == mime::JSON || mime.suffix() == Some(mime::JSON))
+}
+
+/// Masks the value of every field named like a secret, at any depth.
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                let name = name.to_lowercase();
+                if REDACTED_FIELDS.iter().any(|secret| name.contains(secret)) {
+                    *field = Value::String("***".to_string());
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// A body as it is logged: redacted, then cut to `max_bytes` on a character
+/// boundary. A body that isn't JSON can't be redacted, so only its size is
+/// logged.
+fn render_body(bytes: &[u8], max_bytes: usize) -> String {
+    if bytes.is_empty() {
+        return String::new();
+    }
+    let mut value = match serde_json::from_slice::<Value>(bytes) {
+        Ok(value) => value,
+        Err(_) => return format!("<{} bytes, not JSON>", bytes.len()),
+    };
+    redact(&mut value);
+    let mut rendered = value.to_string();
+    if rendered.len() > max_bytes {
+        let total = rendered.len();
+        let mut end = max_bytes;
+        while !rendered.is_char_boundary(end) {
+            end -= 1;
+        }
+        rendered.truncate(end);
+        rendered.push_str(&format!("...<truncated, {} bytes>", total));
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use crate::repositories::deadline;
+    use crate::repositories::todo_repository::test_utils::TodoRepositoryForMemory;
+
+    use super::*;
+
+    async fn current() -> String {
+        let consistency = READ_CONSISTENCY.with(|consistency| *consistency);
+        format!("{:?}", consistency)
+    }
+
+    async fn remaining_budget() -> String {
+        match deadline::remaining() {
+            Some(remaining) => remaining.as_secs().to_string(),
+            None => "none".to_string(),
+        }
+    }
+
+    async fn echo_request_id() -> String {
+        current_request_id().unwrap_or_default()
+    }
+
+    async fn send(req: Request<Body>) -> String {
+        let tokens = ConsistencyTokens::new(b"secret");
+        let repository = Arc::new(TodoRepositoryForMemory::new());
+        let app = Router::new()
+            .route("/", get(current))
+            .layer(middleware::from_fn(move |req, next| {
+                read_consistency(tokens.clone(), repository.clone(), req, next)
+            }));
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn header_selects_read_consistency() {
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        assert_eq!("Eventual", send(req).await);
+
+        let req = Request::builder()
+            .uri("/")
+            .header(READ_CONSISTENCY_HEADER, "primary")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!("Primary", send(req).await);
+
+        let token = ConsistencyTokens::new(b"secret").issue(42);
+        let req = Request::builder()
+            .uri("/")
+            .header(CONSISTENCY_TOKEN_HEADER, &token)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!("AtLeast(42)", send(req).await);
+
+        // asking for the primary outright wins over a token
+        let req = Request::builder()
+            .uri("/")
+            .header(CONSISTENCY_TOKEN_HEADER, &token)
+            .header(READ_CONSISTENCY_HEADER, "primary")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!("Primary", send(req).await);
+
+        // a token that doesn't verify can't be trusted to be old enough
+        let forged = ConsistencyTokens::new(b"guess").issue(42);
+        let req = Request::builder()
+            .uri("/")
+            .header(CONSISTENCY_TOKEN_HEADER, forged)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!("Primary", send(req).await);
+    }
+
+    #[test]
+    fn consistency_tokens_only_verify_with_their_key() {
+        let tokens = ConsistencyTokens::new(b"secret");
+        let token = tokens.issue(0x1_6b37_4d48);
+        assert_eq!(Some(0x1_6b37_4d48), tokens.verify(&token));
+
+        assert_eq!(None, ConsistencyTokens::new(b"other").verify(&token));
+        assert_eq!(None, ConsistencyTokens::random().verify(&token));
+        let (_, signature) = token.split_once('.').unwrap();
+        let moved = format!("{:x}.{}", 0x1_6b37_4d49_u64, signature);
+        assert_eq!(None, tokens.verify(&moved));
+        assert_eq!(None, tokens.verify("garbage"));
+        assert_eq!(None, tokens.verify(""));
+    }
+
+    #[tokio::test]
+    async fn request_id_is_propagated_or_generated() {
+        let app = Router::new()
+            .route("/", get(echo_request_id))
+            .layer(middleware::from_fn(|req, next| {
+                request_id(HeaderName::from_static(REQUEST_ID_HEADER), req, next)
+            }));
+
+        let req = Request::builder()
+            .uri("/")
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!("abc-123", res.headers()[REQUEST_ID_HEADER]);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&b"abc-123"[..], &bytes[..]);
+
+        let req = Request::builder()
+            .uri("/")
+            .header(REQUEST_ID_HEADER, "has spaces")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let generated = res.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert!(Uuid::parse_str(generated).is_ok());
+    }
+
+    #[tokio::test]
+    async fn header_sets_request_deadline() {
+        for (timeout, header, expected) in [
+            (None, Some("500"), "0"),
+            (None, Some("soon"), "none"),
+            (None, Some("-500"), "none"),
+            (None, None, "none"),
+            // past what statement_timeout accepts, and past u64 nanoseconds
+            (None, Some("2147483648"), "3599"),
+            (None, Some("18446744073709551615"), "3599"),
+            (Some(2), None, "1"),
+            (Some(2), Some("500"), "0"),
+            (Some(2), Some("60000"), "1"),
+            (Some(2), Some("soon"), "1"),
+        ] {
+            let timeout = timeout.map(Duration::from_secs);
+            let app = Router::new()
+                .route("/", get(remaining_budget))
+                .layer(middleware::from_fn(move |req, next| {
+                    request_deadline(timeout, req, next)
+                }));
+            let mut req = Request::builder().uri("/");
+            if let Some(header) = header {
+                req = req.header(REQUEST_DEADLINE_HEADER, header);
+            }
+            let res = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(expected.as_bytes(), &bytes[..], "{:?}", header);
+        }
+    }
+
+    fn forwarded(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    #[test]
+    fn client_info_ignores_headers_from_untrusted_peers() {
+        let trusted = vec!["10.0.0.0/8".parse().unwrap()];
+        let headers = forwarded(&[
+            (FORWARDED_FOR_HEADER, "1.2.3.4"),
+            (FORWARDED_PROTO_HEADER, "https"),
+        ]);
+        let peer = "203.0.113.9".parse().unwrap();
+        assert_eq!(
+            ClientInfo {
+                ip: peer,
+                scheme: None
+            },
+            ClientInfo::resolve(peer, &headers, &trusted)
+        );
+    }
+
+    #[test]
+    fn client_info_uses_forwarded_headers_from_trusted_peers() {
+        let trusted = vec!["10.0.0.0/8".parse().unwrap()];
+        let peer = "10.0.0.1".parse().unwrap();
+        let headers = forwarded(&[
+            (FORWARDED_FOR_HEADER, "198.51.100.7"),
+            (FORWARDED_PROTO_HEADER, "HTTPS"),
+        ]);
+        assert_eq!(
+            ClientInfo {
+                ip: "198.51.100.7".parse().unwrap(),
+                scheme: Some("https")
+            },
+            ClientInfo::resolve(peer, &headers, &trusted)
+        );
+        // nothing forwarded: the proxy itself is the client
+        assert_eq!(
+            ClientInfo {
+                ip: peer,
+                scheme: None
+            },
+            ClientInfo::resolve(peer, &HeaderMap::new(), &trusted)
+        );
+    }
+
+    #[test]
+    fn client_info_takes_rightmost_untrusted_hop_of_a_chain() {
+        let trusted = vec!["10.0.0.0/8".parse().unwrap()];
+        let peer = "10.0.0.1".parse().unwrap();
+        // the client claimed 6.6.6.6; our two proxies appended the rest
+        let headers = forwarded(&[
+            (FORWARDED_FOR_HEADER, "6.6.6.6, 198.51.100.7"),
+            (FORWARDED_FOR_HEADER, "10.1.2.3"),
+            (FORWARDED_PROTO_HEADER, "https, http"),
+        ]);
+        assert_eq!(
+            ClientInfo {
+                ip: "198.51.100.7".parse().unwrap(),
+                scheme: Some("https")
+            },
+            ClientInfo::resolve(peer, &headers, &trusted)
+        );
+
+        // a scheme the client made up is left of the one its proxy appended
+        let headers = forwarded(&[
+            (FORWARDED_FOR_HEADER, "6.6.6.6, 198.51.100.7, 10.1.2.3"),
+            (FORWARDED_PROTO_HEADER, "https, http"),
+            (FORWARDED_PROTO_HEADER, "https"),
+        ]);
+        assert_eq!(
+            ClientInfo {
+                ip: "198.51.100.7".parse().unwrap(),
+                scheme: Some("http")
+            },
+            ClientInfo::resolve(peer, &headers, &trusted)
+        );
+        // proxies that don't append a scheme: take the closest one
+        let headers = forwarded(&[
+            (FORWARDED_FOR_HEADER, "198.51.100.7, 10.1.2.3"),
+            (FORWARDED_PROTO_HEADER, "https"),
+        ]);
+        assert_eq!(
+            Some("https"),
+            ClientInfo::resolve(peer, &headers, &trusted).scheme
+        );
+
+        // a garbled hop stops the walk at the last address we could read
+        let headers = forwarded(&[(FORWARDED_FOR_HEADER, "198.51.100.7, unknown, 10.1.2.3")]);
+        let client = ClientInfo::resolve(peer, &headers, &trusted);
+        assert_eq!("10.1.2.3".parse::<IpAddr>().unwrap(), client.ip);
+    }
+
+    #[tokio::test]
+    async fn client_info_is_put_in_extensions() {
+        async fn client(client: Option<axum::extract::Extension<ClientInfo>>) -> String {
+            client.map_or("none".to_string(), |client| client.ip.to_string())
+        }
+        let trusted = Arc::new(vec!["127.0.0.1/32".parse().unwrap()]);
+        let app = Router::new()
+            .route("/", get(client))
+            .layer(middleware::from_fn(move |req, next| {
+                client_info(trusted.clone(), req, next)
+            }));
+
+        let mut req = Request::builder()
+            .uri("/")
+            .header(FORWARDED_FOR_HEADER, "198.51.100.7")
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 50000))));
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&b"198.51.100.7"[..], &bytes[..]);
+
+        // served without connect info, e.g. in process
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&b"none"[..], &bytes[..]);
+    }
+
+    #[test]
+    fn redact_masks_secret_fields_at_any_depth() {
+        let mut value = serde_json::json!({
+            "user": "ai",
+            "Password": "hunter2",
+            "session": { "access_token": "abc", "expires": 60 },
+            "items": [{ "refreshToken": { "nested": "def" } }, "password"],
+        });
+        redact(&mut value);
+        assert_eq!(
+            serde_json::json!({
+                "user": "ai",
+                "Password": "***",
+                "session": { "access_token": "***", "expires": 60 },
+                "items": [{ "refreshToken": "***" }, "password"],
+            }),
+            value
+        );
+    }
+
+    #[test]
+    fn render_body_redacts_before_truncating() {
+        let body = br#"{"a_token":"secret-value-that-is-long","text":"\u65e5\u672c\u8a9e"}"#;
+        let rendered = render_body(body, 1024);
+        assert_eq!(r#"{"a_token":"***","text":"日本語"}"#, rendered);
+
+        // cut inside the multi-byte text, never inside a character
+        let rendered = render_body(body, 30);
+        assert_eq!(
+            r#"{"a_token":"***","text":"日"#.to_string() + "...<truncated, 36 bytes>",
+            rendered
+        );
+        assert!(!render_body(body, 0).contains("secret"));
+    }
+
+    #[test]
+    fn render_body_does_not_log_what_it_cannot_redact() {
+        let rendered = render_body(b"password=hunter2", 1024);
+        assert_eq!("<16 bytes, not JSON>", rendered);
+        assert_eq!("", render_body(b"", 1024));
+    }
+
+    #[tokio::test]
+    async fn decompress_body_inflates_deflate_and_names_unknown_encodings() {
+        use std::io::Write;
+
+        use flate2::{write::ZlibEncoder, Compression};
+
+        let app = Router::new()
+            .route("/", axum::routing::post(|body: String| async move { body }))
+            .layer(middleware::from_fn(|req, next| {
+                decompress_body(64, req, next)
+            }));
+        let req = |encoding: &str, body: Vec<u8>| {
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header(CONTENT_ENCODING, encoding)
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"inflated").unwrap();
+        let res = app
+            .clone()
+            .oneshot(req("deflate", encoder.finish().unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&b"inflated"[..], &bytes[..]);
+
+        let res = app
+            .clone()
+            .oneshot(req("deflate", b"not deflate".to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        let res = app.oneshot(req("br", Vec::new())).await.unwrap();
+        assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&b"unsupported content encoding `br`"[..], &bytes[..]);
+    }
+
+    #[tokio::test]
+    async fn body_logger_passes_bodies_through() {
+        let logger = BodyLogger::new(BodyLogConfig {
+            max_bytes: 8,
+            per_second: 1,
+        });
+        let app = Router::new()
+            .route(
+                "/",
+                axum::routing::post(|body: axum::Json<Value>| async move { body }),
+            )
+            .layer(middleware::from_fn({
+                let logger = logger.clone();
+                move |req, next| logger.clone().run(req, next)
+            }));
+
+        for _ in 0..2 {
+            let req = Request::builder()
+                .method("POST")
+                .uri("/")
+                .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(r#"{"password":"hunter2","text":"long enough"}"#))
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(
+                serde_json::json!({ "password": "hunter2", "text": "long enough" }),
+                body
+            );
+        }
+        // the first request used up this second's budget
+        assert!(!logger.admit());
+    }
+}
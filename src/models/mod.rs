@@ -1,2 +0,0 @@
-pub mod label;
-pub mod todo;
@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use sqlx::{Executor, FromRow, PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::startup;
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+struct Column {
+    table_name: String,
+    column_name: String,
+    data_type: String,
+    is_nullable: String,
+}
+
+impl Column {
+    fn describe(&self) -> String {
+        match self.is_nullable.as_str() {
+            "NO" => format!("{} not null", self.data_type),
+            _ => self.data_type.clone(),
+        }
+    }
+}
+
+/// Tables, columns and indexes of one schema.
+#[derive(Debug, Default)]
+struct Schema {
+    /// table -> column -> description
+    columns: BTreeMap<String, BTreeMap<String, String>>,
+    /// `(table, index)`
+    indexes: BTreeSet<(String, String)>,
+}
+
+impl Schema {
+    async fn load(conn: &mut PgConnection, schema: &str) -> anyhow::Result<Self> {
+        let columns = sqlx::query_as::<_, Column>(
+            r#"
+            SELECT table_name::TEXT, column_name::TEXT, data_type::TEXT, is_nullable::TEXT
+            FROM information_schema.columns
+            WHERE table_schema = $1
+            "#,
+        )
+        .bind(schema)
+        .fetch_all(&mut *conn)
+        .await?;
+        let indexes = sqlx::query_as::<_, (String, String)>(
+            r#"
+            SELECT tablename::TEXT, indexname::TEXT FROM pg_indexes WHERE schemaname = $1
+            "#,
+        )
+        .bind(schema)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let mut loaded = Schema {
+            indexes: indexes.into_iter().collect(),
+            ..Schema::default()
+        };
+        for column in columns {
+            loaded
+                .columns
+                .entry(column.table_name.clone())
+                .or_default()
+                .insert(column.column_name.clone(), column.describe());
+        }
+        Ok(loaded)
+    }
+}
+
+/// What `manpuku check` found; empty when the database matches the build.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    pub problems: Vec<String>,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_ok() {
+            return writeln!(f, "ok: database matches the embedded migrations");
+        }
+        for problem in &self.problems {
+            writeln!(f, "error: {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// The schema the embedded migrations produce, built in a scratch schema
+/// inside a transaction that is rolled back, so nothing is applied.
+async fn expected_schema(pool: &PgPool) -> anyhow::Result<Schema> {
+    let scratch = format!("preflight_{}", Uuid::new_v4().simple());
+    let mut tx = pool.begin().await?;
+    tx.execute(format!("CREATE SCHEMA {}", scratch).as_str())
+        .await?;
+    // `public` last, for extensions the database already has installed there
+    tx.execute(format!("SET LOCAL search_path TO {}, public", scratch).as_str())
+        .await?;
+    for migration in sqlx::migrate!().iter() {
+        tx.execute(&*migration.sql).await?;
+    }
+    let schema = Schema::load(&mut tx, &scratch).await?;
+    tx.rollback().await?;
+    Ok(schema)
+}
+
+/// Compares the database behind `pool` with the embedded migrations: every
+/// migration applied, and every expected table, column and index present.
+/// Read-only, so it is safe to run against production before a rollout.
+pub async fn check(pool: &PgPool) -> anyhow::Result<CheckReport> {
+    let mut report = CheckReport::default();
+    // "unknown" means the migrations aren't tracked here; the schema
+    // comparison below still tells whether the database is usable
+    let migrations = startup::migration_status(pool).await;
+    if migrations.ends_with("pending") {
+        report.problems.push(format!("migrations: {}", migrations));
+    }
+
+    let expected = expected_schema(pool).await?;
+    let mut conn = pool.acquire().await?;
+    let current: String = sqlx::query_scalar("SELECT current_schema()::TEXT")
+        .fetch_one(&mut conn)
+        .await?;
+    let actual = Schema::load(&mut conn, &current).await?;
+
+    for (table, columns) in &expected.columns {
+        let actual_columns = match actual.columns.get(table) {
+            Some(actual_columns) => actual_columns,
+            None => {
+                report.problems.push(format!("missing table {}", table));
+                continue;
+            }
+        };
+        for (column, expected_type) in columns {
+            match actual_columns.get(column) {
+                None => report
+                    .problems
+                    .push(format!("missing column {}.{}", table, column)),
+                Some(actual_type) if actual_type != expected_type => report.problems.push(format!(
+                    "column {}.{} is {}, expected {}",
+                    table, column, actual_type, expected_type
+                )),
+                Some(_) => {}
+            }
+        }
+    }
+    for (table, index) in expected.indexes.difference(&actual.indexes) {
+        if actual.columns.contains_key(table) {
+            report
+                .problems
+                .push(format!("missing index {} on {}", index, table));
+        }
+    }
+    Ok(report)
+}
+
+/// Runs `manpuku check`: prints the report and returns whether it passed.
+pub async fn run(config: &AppConfig) -> bool {
+    let pool = match config
+        .pool
+        .options()
+        .connect(config.database_url.expose())
+        .await
+    {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("error: cannot connect to [{}]: {}", config.database_url, e);
+            return false;
+        }
+    };
+    match check(&pool).await {
+        Ok(report) => {
+            print!("{}", report);
+            report.is_ok()
+        }
+        Err(e) => {
+            eprintln!("error: check failed: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::str::FromStr;
+
+    use dotenv::dotenv;
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn check_detects_dropped_column() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let admin = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let name = format!("preflight_test_{}", Uuid::new_v4().simple());
+        admin
+            .execute(format!("CREATE DATABASE {}", name).as_str())
+            .await
+            .expect("failed to create database");
+
+        let options = PgConnectOptions::from_str(database_url)
+            .unwrap()
+            .database(&name);
+        let pool = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await
+            .expect("failed to connect temp database");
+
+        let report = check(&pool).await.unwrap();
+        assert_eq!(
+            vec![
+                "missing table labels".to_string(),
+                "missing table preferences".to_string(),
+                "missing table todo_collection_version".to_string(),
+                "missing table todo_dependencies".to_string(),
+                "missing table todo_labels".to_string(),
+                "missing table todo_tombstones".to_string(),
+                "missing table todos".to_string()
+            ],
+            report.problems
+        );
+
+        sqlx::migrate!()
+            .run(&pool)
+            .await
+            .expect("failed to migrate");
+        let report = check(&pool).await.unwrap();
+        assert!(report.is_ok(), "{}", report);
+
+        pool.execute("ALTER TABLE todos DROP COLUMN completed")
+            .await
+            .unwrap();
+        let report = check(&pool).await.unwrap();
+        assert_eq!(
+            vec!["missing column todos.completed".to_string()],
+            report.problems
+        );
+
+        pool.close().await;
+        admin
+            .execute(format!("DROP DATABASE {}", name).as_str())
+            .await
+            .expect("failed to drop database");
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use axum::async_trait;
+use futures_util::stream::BoxStream;
+use tokio::sync::broadcast;
+
+use crate::models::id::{LabelId, TodoId};
+use crate::models::todo::{
+    CreateTodo, Todo, TodoChangeEntry, TodoChanges, TodoDependencies, TodoListParams, TodoSync,
+    UpdateTodo, UpdatedTodo,
+};
+use crate::repositories::todo_repository::{
+    PoolUsage, RepairReport, TodoRepository, TodoUnitOfWork,
+};
+
+/// Changes kept for slow subscribers before they start lagging.
+const CHANGE_FEED_CAPACITY: usize = 64;
+/// Changes kept for long polling clients to catch up on by cursor.
+const CHANGE_LOG_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TodoChange {
+    Created(Todo),
+    Updated(Todo),
+    Deleted(TodoId),
+}
+
+impl TodoChange {
+    pub fn id(&self) -> TodoId {
+        match self {
+            TodoChange::Created(todo) | TodoChange::Updated(todo) => todo.id,
+            TodoChange::Deleted(id) => *id,
+        }
+    }
+}
+
+impl From<TodoChange> for TodoChangeEntry {
+    fn from(change: TodoChange) -> Self {
+        match change {
+            TodoChange::Created(todo) => TodoChangeEntry::Created { todo },
+            TodoChange::Updated(todo) => TodoChangeEntry::Updated { todo },
+            TodoChange::Deleted(id) => TodoChangeEntry::Deleted { id },
+        }
+    }
+}
+
+/// The latest changes, numbered from 1 in publish order.
+#[derive(Debug, Default)]
+struct ChangeLog {
+    last: u64,
+    entries: VecDeque<(u64, TodoChange)>,
+}
+
+/// Broadcasts every successful todo mutation to whoever is listening, and
+/// keeps the latest ones so pollers can ask for what they missed.
+#[derive(Debug, Clone)]
+pub struct ChangeFeed {
+    sender: broadcast::Sender<TodoChange>,
+    log: Arc<Mutex<ChangeLog>>,
+}
+
+impl Default for ChangeFeed {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CHANGE_FEED_CAPACITY);
+        Self {
+            sender,
+            log: Arc::default(),
+        }
+    }
+}
+
+impl ChangeFeed {
+    pub fn subscribe(&self) -> broadcast::Receiver<TodoChange> {
+        self.sender.subscribe()
+    }
+
+    /// Number of the latest change, 0 before the first.
+    pub fn cursor(&self) -> u64 {
+        self.log.lock().unwrap().last
+    }
+
+    /// Changes after `cursor`, or `None` when some of them are no longer
+    /// kept or `cursor` is from before a restart, so the caller must reload.
+    pub fn since(&self, cursor: u64) -> Option<TodoChanges> {
+        let log = self.log.lock().unwrap();
+        let oldest = log.entries.front().map_or(log.last + 1, |(seq, _)| *seq);
+        if cursor > log.last || cursor + 1 < oldest {
+            return None;
+        }
+        let changes = log
+            .entries
+            .iter()
+            .filter(|(seq, _)| *seq > cursor)
+            .map(|(_, change)| change.clone().into())
+            .collect();
+        Some(TodoChanges {
+            cursor: log.last,
+            changes,
+        })
+    }
+
+    fn publish(&self, change: TodoChange) {
+        // numbered and sent under one lock, so pollers see the log in order
+        let mut log = self.log.lock().unwrap();
+        log.last += 1;
+        let seq = log.last;
+        log.entries.push_back((seq, change.clone()));
+        if log.entries.len() > CHANGE_LOG_CAPACITY {
+            log.entries.pop_front();
+        }
+        // no subscribers is not an error
+        let _ = self.sender.send(change);
+    }
+}
+
+/// Publishes the mutations of the wrapped repository to a [`ChangeFeed`].
+#[derive(Debug, Clone)]
+pub struct Notifying<R> {
+    inner: R,
+    feed: ChangeFeed,
+}
+
+impl<R> Notifying<R> {
+    pub fn new(inner: R, feed: ChangeFeed) -> Self {
+        Self { inner, feed }
+    }
+}
+
+/// Publishes the steps of the wrapped unit of work once it commits, a todo
+/// created and then changed within it as a single creation.
+struct NotifyingUnitOfWork {
+    inner: Box<dyn TodoUnitOfWork>,
+    feed: ChangeFeed,
+    changes: Vec<TodoChange>,
+}
+
+impl NotifyingUnitOfWork {
+    fn record(&mut self, todo: &Todo) {
+        let id = todo.id;
+        match self.changes.iter_mut().find(|change| change.id() == id) {
+            Some(TodoChange::Created(created)) => *created = todo.clone(),
+            _ => self.changes.push(TodoChange::Updated(todo.clone())),
+        }
+    }
+}
+
+#[async_trait]
+impl TodoUnitOfWork for NotifyingUnitOfWork {
+    async fn create(&mut self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let todo = self.inner.create(payload).await?;
+        self.changes.push(TodoChange::Created(todo.clone()));
+        Ok(todo)
+    }
+
+    async fn set_labels(&mut self, id: TodoId, label_ids: Vec<LabelId>) -> anyhow::Result<Todo> {
+        let todo = self.inner.set_labels(id, label_ids).await?;
+        self.record(&todo);
+        Ok(todo)
+    }
+
+    async fn commit(self: Box<Self>) -> anyhow::Result<()> {
+        self.inner.commit().await?;
+        for change in self.changes {
+            self.feed.publish(change);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<R: TodoRepository> TodoRepository for Notifying<R> {
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let todo = self.inner.create(payload).await?;
+        self.feed.publish(TodoChange::Created(todo.clone()));
+        Ok(todo)
+    }
+
+    async fn begin(&self) -> anyhow::Result<Box<dyn TodoUnitOfWork>> {
+        Ok(Box::new(NotifyingUnitOfWork {
+            inner: self.inner.begin().await?,
+            feed: self.feed.clone(),
+            changes: Vec::new(),
+        }))
+    }
+
+    async fn find(&self, id: TodoId) -> anyhow::Result<Todo> {
+        self.inner.find(id).await
+    }
+
+    async fn count(&self) -> anyhow::Result<i64> {
+        self.inner.count().await
+    }
+
+    async fn existing(&self, ids: &[TodoId]) -> anyhow::Result<BTreeSet<TodoId>> {
+        self.inner.existing(ids).await
+    }
+
+    async fn dependencies(&self, id: TodoId) -> anyhow::Result<TodoDependencies> {
+        self.inner.dependencies(id).await
+    }
+
+    async fn open_dependencies(&self, id: TodoId) -> anyhow::Result<Vec<TodoId>> {
+        self.inner.open_dependencies(id).await
+    }
+
+    async fn add_dependency(
+        &self,
+        id: TodoId,
+        depends_on: TodoId,
+    ) -> anyhow::Result<TodoDependencies> {
+        self.inner.add_dependency(id, depends_on).await
+    }
+
+    async fn remove_dependency(
+        &self,
+        id: TodoId,
+        depends_on: TodoId,
+    ) -> anyhow::Result<TodoDependencies> {
+        self.inner.remove_dependency(id, depends_on).await
+    }
+
+    async fn find_by_external_id(&self, external_id: &str) -> anyhow::Result<Todo> {
+        self.inner.find_by_external_id(external_id).await
+    }
+
+    async fn random(&self, label: Option<LabelId>) -> anyhow::Result<Option<Todo>> {
+        self.inner.random(label).await
+    }
+
+    async fn all(&self, params: TodoListParams) -> anyhow::Result<Vec<Todo>> {
+        self.inner.all(params).await
+    }
+
+    fn stream(&self, params: TodoListParams) -> BoxStream<'static, anyhow::Result<Todo>> {
+        self.inner.stream(params)
+    }
+
+    async fn sync(&self, since: Option<u64>) -> anyhow::Result<TodoSync> {
+        self.inner.sync(since).await
+    }
+
+    async fn version(&self) -> anyhow::Result<u64> {
+        self.inner.version().await
+    }
+
+    async fn seq(&self, id: TodoId) -> anyhow::Result<u64> {
+        self.inner.seq(id).await
+    }
+
+    async fn write_position(&self) -> anyhow::Result<Option<u64>> {
+        self.inner.write_position().await
+    }
+
+    async fn update(&self, id: TodoId, payload: UpdateTodo) -> anyhow::Result<UpdatedTodo> {
+        let updated = self.inner.update(id, payload).await?;
+        if !updated.changed_fields.is_empty() {
+            self.feed.publish(TodoChange::Updated(updated.todo.clone()));
+        }
+        Ok(updated)
+    }
+
+    async fn set_pinned(&self, id: TodoId, pinned: bool) -> anyhow::Result<Todo> {
+        let todo = self.inner.set_pinned(id, pinned).await?;
+        self.feed.publish(TodoChange::Updated(todo.clone()));
+        Ok(todo)
+    }
+
+    async fn reset(&self, id: TodoId) -> anyhow::Result<Todo> {
+        let todo = self.inner.reset(id).await?;
+        self.feed.publish(TodoChange::Updated(todo.clone()));
+        Ok(todo)
+    }
+
+    async fn set_labels(&self, id: TodoId, label_ids: Vec<LabelId>) -> anyhow::Result<Todo> {
+        let todo = self.inner.set_labels(id, label_ids).await?;
+        self.feed.publish(TodoChange::Updated(todo.clone()));
+        Ok(todo)
+    }
+
+    async fn move_label(&self, from: LabelId, to: LabelId) -> anyhow::Result<Vec<Todo>> {
+        let todos = self.inner.move_label(from, to).await?;
+        for todo in &todos {
+            self.feed.publish(TodoChange::Updated(todo.clone()));
+        }
+        Ok(todos)
+    }
+
+    async fn delete(&self, id: TodoId) -> anyhow::Result<()> {
+        self.inner.delete(id).await?;
+        self.feed.publish(TodoChange::Deleted(id));
+        Ok(())
+    }
+
+    async fn ping(&self, write: bool) -> anyhow::Result<()> {
+        self.inner.ping(write).await
+    }
+
+    fn pool_usage(&self) -> Option<PoolUsage> {
+        self.inner.pool_usage()
+    }
+
+    async fn repair(&self, dry_run: bool) -> anyhow::Result<RepairReport> {
+        self.inner.repair(dry_run).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::repositories::todo_repository::test_utils::TodoRepositoryForMemory;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn publishes_successful_mutations() {
+        let feed = ChangeFeed::default();
+        let mut changes = feed.subscribe();
+        let repository = Notifying::new(TodoRepositoryForMemory::new(), feed);
+
+        let todo = repository
+            .create(CreateTodo::new("todo text".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            TodoChange::Created(todo.clone()),
+            changes.recv().await.unwrap()
+        );
+
+        let todo = repository
+            .update(
+                todo.id,
+                UpdateTodo {
+                    text: None,
+                    completed: Some(true),
+                    external_id: None,
+                },
+            )
+            .await
+            .unwrap()
+            .todo;
+        assert_eq!(
+            TodoChange::Updated(todo.clone()),
+            changes.recv().await.unwrap()
+        );
+        // an update that changes nothing isn't a change
+        let unchanged = UpdateTodo {
+            text: None,
+            completed: Some(true),
+            external_id: None,
+        };
+        repository.update(todo.id, unchanged).await.unwrap();
+
+        // failures publish nothing
+        assert!(repository.delete(TodoId::new(999).unwrap()).await.is_err());
+        repository.delete(todo.id).await.unwrap();
+        assert_eq!(TodoChange::Deleted(todo.id), changes.recv().await.unwrap());
+    }
+
+    #[test]
+    fn since_returns_changes_after_cursor() {
+        let feed = ChangeFeed::default();
+        assert_eq!(0, feed.cursor());
+        let ids = (1..=3)
+            .map(|id| TodoId::new(id).unwrap())
+            .collect::<Vec<_>>();
+        for id in &ids {
+            feed.publish(TodoChange::Deleted(*id));
+        }
+
+        let changes = feed.since(1).unwrap();
+        assert_eq!(3, changes.cursor);
+        assert_eq!(
+            vec![
+                TodoChangeEntry::Deleted { id: ids[1] },
+                TodoChangeEntry::Deleted { id: ids[2] }
+            ],
+            changes.changes
+        );
+        assert!(feed.since(3).unwrap().changes.is_empty());
+        // from another process, or never handed out
+        assert_eq!(None, feed.since(4));
+
+        for _ in 0..CHANGE_LOG_CAPACITY {
+            feed.publish(TodoChange::Deleted(ids[0]));
+        }
+        assert_eq!(None, feed.since(2));
+        assert_eq!(CHANGE_LOG_CAPACITY, feed.since(3).unwrap().changes.len());
+    }
+}
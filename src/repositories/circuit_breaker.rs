@@ -0,0 +1,654 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use thiserror::Error;
+
+use crate::models::id::{LabelId, TodoId};
+use crate::models::label::{BulkLabel, Label, LabelListParams};
+use crate::models::preferences::{Preferences, StoredPreferences};
+use crate::models::todo::{
+    CreateTodo, Todo, TodoDependencies, TodoListParams, TodoSync, UpdateTodo, UpdatedTodo,
+};
+use crate::repositories::label_repository::LabelRepository;
+use crate::repositories::preferences_repository::PreferencesRepository;
+use crate::repositories::todo_repository::{
+    PoolUsage, RepairReport, TodoRepository, TodoUnitOfWork,
+};
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_COOL_DOWN_SECS: u64 = 30;
+
+#[derive(Debug, Error)]
+#[error("database circuit is open, retry after {}s", retry_after.as_secs())]
+pub struct CircuitOpen {
+    pub retry_after: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    pub failure_threshold: u32,
+    pub cool_down: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            cool_down: Duration::from_secs(DEFAULT_COOL_DOWN_SECS),
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    /// Reads `DB_CIRCUIT_BREAKER_THRESHOLD` and `DB_CIRCUIT_BREAKER_COOL_DOWN_SECS`.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let failure_threshold = env::var("DB_CIRCUIT_BREAKER_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default.failure_threshold);
+        let cool_down = env::var("DB_CIRCUIT_BREAKER_COOL_DOWN_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(default.cool_down);
+        Self {
+            failure_threshold,
+            cool_down,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { probe_started: Instant },
+}
+
+impl CircuitState {
+    fn gauge(&self) -> f64 {
+        match self {
+            CircuitState::Closed { .. } => 0.0,
+            CircuitState::Open { .. } => 1.0,
+            CircuitState::HalfOpen { .. } => 2.0,
+        }
+    }
+}
+
+/// Shared breaker state, cloned into every repository talking to the same database.
+#[derive(Debug, Clone)]
+pub struct Breaker {
+    config: CircuitBreakerConfig,
+    state: Arc<Mutex<CircuitState>>,
+}
+
+impl Breaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: Arc::new(Mutex::new(CircuitState::Closed { failures: 0 })),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn state(&self) -> CircuitState {
+        *self.state.lock().unwrap()
+    }
+
+    pub async fn call<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: Future<Output = anyhow::Result<T>>,
+    {
+        self.acquire()?;
+        let res = f.await;
+        match &res {
+            Err(e) if is_connection_error(e) => self.on_failure(),
+            _ => self.on_success(),
+        }
+        res
+    }
+
+    /// Like [`Breaker::call`] for a stream: the circuit is checked up front
+    /// and the first item reports whether the database was reachable.
+    pub fn call_stream<T, F>(&self, f: F) -> BoxStream<'static, anyhow::Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> BoxStream<'static, anyhow::Result<T>>,
+    {
+        if let Err(open) = self.acquire() {
+            return stream::once(async move { Err(open.into()) }).boxed();
+        }
+        let breaker = self.clone();
+        let mut reported = false;
+        f().map(move |item| {
+            if !reported {
+                reported = true;
+                match &item {
+                    Err(e) if is_connection_error(e) => breaker.on_failure(),
+                    _ => breaker.on_success(),
+                }
+            }
+            item
+        })
+        .boxed()
+    }
+
+    fn acquire(&self) -> Result<(), CircuitOpen> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            CircuitState::Closed { .. } => Ok(()),
+            CircuitState::Open { until } if now < until => Err(CircuitOpen {
+                retry_after: until - now,
+            }),
+            CircuitState::Open { .. } => {
+                self.transition(&mut state, CircuitState::HalfOpen { probe_started: now });
+                Ok(())
+            }
+            // a probe that never reported back (e.g. a cancelled request) must
+            // not wedge the breaker, so allow another one after a cool-down
+            CircuitState::HalfOpen { probe_started }
+                if now.duration_since(probe_started) >= self.config.cool_down =>
+            {
+                self.transition(&mut state, CircuitState::HalfOpen { probe_started: now });
+                Ok(())
+            }
+            CircuitState::HalfOpen { probe_started } => Err(CircuitOpen {
+                retry_after: self.config.cool_down - now.duration_since(probe_started),
+            }),
+        }
+    }
+
+    fn on_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if *state != (CircuitState::Closed { failures: 0 }) {
+            self.transition(&mut state, CircuitState::Closed { failures: 0 });
+        }
+    }
+
+    fn on_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let next = match *state {
+            CircuitState::Closed { failures } if failures + 1 < self.config.failure_threshold => {
+                CircuitState::Closed {
+                    failures: failures + 1,
+                }
+            }
+            _ => CircuitState::Open {
+                until: Instant::now() + self.config.cool_down,
+            },
+        };
+        self.transition(&mut state, next);
+    }
+
+    fn transition(&self, state: &mut CircuitState, next: CircuitState) {
+        match (&*state, &next) {
+            (CircuitState::Closed { .. }, CircuitState::Closed { .. }) => {}
+            (_, CircuitState::Open { .. }) => tracing::warn!(
+                "database circuit opened for {}s",
+                self.config.cool_down.as_secs()
+            ),
+            (_, CircuitState::HalfOpen { .. }) => {
+                tracing::info!("database circuit half-open, sending probe")
+            }
+            (_, CircuitState::Closed { .. }) => tracing::info!("database circuit closed"),
+        }
+        *state = next;
+        metrics::gauge!("db_circuit_breaker_state", state.gauge());
+    }
+}
+
+/// Errors meaning the database could not be reached at all, as opposed to a
+/// query that ran and failed (not found, constraint violations, ...).
+fn is_connection_error(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<sqlx::Error>(),
+        Some(
+            sqlx::Error::Io(_)
+                | sqlx::Error::Tls(_)
+                | sqlx::Error::PoolTimedOut
+                | sqlx::Error::PoolClosed
+                | sqlx::Error::WorkerCrashed
+        )
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker<R> {
+    inner: R,
+    breaker: Breaker,
+}
+
+impl<R> CircuitBreaker<R> {
+    pub fn new(inner: R, breaker: Breaker) -> Self {
+        Self { inner, breaker }
+    }
+}
+
+#[async_trait]
+impl<R: TodoRepository> TodoRepository for CircuitBreaker<R> {
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        self.breaker.call(self.inner.create(payload)).await
+    }
+
+    async fn begin(&self) -> anyhow::Result<Box<dyn TodoUnitOfWork>> {
+        self.breaker.call(self.inner.begin()).await
+    }
+
+    async fn find(&self, id: TodoId) -> anyhow::Result<Todo> {
+        self.breaker.call(self.inner.find(id)).await
+    }
+
+    async fn count(&self) -> anyhow::Result<i64> {
+        self.breaker.call(self.inner.count()).await
+    }
+
+    async fn existing(&self, ids: &[TodoId]) -> anyhow::Result<BTreeSet<TodoId>> {
+        self.breaker.call(self.inner.existing(ids)).await
+    }
+
+    async fn dependencies(&self, id: TodoId) -> anyhow::Result<TodoDependencies> {
+        self.breaker.call(self.inner.dependencies(id)).await
+    }
+
+    async fn open_dependencies(&self, id: TodoId) -> anyhow::Result<Vec<TodoId>> {
+        self.breaker.call(self.inner.open_dependencies(id)).await
+    }
+
+    async fn add_dependency(
+        &self,
+        id: TodoId,
+        depends_on: TodoId,
+    ) -> anyhow::Result<TodoDependencies> {
+        self.breaker
+            .call(self.inner.add_dependency(id, depends_on))
+            .await
+    }
+
+    async fn remove_dependency(
+        &self,
+        id: TodoId,
+        depends_on: TodoId,
+    ) -> anyhow::Result<TodoDependencies> {
+        self.breaker
+            .call(self.inner.remove_dependency(id, depends_on))
+            .await
+    }
+
+    async fn find_by_external_id(&self, external_id: &str) -> anyhow::Result<Todo> {
+        self.breaker
+            .call(self.inner.find_by_external_id(external_id))
+            .await
+    }
+
+    async fn random(&self, label: Option<LabelId>) -> anyhow::Result<Option<Todo>> {
+        self.breaker.call(self.inner.random(label)).await
+    }
+
+    async fn all(&self, params: TodoListParams) -> anyhow::Result<Vec<Todo>> {
+        self.breaker.call(self.inner.all(params)).await
+    }
+
+    fn stream(&self, params: TodoListParams) -> BoxStream<'static, anyhow::Result<Todo>> {
+        self.breaker.call_stream(|| self.inner.stream(params))
+    }
+
+    async fn sync(&self, since: Option<u64>) -> anyhow::Result<TodoSync> {
+        self.breaker.call(self.inner.sync(since)).await
+    }
+
+    async fn version(&self) -> anyhow::Result<u64> {
+        self.breaker.call(self.inner.version()).await
+    }
+
+    async fn seq(&self, id: TodoId) -> anyhow::Result<u64> {
+        self.breaker.call(self.inner.seq(id)).await
+    }
+
+    async fn write_position(&self) -> anyhow::Result<Option<u64>> {
+        self.breaker.call(self.inner.write_position()).await
+    }
+
+    async fn update(&self, id: TodoId, payload: UpdateTodo) -> anyhow::Result<UpdatedTodo> {
+        self.breaker.call(self.inner.update(id, payload)).await
+    }
+
+    async fn set_pinned(&self, id: TodoId, pinned: bool) -> anyhow::Result<Todo> {
+        self.breaker.call(self.inner.set_pinned(id, pinned)).await
+    }
+
+    async fn reset(&self, id: TodoId) -> anyhow::Result<Todo> {
+        self.breaker.call(self.inner.reset(id)).await
+    }
+
+    async fn set_labels(&self, id: TodoId, label_ids: Vec<LabelId>) -> anyhow::Result<Todo> {
+        self.breaker
+            .call(self.inner.set_labels(id, label_ids))
+            .await
+    }
+
+    async fn move_label(&self, from: LabelId, to: LabelId) -> anyhow::Result<Vec<Todo>> {
+        self.breaker.call(self.inner.move_label(from, to)).await
+    }
+
+    async fn delete(&self, id: TodoId) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.delete(id)).await
+    }
+
+    async fn ping(&self, write: bool) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.ping(write)).await
+    }
+
+    fn pool_usage(&self) -> Option<PoolUsage> {
+        self.inner.pool_usage()
+    }
+
+    async fn repair(&self, dry_run: bool) -> anyhow::Result<RepairReport> {
+        self.breaker.call(self.inner.repair(dry_run)).await
+    }
+}
+
+#[async_trait]
+impl<R: LabelRepository> LabelRepository for CircuitBreaker<R> {
+    async fn create(&self, name: String) -> anyhow::Result<Label> {
+        self.breaker.call(self.inner.create(name)).await
+    }
+
+    async fn create_many(&self, names: Vec<String>) -> anyhow::Result<Vec<BulkLabel>> {
+        self.breaker.call(self.inner.create_many(names)).await
+    }
+
+    async fn ensure(&self, name: String) -> anyhow::Result<Label> {
+        self.breaker.call(self.inner.ensure(name)).await
+    }
+
+    async fn all(&self, params: LabelListParams) -> anyhow::Result<Vec<Label>> {
+        self.breaker.call(self.inner.all(params)).await
+    }
+
+    async fn count(&self, params: &LabelListParams) -> anyhow::Result<i64> {
+        self.breaker.call(self.inner.count(params)).await
+    }
+
+    async fn suggest(&self, prefix: &str) -> anyhow::Result<Vec<Label>> {
+        self.breaker.call(self.inner.suggest(prefix)).await
+    }
+
+    async fn delete(&self, id: LabelId) -> anyhow::Result<()> {
+        self.breaker.call(self.inner.delete(id)).await
+    }
+}
+
+#[async_trait]
+impl<R: PreferencesRepository> PreferencesRepository for CircuitBreaker<R> {
+    async fn get(&self, owner: &str) -> anyhow::Result<StoredPreferences> {
+        self.breaker.call(self.inner.get(owner)).await
+    }
+
+    async fn put(
+        &self,
+        owner: &str,
+        preferences: Preferences,
+    ) -> anyhow::Result<StoredPreferences> {
+        self.breaker.call(self.inner.put(owner, preferences)).await
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::repositories::todo_repository::test_utils::TodoRepositoryForMemory;
+
+    use super::*;
+
+    /// Memory repository that fails like an unreachable database while `down` is set.
+    #[derive(Debug, Clone, Default)]
+    pub struct FlakyTodoRepository {
+        inner: TodoRepositoryForMemory,
+        down: Arc<AtomicBool>,
+    }
+
+    impl FlakyTodoRepository {
+        pub fn new() -> Self {
+            Self {
+                inner: TodoRepositoryForMemory::new(),
+                down: Arc::default(),
+            }
+        }
+
+        pub fn set_down(&self, down: bool) {
+            self.down.store(down, Ordering::SeqCst);
+        }
+
+        fn check(&self) -> anyhow::Result<()> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(sqlx::Error::PoolTimedOut.into());
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl TodoRepository for FlakyTodoRepository {
+        async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+            self.check()?;
+            self.inner.create(payload).await
+        }
+
+        async fn begin(&self) -> anyhow::Result<Box<dyn TodoUnitOfWork>> {
+            self.check()?;
+            self.inner.begin().await
+        }
+
+        async fn find(&self, id: TodoId) -> anyhow::Result<Todo> {
+            self.check()?;
+            self.inner.find(id).await
+        }
+
+        async fn count(&self) -> anyhow::Result<i64> {
+            self.check()?;
+            self.inner.count().await
+        }
+
+        async fn existing(&self, ids: &[TodoId]) -> anyhow::Result<BTreeSet<TodoId>> {
+            self.check()?;
+            self.inner.existing(ids).await
+        }
+
+        async fn dependencies(&self, id: TodoId) -> anyhow::Result<TodoDependencies> {
+            self.check()?;
+            self.inner.dependencies(id).await
+        }
+
+        async fn open_dependencies(&self, id: TodoId) -> anyhow::Result<Vec<TodoId>> {
+            self.check()?;
+            self.inner.open_dependencies(id).await
+        }
+
+        async fn add_dependency(
+            &self,
+            id: TodoId,
+            depends_on: TodoId,
+        ) -> anyhow::Result<TodoDependencies> {
+            self.check()?;
+            self.inner.add_dependency(id, depends_on).await
+        }
+
+        async fn remove_dependency(
+            &self,
+            id: TodoId,
+            depends_on: TodoId,
+        ) -> anyhow::Result<TodoDependencies> {
+            self.check()?;
+            self.inner.remove_dependency(id, depends_on).await
+        }
+
+        async fn find_by_external_id(&self, external_id: &str) -> anyhow::Result<Todo> {
+            self.check()?;
+            self.inner.find_by_external_id(external_id).await
+        }
+
+        async fn random(&self, label: Option<LabelId>) -> anyhow::Result<Option<Todo>> {
+            self.check()?;
+            self.inner.random(label).await
+        }
+
+        async fn all(&self, params: TodoListParams) -> anyhow::Result<Vec<Todo>> {
+            self.check()?;
+            self.inner.all(params).await
+        }
+
+        fn stream(&self, params: TodoListParams) -> BoxStream<'static, anyhow::Result<Todo>> {
+            match self.check() {
+                Ok(()) => self.inner.stream(params),
+                Err(e) => stream::once(async move { Err(e) }).boxed(),
+            }
+        }
+
+        async fn sync(&self, since: Option<u64>) -> anyhow::Result<TodoSync> {
+            self.check()?;
+            self.inner.sync(since).await
+        }
+
+        async fn version(&self) -> anyhow::Result<u64> {
+            self.check()?;
+            self.inner.version().await
+        }
+
+        async fn seq(&self, id: TodoId) -> anyhow::Result<u64> {
+            self.check()?;
+            self.inner.seq(id).await
+        }
+
+        async fn update(&self, id: TodoId, payload: UpdateTodo) -> anyhow::Result<UpdatedTodo> {
+            self.check()?;
+            self.inner.update(id, payload).await
+        }
+
+        async fn set_pinned(&self, id: TodoId, pinned: bool) -> anyhow::Result<Todo> {
+            self.check()?;
+            self.inner.set_pinned(id, pinned).await
+        }
+
+        async fn reset(&self, id: TodoId) -> anyhow::Result<Todo> {
+            self.check()?;
+            self.inner.reset(id).await
+        }
+
+        async fn set_labels(&self, id: TodoId, label_ids: Vec<LabelId>) -> anyhow::Result<Todo> {
+            self.check()?;
+            self.inner.set_labels(id, label_ids).await
+        }
+
+        async fn move_label(&self, from: LabelId, to: LabelId) -> anyhow::Result<Vec<Todo>> {
+            self.check()?;
+            self.inner.move_label(from, to).await
+        }
+
+        async fn delete(&self, id: TodoId) -> anyhow::Result<()> {
+            self.check()?;
+            self.inner.delete(id).await
+        }
+
+        async fn ping(&self, write: bool) -> anyhow::Result<()> {
+            self.check()?;
+            self.inner.ping(write).await
+        }
+
+        fn pool_usage(&self) -> Option<PoolUsage> {
+            self.inner.pool_usage()
+        }
+
+        async fn repair(&self, dry_run: bool) -> anyhow::Result<RepairReport> {
+            self.check()?;
+            self.inner.repair(dry_run).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_utils::FlakyTodoRepository;
+    use super::*;
+
+    fn breaker() -> Breaker {
+        Breaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            cool_down: Duration::from_millis(50),
+        })
+    }
+
+    #[tokio::test]
+    async fn walks_closed_open_half_open_closed() {
+        let inner = FlakyTodoRepository::new();
+        let breaker = breaker();
+        let repository = CircuitBreaker::new(inner.clone(), breaker.clone());
+
+        // closed: not-found is an answer from the database, not an outage
+        assert!(repository.find(TodoId::new(1).unwrap()).await.is_err());
+        assert_eq!(CircuitState::Closed { failures: 0 }, breaker.state());
+
+        // two connection failures open the circuit
+        inner.set_down(true);
+        assert!(repository.all(TodoListParams::default()).await.is_err());
+        assert_eq!(CircuitState::Closed { failures: 1 }, breaker.state());
+        assert!(repository.all(TodoListParams::default()).await.is_err());
+        assert!(matches!(breaker.state(), CircuitState::Open { .. }));
+
+        // open: fail fast without reaching the inner repository
+        inner.set_down(false);
+        let err = repository.all(TodoListParams::default()).await.unwrap_err();
+        assert!(err.downcast_ref::<CircuitOpen>().is_some());
+
+        // after the cool-down a failing probe re-opens the circuit
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        inner.set_down(true);
+        assert!(repository.all(TodoListParams::default()).await.is_err());
+        assert!(matches!(breaker.state(), CircuitState::Open { .. }));
+
+        // and a successful probe closes it again
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        inner.set_down(false);
+        assert!(repository.all(TodoListParams::default()).await.is_ok());
+        assert_eq!(CircuitState::Closed { failures: 0 }, breaker.state());
+    }
+
+    #[tokio::test]
+    async fn streams_go_through_the_breaker() {
+        let inner = FlakyTodoRepository::new();
+        let breaker = breaker();
+        let repository = CircuitBreaker::new(inner.clone(), breaker.clone());
+
+        inner.set_down(true);
+        for _ in 0..2 {
+            let mut todos = repository.stream(TodoListParams::default());
+            assert!(todos.next().await.unwrap().is_err());
+        }
+        assert!(matches!(breaker.state(), CircuitState::Open { .. }));
+
+        inner.set_down(false);
+        let mut todos = repository.stream(TodoListParams::default());
+        let err = todos.next().await.unwrap().unwrap_err();
+        assert!(err.downcast_ref::<CircuitOpen>().is_some());
+    }
+
+    #[tokio::test]
+    async fn half_open_allows_a_single_probe() {
+        let breaker = breaker();
+        breaker.on_failure();
+        breaker.on_failure();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        assert!(breaker.acquire().is_ok());
+        assert!(matches!(breaker.state(), CircuitState::HalfOpen { .. }));
+        assert!(breaker.acquire().is_err());
+    }
+}
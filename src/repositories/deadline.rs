@@ -0,0 +1,148 @@
+use std::time::{Duration, Instant};
+
+use sqlx::{PgPool, Postgres, Transaction};
+use thiserror::Error;
+
+/// SQLSTATE `query_canceled`, raised when `statement_timeout` fires.
+const QUERY_CANCELED: &str = "57014";
+
+tokio::task_local! {
+    /// When the client of the request currently being handled gives up.
+    pub static DEADLINE: Instant;
+}
+
+#[derive(Debug, Error)]
+#[error("request deadline exceeded")]
+pub struct DeadlineExceeded;
+
+/// Budget left before the current request's deadline, if it has one.
+pub fn remaining() -> Option<Duration> {
+    DEADLINE
+        .try_with(|deadline| deadline.saturating_duration_since(Instant::now()))
+        .ok()
+}
+
+/// Starts a transaction whose statements are cancelled by Postgres itself
+/// once the request's deadline passes, so no connection is held for a client
+/// that already gave up.
+pub async fn begin(pool: &PgPool) -> anyhow::Result<Transaction<'static, Postgres>> {
+    let remaining = match remaining() {
+        None => return Ok(pool.begin().await?),
+        Some(remaining) if remaining.is_zero() => return Err(DeadlineExceeded.into()),
+        Some(remaining) => remaining,
+    };
+    let mut tx = tokio::time::timeout(remaining, pool.begin())
+        .await
+        .map_err(|_| DeadlineExceeded)??;
+    // local to the transaction, so it never leaks into the pooled connection
+    let millis = remaining.as_millis().clamp(1, i32::MAX as u128).to_string();
+    sqlx::query("SELECT set_config('statement_timeout', $1, true)")
+        .bind(millis)
+        .execute(&mut tx)
+        .await?;
+    Ok(tx)
+}
+
+/// Whether `err` means the request ran out of time, before or during a query.
+pub fn is_deadline_exceeded(err: &anyhow::Error) -> bool {
+    if err.is::<DeadlineExceeded>() {
+        return true;
+    }
+    match err.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(e)) => e.code().as_deref() == Some(QUERY_CANCELED),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn no_deadline_outside_requests() {
+        assert_eq!(None, remaining());
+        let past = Instant::now();
+        let left = DEADLINE.scope(past, async { remaining() }).await;
+        assert_eq!(Some(Duration::ZERO), left);
+    }
+
+    #[tokio::test]
+    async fn slow_query_is_cancelled_at_the_deadline() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPoolOptions::new()
+            .max_connections(1)
+            .connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+
+        let started = Instant::now();
+        let deadline = started + Duration::from_millis(200);
+        let res: anyhow::Result<()> = DEADLINE
+            .scope(deadline, async {
+                let mut tx = begin(&pool).await?;
+                sqlx::query("SELECT pg_sleep(5)").execute(&mut tx).await?;
+                Ok(())
+            })
+            .await;
+        assert!(is_deadline_exceeded(&res.unwrap_err()));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // the only connection is back in the pool, without the timeout
+        let (timeout,): (String,) = sqlx::query_as("SHOW statement_timeout")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!("0", timeout);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let expired = DEADLINE.scope(started, begin(&pool)).await;
+        assert!(is_deadline_exceeded(&expired.unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn every_repository_stops_at_the_deadline() {
+        use crate::models::id::{LabelId, TodoId};
+        use crate::repositories::label_repository::{LabelRepository, LabelRepositoryForDB};
+        use crate::repositories::preferences_repository::{
+            PreferencesRepository, PreferencesRepositoryForDb,
+        };
+        use crate::repositories::todo_repository::{TodoRepository, TodoRepositoryForDb};
+
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let labels = LabelRepositoryForDB::new(pool.clone());
+        let preferences = PreferencesRepositoryForDb::new(pool.clone());
+        let todos = TodoRepositoryForDb::with_replica(pool.clone(), pool);
+        let id = TodoId::new(1).unwrap();
+
+        let passed = Instant::now();
+        let errors = [
+            DEADLINE.scope(passed, labels.suggest("a")).await.err(),
+            DEADLINE
+                .scope(passed, labels.delete(LabelId::new(i32::MAX).unwrap()))
+                .await
+                .err(),
+            DEADLINE.scope(passed, preferences.get("owner")).await.err(),
+            DEADLINE.scope(passed, todos.existing(&[id])).await.err(),
+            DEADLINE.scope(passed, todos.count()).await.err(),
+            DEADLINE.scope(passed, todos.version()).await.err(),
+            DEADLINE.scope(passed, todos.seq(id)).await.err(),
+        ];
+        for (i, err) in errors.into_iter().enumerate() {
+            assert!(
+                is_deadline_exceeded(&err.expect("ran past the deadline")),
+                "{}",
+                i
+            );
+        }
+    }
+}
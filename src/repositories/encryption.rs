@@ -0,0 +1,178 @@
+use std::fmt;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::models::todo::SEALED_TEXT_PREFIX;
+
+/// Marks a stored value as encrypted; the key id and the base64 nonce and
+/// ciphertext follow, e.g. `enc:v1:1f2e3d4c:...`.
+const PREFIX: &str = SEALED_TEXT_PREFIX;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum EncryptionError {
+    #[error("encryption key must be 32 bytes of base64")]
+    InvalidKey,
+    #[error("encrypted with unknown key {0}")]
+    UnknownKey(String),
+    #[error("encrypted value is corrupt or was tampered with")]
+    Corrupt,
+}
+
+struct Key {
+    id: String,
+    cipher: Aes256Gcm,
+}
+
+impl Key {
+    fn from_base64(key: &str) -> Result<Self, EncryptionError> {
+        let key = STANDARD
+            .decode(key.trim())
+            .map_err(|_| EncryptionError::InvalidKey)?;
+        if key.len() != 32 {
+            return Err(EncryptionError::InvalidKey);
+        }
+        // not secret: it only tells which key sealed a value
+        let id = hex::encode(&Sha256::digest(&key)[..4]);
+        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| EncryptionError::InvalidKey)?;
+        Ok(Self { id, cipher })
+    }
+}
+
+/// AES-256-GCM for values stored at rest, under a fresh random nonce each
+/// time. Values are sealed with the current key and opened with whichever
+/// key sealed them, so previous keys keep working while rows are
+/// re-encrypted; values without the prefix were stored before encryption
+/// was turned on and are returned as they are. Todo validation rejects
+/// text starting with the prefix, so plaintext can't be mistaken for a
+/// sealed value; a row stored with such text before that rule fails to
+/// open and has to be rewritten by hand.
+pub struct TextCipher {
+    current: Key,
+    previous: Vec<Key>,
+}
+
+impl TextCipher {
+    /// Keys are 32 bytes of base64.
+    pub fn new(current: &str, previous: &[&str]) -> Result<Self, EncryptionError> {
+        Ok(Self {
+            current: Key::from_base64(current)?,
+            previous: previous
+                .iter()
+                .map(|key| Key::from_base64(key))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn seal(&self, plaintext: &str) -> String {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .current
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .expect("AES-GCM seals any text that fits in memory");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        format!("{}{}:{}", PREFIX, self.current.id, STANDARD.encode(sealed))
+    }
+
+    pub fn open(&self, stored: &str) -> Result<String, EncryptionError> {
+        let (key_id, sealed) = match stored.strip_prefix(PREFIX) {
+            Some(rest) => rest.split_once(':').ok_or(EncryptionError::Corrupt)?,
+            None => return Ok(stored.to_string()),
+        };
+        let key = std::iter::once(&self.current)
+            .chain(&self.previous)
+            .find(|key| key.id == key_id)
+            .ok_or_else(|| EncryptionError::UnknownKey(key_id.to_string()))?;
+        let sealed = STANDARD
+            .decode(sealed)
+            .map_err(|_| EncryptionError::Corrupt)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(EncryptionError::Corrupt);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = key
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| EncryptionError::Corrupt)?;
+        String::from_utf8(plaintext).map_err(|_| EncryptionError::Corrupt)
+    }
+
+    /// Whether `stored` is sealed at all, with whichever key.
+    pub fn is_sealed(stored: &str) -> bool {
+        stored.starts_with(PREFIX)
+    }
+
+    /// Whether `stored` is sealed with the current key, so re-encrypting it
+    /// would change nothing.
+    pub fn is_current(&self, stored: &str) -> bool {
+        stored
+            .strip_prefix(PREFIX)
+            .and_then(|rest| rest.split_once(':'))
+            .is_some_and(|(key_id, _)| key_id == self.current.id)
+    }
+}
+
+impl fmt::Debug for TextCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TextCipher")
+            .field("current", &self.current.id)
+            .field(
+                "previous",
+                &self.previous.iter().map(|key| &key.id).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY_A: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+    const KEY_B: &str = "Hx4dHBsaGRgXFhUUExIREA8ODQwLCgkIBwYFBAMCAQA=";
+
+    #[test]
+    fn sealed_text_opens_with_the_keys_that_know_it() {
+        let a = TextCipher::new(KEY_A, &[]).unwrap();
+        let sealed = a.seal("buy milk");
+        assert!(sealed.starts_with(PREFIX));
+        assert!(!sealed.contains("buy milk"));
+        // a fresh nonce every time
+        assert_ne!(sealed, a.seal("buy milk"));
+        assert_eq!("buy milk", a.open(&sealed).unwrap());
+        assert!(a.is_current(&sealed));
+
+        let rotated = TextCipher::new(KEY_B, &[KEY_A]).unwrap();
+        assert_eq!("buy milk", rotated.open(&sealed).unwrap());
+        assert!(!rotated.is_current(&sealed));
+        let resealed = rotated.seal("buy milk");
+        assert!(matches!(
+            a.open(&resealed),
+            Err(EncryptionError::UnknownKey(_))
+        ));
+
+        // stored before encryption was turned on
+        assert_eq!("plain", a.open("plain").unwrap());
+        assert!(!a.is_current("plain"));
+    }
+
+    #[test]
+    fn tampering_and_bad_keys_are_rejected() {
+        let a = TextCipher::new(KEY_A, &[]).unwrap();
+        let mut sealed = a.seal("buy milk").into_bytes();
+        let last = sealed.len() - 2;
+        sealed[last] = if sealed[last] == b'A' { b'B' } else { b'A' };
+        let tampered = String::from_utf8(sealed).unwrap();
+        assert_eq!(Err(EncryptionError::Corrupt), a.open(&tampered));
+
+        assert!(TextCipher::new("c2hvcnQ=", &[]).is_err());
+        assert!(TextCipher::new(KEY_A, &["not base64!"]).is_err());
+    }
+}
@@ -1,16 +1,62 @@
+use std::collections::HashSet;
+
 use axum::async_trait;
-use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 
+use crate::models::id::LabelId;
 use crate::models::label::*;
 
-use super::RepositoryError;
+use super::{deadline, RepositoryError};
 
 #[async_trait]
 pub trait LabelRepository: Clone + Send + Sync + 'static {
+    /// Fails with [`RepositoryError::Duplicate`] when a label of that name,
+    /// ignoring case, exists already.
     async fn create(&self, name: String) -> anyhow::Result<Label>;
-    async fn all(&self) -> anyhow::Result<Vec<Label>>;
-    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+    /// Creates the distinct `names`, reusing labels that already exist, in
+    /// input order; names differing only in case are the same label.
+    async fn create_many(&self, names: Vec<String>) -> anyhow::Result<Vec<BulkLabel>>;
+    /// Returns the label named `name`, ignoring case, creating it if it
+    /// doesn't exist. Shares [`LabelRepository::create_many`] by default, so
+    /// every backend agrees.
+    async fn ensure(&self, name: String) -> anyhow::Result<Label> {
+        let mut labels = self.create_many(vec![name]).await?;
+        let bulk = labels
+            .pop()
+            .ok_or_else(|| RepositoryError::Unexpected("no label ensured".to_string()))?;
+        Ok(bulk.label)
+    }
+    /// Labels matching `params.q` in `params.sort` order, ties broken by id;
+    /// a fuzzy search without a sort lists the closest names first.
+    async fn all(&self, params: LabelListParams) -> anyhow::Result<Vec<Label>>;
+    /// How many labels match `params.q`, ignoring paging.
+    async fn count(&self, params: &LabelListParams) -> anyhow::Result<i64>;
+    /// Labels whose name starts with `prefix` (case-insensitive), most used
+    /// first, at most [`SUGGEST_LIMIT`].
+    async fn suggest(&self, prefix: &str) -> anyhow::Result<Vec<Label>>;
+    async fn delete(&self, id: LabelId) -> anyhow::Result<()>;
+}
+
+pub const SUGGEST_LIMIT: usize = 10;
+
+/// Trigram similarity a fuzzy search needs; low, since one transposed letter
+/// in a short name like `wrok` already leaves few trigrams in common.
+const FUZZY_SIMILARITY: f64 = 0.1;
+
+/// Escapes `LIKE` wildcards so `text` only matches itself.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Drops later spellings of a name already in the batch, ignoring case.
+fn dedup_names(names: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    names
+        .into_iter()
+        .filter(|name| seen.insert(name.to_lowercase()))
+        .collect()
 }
 
 #[derive(Debug, Clone)]
@@ -22,51 +68,202 @@ impl LabelRepositoryForDB {
     pub fn new(pool: PgPool) -> Self {
         Self { pool }
     }
-}
 
-#[async_trait]
-impl LabelRepository for LabelRepositoryForDB {
-    async fn create(&self, name: String) -> anyhow::Result<Label> {
-        let optional_label = sqlx::query_as::<_, Label>(
+    /// Inserts a label named `name` unless one of that name, ignoring case,
+    /// exists already; the unique index on `lower(name)` decides, so
+    /// concurrent inserts of the same name can't both succeed.
+    async fn insert_new(
+        tx: &mut Transaction<'_, Postgres>,
+        name: &str,
+    ) -> anyhow::Result<Option<Label>> {
+        let label = sqlx::query_as::<_, Label>(
             r#"
-                select * from labels where name = $1
-                 "#,
+                insert into labels ( name )
+                values ( $1 )
+                on conflict ( lower(name) ) do nothing
+                returning *
+                "#,
         )
-        .bind(name.clone())
-        .fetch_optional(&self.pool)
+        .bind(name)
+        .fetch_optional(&mut *tx)
         .await?;
+        Ok(label)
+    }
 
-        if let Some(label) = optional_label {
-            return Err(RepositoryError::Duplicate(label.id).into());
-        }
-
+    /// The label named `name`, ignoring case.
+    async fn find_by_name(tx: &mut Transaction<'_, Postgres>, name: &str) -> anyhow::Result<Label> {
         let label = sqlx::query_as::<_, Label>(
             r#"
-                insert into labels ( name )
-                values ( $1 )
-                returning *
+                select * from labels where lower(name) = lower($1)
                 "#,
         )
-        .bind(name.clone())
-        .fetch_one(&self.pool)
+        .bind(name)
+        .fetch_one(&mut *tx)
         .await?;
+        Ok(label)
+    }
+
+    /// [`LabelRepository::create`] within `tx`, e.g. one begun by
+    /// [`TodoRepositoryForDb::transaction`](super::todo_repository::TodoRepositoryForDb::transaction),
+    /// kept only once the caller commits it.
+    pub async fn create_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        name: String,
+    ) -> anyhow::Result<Label> {
+        match Self::insert_new(&mut *tx, &name).await? {
+            Some(label) => Ok(label),
+            None => {
+                let existing = Self::find_by_name(&mut *tx, &name).await?;
+                Err(RepositoryError::Duplicate(existing).into())
+            }
+        }
+    }
+
+    /// Starts a transaction for a search, with the similarity threshold of
+    /// the `%` operator set for fuzzy ones; returns the `WHERE` clause and
+    /// the pattern bound to it as `$1`.
+    async fn begin_search(
+        &self,
+        params: &LabelListParams,
+    ) -> anyhow::Result<(Transaction<'static, Postgres>, &'static str, Option<String>)> {
+        let mut tx = deadline::begin(&self.pool).await?;
+        let (filter, pattern) = match params.search() {
+            None => ("TRUE", None),
+            Some(q) if params.fuzzy => {
+                // local to the transaction, so it never leaks into the pool
+                sqlx::query("SELECT set_config('pg_trgm.similarity_threshold', $1, true)")
+                    .bind(FUZZY_SIMILARITY.to_string())
+                    .execute(&mut tx)
+                    .await?;
+                ("labels.name % $1", Some(q.to_string()))
+            }
+            Some(q) => (
+                "labels.name ILIKE $1",
+                Some(format!("%{}%", escape_like(q))),
+            ),
+        };
+        Ok((tx, filter, pattern))
+    }
+}
+
+#[async_trait]
+impl LabelRepository for LabelRepositoryForDB {
+    async fn create(&self, name: String) -> anyhow::Result<Label> {
+        let mut tx = deadline::begin(&self.pool).await?;
+        let label = self.create_in(&mut tx, name).await?;
+        tx.commit().await?;
 
         Ok(label)
     }
 
-    async fn all(&self) -> anyhow::Result<Vec<Label>> {
+    async fn create_many(&self, names: Vec<String>) -> anyhow::Result<Vec<BulkLabel>> {
+        if names.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut tx = deadline::begin(&self.pool).await?;
+        let mut labels = vec![];
+        for name in dedup_names(names) {
+            let label = match Self::insert_new(&mut tx, &name).await? {
+                Some(label) => BulkLabel {
+                    label,
+                    created: true,
+                },
+                None => BulkLabel {
+                    label: Self::find_by_name(&mut tx, &name).await?,
+                    created: false,
+                },
+            };
+            labels.push(label);
+        }
+        tx.commit().await?;
+
+        Ok(labels)
+    }
+
+    /// One insert that gives way to an existing label, then one lookup, so
+    /// concurrent calls for the same name all return the one label.
+    async fn ensure(&self, name: String) -> anyhow::Result<Label> {
+        let mut tx = deadline::begin(&self.pool).await?;
+        let label = match Self::insert_new(&mut tx, &name).await? {
+            Some(label) => label,
+            None => Self::find_by_name(&mut tx, &name).await?,
+        };
+        tx.commit().await?;
+
+        Ok(label)
+    }
+
+    async fn all(&self, params: LabelListParams) -> anyhow::Result<Vec<Label>> {
+        let (mut tx, filter, pattern) = self.begin_search(&params).await?;
+        // only these fixed clauses ever reach the query
+        let order = match params.sort {
+            None if params.fuzzy && pattern.is_some() => {
+                "similarity(labels.name, $1) DESC, labels.id ASC"
+            }
+            sort => match sort.unwrap_or_default() {
+                LabelSort::Id => "labels.id ASC",
+                LabelSort::Name => "labels.name ASC, labels.id ASC",
+                LabelSort::TodoCount => {
+                    "(SELECT COUNT(*) FROM todo_labels tl WHERE tl.label_id = labels.id) DESC, labels.id ASC"
+                }
+            },
+        };
+        let labels = sqlx::query_as::<_, Label>(&format!(
+            r#"
+            SELECT * FROM labels
+            WHERE {}
+            ORDER BY {}
+            LIMIT $2 OFFSET $3
+            "#,
+            filter, order
+        ))
+        .bind(pattern)
+        .bind(params.limit)
+        .bind(params.offset)
+        .fetch_all(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(labels)
+    }
+
+    async fn count(&self, params: &LabelListParams) -> anyhow::Result<i64> {
+        let (mut tx, filter, pattern) = self.begin_search(params).await?;
+        let count = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM labels WHERE {}", filter))
+            .bind(pattern)
+            .fetch_one(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(count)
+    }
+
+    async fn suggest(&self, prefix: &str) -> anyhow::Result<Vec<Label>> {
+        let pattern = format!("{}%", escape_like(prefix));
+        let mut tx = deadline::begin(&self.pool).await?;
         let labels = sqlx::query_as::<_, Label>(
             r#"
-            SELECT * FROM labels order by labels.id asc
+            SELECT labels.id, labels.name
+            FROM labels
+                LEFT OUTER JOIN todo_labels tl ON tl.label_id = labels.id
+            WHERE labels.name ILIKE $1
+            GROUP BY labels.id, labels.name
+            ORDER BY COUNT(tl.todo_id) DESC, labels.name ASC
+            LIMIT $2
             "#,
         )
-        .fetch_all(&self.pool)
+        .bind(pattern)
+        .bind(SUGGEST_LIMIT as i64)
+        .fetch_all(&mut tx)
         .await?;
+        tx.commit().await?;
 
         Ok(labels)
     }
 
-    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+    async fn delete(&self, id: LabelId) -> anyhow::Result<()> {
+        let mut tx = deadline::begin(&self.pool).await?;
         sqlx::query(
             r#"
             DELETE FROM labels
@@ -74,8 +271,9 @@ impl LabelRepository for LabelRepositoryForDB {
             "#,
         )
         .bind(id)
-        .execute(&self.pool)
+        .execute(&mut tx)
         .await?;
+        tx.commit().await?;
 
         Ok(())
     }
@@ -83,6 +281,10 @@ impl LabelRepository for LabelRepositoryForDB {
 
 #[cfg(test)]
 mod test {
+    use std::env;
+
+    use dotenv::dotenv;
+
     use super::*;
     use crate::repositories::label_repository::test_utils::LabelRepositoryForMemory;
 
@@ -99,7 +301,10 @@ mod test {
         assert_eq!(label.name, label_text);
 
         // all
-        let labels = repository.all().await.expect("[all] returned Err");
+        let labels = repository
+            .all(LabelListParams::default())
+            .await
+            .expect("[all] returned Err");
         let label = labels.last().unwrap();
         assert_eq!(label.name, label_text);
 
@@ -109,88 +314,448 @@ mod test {
             .await
             .expect("[delete] returned Err");
     }
+
+    #[tokio::test]
+    async fn all_sorts_and_pages() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = LabelRepositoryForDB::new(pool.clone());
+        let mut created = Vec::new();
+        for name in ["all_sorts b", "all_sorts c", "all_sorts a"] {
+            created.push(repository.create(name.to_string()).await.unwrap());
+        }
+        let (todo_id,): (i32,) =
+            sqlx::query_as("INSERT INTO todos (text) VALUES ('all_sorts todo') RETURNING id")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        sqlx::query("INSERT INTO todo_labels (todo_id, label_id) VALUES ($1, $2)")
+            .bind(todo_id)
+            .bind(created[1].id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // other tests share the table, so only look at what this one created
+        let sorted = |sort| {
+            let repository = repository.clone();
+            let created = created.clone();
+            async move {
+                let params = LabelListParams {
+                    sort,
+                    ..LabelListParams::default()
+                };
+                repository
+                    .all(params)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .filter(|label| created.contains(label))
+                    .map(|label| label.name)
+                    .collect::<Vec<_>>()
+            }
+        };
+        let names = |order: [usize; 3]| order.map(|i| created[i].name.clone()).to_vec();
+        assert_eq!(names([0, 1, 2]), sorted(None).await);
+        assert_eq!(names([2, 0, 1]), sorted(Some(LabelSort::Name)).await);
+        assert_eq!(names([1, 0, 2]), sorted(Some(LabelSort::TodoCount)).await);
+
+        let page = repository
+            .all(LabelListParams {
+                limit: Some(2),
+                offset: Some(1),
+                ..LabelListParams::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(2, page.len());
+        assert!(repository.count(&LabelListParams::default()).await.unwrap() >= 3);
+
+        sqlx::query("DELETE FROM todo_labels WHERE todo_id = $1")
+            .bind(todo_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM todos WHERE id = $1")
+            .bind(todo_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        for label in &created {
+            repository.delete(label.id).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn all_searches_names() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = LabelRepositoryForDB::new(pool);
+        let mut created = Vec::new();
+        for name in [
+            "all_searches home",
+            "all_searches work",
+            "all_searches 100%",
+        ] {
+            created.push(repository.create(name.to_string()).await.unwrap());
+        }
+
+        // other tests share the table, so only look at what this one created
+        let search = |q: &str, fuzzy| {
+            let repository = repository.clone();
+            let created = created.clone();
+            let params = LabelListParams {
+                q: Some(q.to_string()),
+                fuzzy,
+                ..LabelListParams::default()
+            };
+            async move {
+                let count = repository.count(&params).await.unwrap();
+                let names = repository
+                    .all(params)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .filter(|label| created.contains(label))
+                    .map(|label| label.name)
+                    .collect::<Vec<_>>();
+                assert!(count >= names.len() as i64);
+                names
+            }
+        };
+        assert_eq!(
+            vec!["all_searches work"],
+            search("SEARCHES WO", false).await
+        );
+        assert_eq!(vec!["all_searches 100%"], search("0%", false).await);
+        assert!(search("all_searches wrok", false).await.is_empty());
+        // closest first, despite the typo
+        let names = search("all_searches wrok", true).await;
+        assert_eq!(Some(&"all_searches work".to_string()), names.first());
+
+        for label in &created {
+            repository.delete(label.id).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn create_many_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = LabelRepositoryForDB::new(pool);
+        let existing = repository
+            .create("create_many existing".to_string())
+            .await
+            .expect("[create] returned Err");
+
+        let labels = repository
+            .create_many(vec![
+                "create_many new".to_string(),
+                existing.name.clone(),
+                "CREATE_MANY NEW".to_string(),
+            ])
+            .await
+            .expect("[create_many] returned Err");
+        assert_eq!(2, labels.len());
+        assert_eq!("create_many new", labels[0].label.name);
+        assert!(labels[0].created);
+        assert_eq!(
+            BulkLabel {
+                label: existing.clone(),
+                created: false,
+            },
+            labels[1]
+        );
+
+        // a second run only finds them, whatever their case
+        let again = repository
+            .create_many(vec!["Create_Many New".to_string()])
+            .await
+            .expect("[create_many] returned Err");
+        assert_eq!(labels[0].label, again[0].label);
+        assert!(!again[0].created);
+        let res = repository.create("CREATE_MANY EXISTING".to_string()).await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref(),
+            Some(RepositoryError::Duplicate(label)) if *label == existing
+        ));
+
+        // concurrent batches of the same name end up with one label
+        let racing = (0..8).map(|i| {
+            let repository = repository.clone();
+            let name = match i % 2 {
+                0 => "create_many race",
+                _ => "CREATE_MANY RACE",
+            };
+            tokio::spawn(async move { repository.create_many(vec![name.to_string()]).await })
+        });
+        let mut raced = Vec::new();
+        for task in racing.collect::<Vec<_>>() {
+            raced.push(
+                task.await
+                    .unwrap()
+                    .expect("[create_many] returned Err")
+                    .remove(0),
+            );
+        }
+        assert_eq!(1, raced.iter().filter(|bulk| bulk.created).count());
+        assert!(raced.iter().all(|bulk| bulk.label == raced[0].label));
+        repository.delete(raced[0].label.id).await.unwrap();
+
+        // and so do concurrent ensures
+        let racing = (0..8).map(|i| {
+            let repository = repository.clone();
+            let name = match i % 2 {
+                0 => "ensure race",
+                _ => "Ensure Race",
+            };
+            tokio::spawn(async move { repository.ensure(name.to_string()).await })
+        });
+        let mut ensured = Vec::new();
+        for task in racing.collect::<Vec<_>>() {
+            ensured.push(task.await.unwrap().expect("[ensure] returned Err"));
+        }
+        assert!(ensured.iter().all(|label| *label == ensured[0]));
+        assert_eq!(
+            ensured[0],
+            repository.ensure("ENSURE RACE".to_string()).await.unwrap()
+        );
+        repository.delete(ensured[0].id).await.unwrap();
+        assert!(repository.create_many(vec![]).await.unwrap().is_empty());
+
+        repository.delete(existing.id).await.unwrap();
+        repository.delete(labels[0].label.id).await.unwrap();
+    }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "test-utils"))]
 pub mod test_utils {
-    use std::collections::HashMap;
+    use std::cmp::Reverse;
+    use std::collections::{BTreeSet, HashMap};
     use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
     use axum::async_trait;
 
-    use crate::models::label::CreateLabel;
-    use crate::repositories::label_repository::LabelRepository;
+    use crate::models::id::{LabelId, TodoId};
+    use crate::models::label::{BulkLabel, LabelListParams, LabelSort};
+    use crate::repositories::label_repository::{dedup_names, LabelRepository, SUGGEST_LIMIT};
     use crate::repositories::RepositoryError;
 
     use super::Label;
 
-    impl Label {
-        pub fn new(id: i32, name: String) -> Self {
-            Self { id, name }
+    type LabelData = HashMap<LabelId, Label>;
+
+    /// The label named `name`, ignoring case, like the unique index on
+    /// `lower(name)`.
+    fn find_by_name<'a>(store: &'a LabelData, name: &str) -> Option<&'a Label> {
+        let name = name.to_lowercase();
+        store
+            .values()
+            .find(|label| label.name.to_lowercase() == name)
+    }
+    /// `(todo_id, label_id)` pairs, the memory counterpart of `todo_labels`.
+    pub type TodoLabels = BTreeSet<(TodoId, LabelId)>;
+
+    /// Edits turning `a` into `b`, counting characters.
+    fn levenshtein(a: &str, b: &str) -> usize {
+        let b: Vec<char> = b.chars().collect();
+        let mut row: Vec<usize> = (0..=b.len()).collect();
+        for (i, a) in a.chars().enumerate() {
+            let mut diagonal = row[0];
+            row[0] = i + 1;
+            for (j, b) in b.iter().enumerate() {
+                let substituted = diagonal + usize::from(a != *b);
+                diagonal = row[j + 1];
+                row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+            }
         }
+        row[b.len()]
     }
 
-    type LabelData = HashMap<i32, Label>;
+    /// How far `name` is from `params.q`, or `None` when it doesn't match.
+    /// Fuzzy searches fall back to edit distance in place of trigrams,
+    /// allowing about one typo per two characters.
+    fn search_distance(name: &str, params: &LabelListParams) -> Option<usize> {
+        let q = match params.search() {
+            Some(q) => q.to_lowercase(),
+            None => return Some(0),
+        };
+        let name = name.to_lowercase();
+        if name.contains(&q) {
+            return Some(0);
+        }
+        if !params.fuzzy {
+            return None;
+        }
+        let distance = levenshtein(&name, &q);
+        (distance <= (q.chars().count() / 2).max(1)).then_some(distance)
+    }
 
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, Default)]
     pub struct LabelRepositoryForMemory {
         data: Arc<RwLock<LabelData>>,
+        todo_labels: Arc<RwLock<TodoLabels>>,
     }
 
     impl LabelRepositoryForMemory {
         pub fn new() -> Self {
             Self {
                 data: Arc::new(RwLock::new(HashMap::new())),
+                todo_labels: Arc::default(),
             }
         }
 
-        pub fn read_store_ref(&self) -> RwLockReadGuard<LabelData> {
+        pub fn read_store_ref(&self) -> RwLockReadGuard<'_, LabelData> {
             self.data.read().unwrap()
         }
 
-        pub fn write_store_ref(&self) -> RwLockWriteGuard<LabelData> {
+        pub fn write_store_ref(&self) -> RwLockWriteGuard<'_, LabelData> {
             self.data.write().unwrap()
         }
+
+        pub fn read_todo_labels_ref(&self) -> RwLockReadGuard<'_, TodoLabels> {
+            self.todo_labels.read().unwrap()
+        }
+
+        pub fn write_todo_labels_ref(&self) -> RwLockWriteGuard<'_, TodoLabels> {
+            self.todo_labels.write().unwrap()
+        }
     }
 
     #[async_trait]
     impl LabelRepository for LabelRepositoryForMemory {
         async fn create(&self, name: String) -> anyhow::Result<Label> {
             let mut store = self.write_store_ref();
-            if let Some((_key, label)) = store.iter().find(|(_key, label)| label.name == name) {
-                return Ok(label.clone());
+            if let Some(label) = find_by_name(&store, &name) {
+                return Err(RepositoryError::Duplicate(label.clone()).into());
             };
 
-            let id = (store.len() + 1) as i32;
+            let id = LabelId::new((store.len() + 1) as i32).expect("ids start at 1");
             let label = Label::new(id, name.clone());
             store.insert(id, label.clone());
             Ok(label)
         }
 
-        async fn all(&self) -> anyhow::Result<Vec<Label>> {
-            let store = self.read_store_ref();
-            let labels = Vec::from_iter(store.values().map(|label| label.clone()));
+        async fn create_many(&self, names: Vec<String>) -> anyhow::Result<Vec<BulkLabel>> {
+            let mut store = self.write_store_ref();
+            let labels = dedup_names(names)
+                .into_iter()
+                .map(|name| {
+                    if let Some(label) = find_by_name(&store, &name) {
+                        return BulkLabel {
+                            label: label.clone(),
+                            created: false,
+                        };
+                    }
+                    let id = LabelId::new((store.len() + 1) as i32).expect("ids start at 1");
+                    let label = Label::new(id, name);
+                    store.insert(id, label.clone());
+                    BulkLabel {
+                        label,
+                        created: true,
+                    }
+                })
+                .collect();
             Ok(labels)
         }
 
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        async fn all(&self, params: LabelListParams) -> anyhow::Result<Vec<Label>> {
+            let store = self.read_store_ref();
+            let todo_labels = self.read_todo_labels_ref();
+            let usage = |label: &Label| {
+                todo_labels
+                    .iter()
+                    .filter(|(_todo_id, label_id)| *label_id == label.id)
+                    .count()
+            };
+            let mut labels = store
+                .values()
+                .filter_map(|label| Some((search_distance(&label.name, &params)?, label.clone())))
+                .collect::<Vec<_>>();
+            labels.sort_by_key(|(_distance, label)| label.id);
+            match params.sort {
+                None if params.fuzzy => labels.sort_by_key(|(distance, _label)| *distance),
+                sort => match sort.unwrap_or_default() {
+                    LabelSort::Id => {}
+                    LabelSort::Name => labels.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name)),
+                    LabelSort::TodoCount => {
+                        labels.sort_by_key(|(_distance, label)| Reverse(usage(label)))
+                    }
+                },
+            }
+            Ok(labels
+                .into_iter()
+                .skip(params.offset.unwrap_or(0) as usize)
+                .take(params.limit.map_or(usize::MAX, |limit| limit as usize))
+                .map(|(_distance, label)| label)
+                .collect())
+        }
+
+        async fn count(&self, params: &LabelListParams) -> anyhow::Result<i64> {
+            let store = self.read_store_ref();
+            let matching = store
+                .values()
+                .filter(|label| search_distance(&label.name, params).is_some())
+                .count();
+            Ok(matching as i64)
+        }
+
+        async fn suggest(&self, prefix: &str) -> anyhow::Result<Vec<Label>> {
+            let prefix = prefix.to_lowercase();
+            let store = self.read_store_ref();
+            let todo_labels = self.read_todo_labels_ref();
+            let mut counted = store
+                .values()
+                .filter(|label| label.name.to_lowercase().starts_with(&prefix))
+                .map(|label| {
+                    let usage = todo_labels
+                        .iter()
+                        .filter(|(_todo_id, label_id)| *label_id == label.id)
+                        .count();
+                    (usage, label)
+                })
+                .collect::<Vec<_>>();
+            counted.sort_by(|(a_usage, a), (b_usage, b)| {
+                b_usage.cmp(a_usage).then_with(|| a.name.cmp(&b.name))
+            });
+            Ok(counted
+                .into_iter()
+                .take(SUGGEST_LIMIT)
+                .map(|(_usage, label)| label.clone())
+                .collect())
+        }
+
+        async fn delete(&self, id: LabelId) -> anyhow::Result<()> {
             let mut store = self.write_store_ref();
-            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
+            store
+                .remove(&id)
+                .ok_or(RepositoryError::NotFound(id.get()))?;
             Ok(())
         }
     }
 
+    #[cfg(test)]
     mod test {
         use std::vec;
 
-        use crate::models::label::Label;
+        use crate::models::id::{LabelId, TodoId};
+        use crate::models::label::{Label, LabelListParams, LabelSort};
 
-        use super::{LabelRepository, LabelRepositoryForMemory};
+        use super::{levenshtein, LabelRepository, LabelRepositoryForMemory};
 
         #[tokio::test]
         async fn label_crud_scenario() {
             let text = "label text".to_string();
-            let id = 1;
+            let id = LabelId::new(1).unwrap();
             let expected = Label::new(id, text.clone());
 
             // create
@@ -202,12 +767,134 @@ pub mod test_utils {
             assert_eq!(expected, label);
 
             // all
-            let label = repository.all().await.unwrap();
+            let label = repository.all(LabelListParams::default()).await.unwrap();
             assert_eq!(vec![expected], label);
 
             // delete
             let res = repository.delete(id).await;
             assert!(res.is_ok())
         }
+
+        #[tokio::test]
+        async fn create_many_dedups_in_input_order() {
+            let repository = LabelRepositoryForMemory::new();
+            let existing = repository.create("b".to_string()).await.unwrap();
+
+            let labels = repository
+                .create_many(vec!["a".to_string(), "b".to_string(), "A".to_string()])
+                .await
+                .unwrap();
+            let names = labels
+                .iter()
+                .map(|bulk| (bulk.label.name.as_str(), bulk.created))
+                .collect::<Vec<_>>();
+            assert_eq!(vec![("a", true), ("b", false)], names);
+            assert_eq!(existing, labels[1].label);
+
+            // existing labels are found ignoring case too
+            let again = repository.create_many(vec!["B".to_string()]).await.unwrap();
+            assert_eq!(existing, again[0].label);
+            assert!(repository.create("A".to_string()).await.is_err());
+        }
+
+        #[tokio::test]
+        async fn suggest_orders_by_usage() {
+            let repository = LabelRepositoryForMemory::new();
+            let work = repository.create("work".to_string()).await.unwrap();
+            let workout = repository.create("Workout".to_string()).await.unwrap();
+            repository.create("home".to_string()).await.unwrap();
+            let (first, second) = (TodoId::new(1).unwrap(), TodoId::new(2).unwrap());
+            repository.write_todo_labels_ref().extend([
+                (first, workout.id),
+                (second, workout.id),
+                (first, work.id),
+            ]);
+
+            let labels = repository.suggest("wo").await.unwrap();
+            assert_eq!(vec![workout, work.clone()], labels);
+
+            let labels = repository.suggest("WORK").await.unwrap();
+            assert_eq!(2, labels.len());
+            assert!(repository.suggest("x").await.unwrap().is_empty());
+        }
+
+        #[tokio::test]
+        async fn all_sorts_and_pages() {
+            let repository = LabelRepositoryForMemory::new();
+            let mut labels = Vec::new();
+            for name in ["b", "c", "a"] {
+                labels.push(repository.create(name.to_string()).await.unwrap());
+            }
+            repository
+                .write_todo_labels_ref()
+                .insert((TodoId::new(1).unwrap(), labels[1].id));
+            let all = |sort, limit, offset| {
+                let params = LabelListParams {
+                    limit,
+                    offset,
+                    sort,
+                    ..LabelListParams::default()
+                };
+                let repository = repository.clone();
+                async move { repository.all(params).await.unwrap() }
+            };
+            let picked =
+                |order: &[usize]| order.iter().map(|i| labels[*i].clone()).collect::<Vec<_>>();
+
+            assert_eq!(picked(&[0, 1, 2]), all(None, None, None).await);
+            assert_eq!(
+                picked(&[2, 0, 1]),
+                all(Some(LabelSort::Name), None, None).await
+            );
+            assert_eq!(
+                picked(&[1, 0, 2]),
+                all(Some(LabelSort::TodoCount), None, None).await
+            );
+            assert_eq!(picked(&[1]), all(None, Some(1), Some(1)).await);
+            assert_eq!(
+                3,
+                repository.count(&LabelListParams::default()).await.unwrap()
+            );
+        }
+
+        #[tokio::test]
+        async fn all_searches_with_levenshtein_fallback() {
+            let repository = LabelRepositoryForMemory::new();
+            let mut labels = Vec::new();
+            for name in ["workout", "home", "Work"] {
+                labels.push(repository.create(name.to_string()).await.unwrap());
+            }
+            let search = |q: &str, fuzzy| {
+                let params = LabelListParams {
+                    q: Some(q.to_string()),
+                    fuzzy,
+                    ..LabelListParams::default()
+                };
+                let repository = repository.clone();
+                async move {
+                    let count = repository.count(&params).await.unwrap();
+                    let labels = repository.all(params).await.unwrap();
+                    assert_eq!(labels.len() as i64, count);
+                    labels
+                }
+            };
+
+            assert_eq!(
+                vec![labels[0].clone(), labels[2].clone()],
+                search("wor", false).await
+            );
+            assert!(search("wrok", false).await.is_empty());
+            assert_eq!(vec![labels[2].clone()], search("wrok", true).await);
+            assert_eq!(vec![labels[1].clone()], search("hoem", true).await);
+            assert_eq!(3, search("", true).await.len());
+        }
+
+        #[test]
+        fn levenshtein_counts_edits() {
+            assert_eq!(0, levenshtein("work", "work"));
+            assert_eq!(2, levenshtein("work", "wrok"));
+            assert_eq!(3, levenshtein("workout", "work"));
+            assert_eq!(1, levenshtein("日本語", "日本"));
+        }
     }
 }
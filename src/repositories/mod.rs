@@ -1,14 +1,33 @@
 use thiserror::Error;
 
+use crate::models::label::Label;
+
+pub mod change_feed;
+pub mod circuit_breaker;
+pub mod deadline;
+pub mod encryption;
 pub mod label_repository;
+pub mod preferences_repository;
 pub mod todo_repository;
 
 #[derive(Debug, Error)]
-enum RepositoryError {
+pub enum RepositoryError {
     #[error("Unexpected Error: [{0}]")]
     Unexpected(String),
     #[error("NotFound, id is {0}")]
     NotFound(i32),
-    #[error("Duplicate data, id is {0}")]
-    Duplicate(i32),
+    #[error("NotFound, label ids are {0:?}")]
+    LabelsNotFound(Vec<i32>),
+    #[error("Duplicate label {}, id is {}", .0.name, .0.id)]
+    Duplicate(Label),
+    #[error("NotFound, external id is {0}")]
+    ExternalIdNotFound(String),
+    #[error("Duplicate external id {0}")]
+    DuplicateExternalId(String),
+    #[error("Dependency of {0} on {1} would form a cycle")]
+    DependencyCycle(i32, i32),
+    #[error("Dependencies below {0} go deeper than {1} to check for cycles")]
+    DependencyTooDeep(i32, usize),
+    #[error("Todo {0} is completed and locked")]
+    CompletedLocked(i32),
 }
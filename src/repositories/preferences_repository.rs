@@ -0,0 +1,200 @@
+use axum::async_trait;
+use sqlx::types::Json;
+use sqlx::PgPool;
+
+use super::deadline;
+
+use crate::models::preferences::{Preferences, StoredPreferences};
+
+/// Preferences per owner, an opaque key the handler derives from the
+/// caller's credentials.
+#[async_trait]
+pub trait PreferencesRepository: Clone + Send + Sync + 'static {
+    /// The owner's preferences; empty, without `updated_at`, before the first
+    /// [`PreferencesRepository::put`].
+    async fn get(&self, owner: &str) -> anyhow::Result<StoredPreferences>;
+    /// Replaces the owner's preferences as a whole; concurrent writes don't
+    /// conflict, the last one wins.
+    async fn put(&self, owner: &str, preferences: Preferences)
+        -> anyhow::Result<StoredPreferences>;
+}
+
+#[derive(Debug, Clone)]
+pub struct PreferencesRepositoryForDb {
+    pool: PgPool,
+}
+
+impl PreferencesRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl PreferencesRepository for PreferencesRepositoryForDb {
+    async fn get(&self, owner: &str) -> anyhow::Result<StoredPreferences> {
+        let mut tx = deadline::begin(&self.pool).await?;
+        let stored = sqlx::query_as::<_, (Json<Preferences>, i64)>(
+            r#"
+            SELECT document, (EXTRACT(EPOCH FROM updated_at) * 1000)::BIGINT
+            FROM preferences
+            WHERE owner = $1
+            "#,
+        )
+        .bind(owner)
+        .fetch_optional(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(match stored {
+            Some((Json(preferences), updated_at)) => StoredPreferences {
+                preferences,
+                updated_at: Some(updated_at),
+            },
+            None => StoredPreferences::default(),
+        })
+    }
+
+    async fn put(
+        &self,
+        owner: &str,
+        preferences: Preferences,
+    ) -> anyhow::Result<StoredPreferences> {
+        let mut tx = deadline::begin(&self.pool).await?;
+        let (Json(preferences), updated_at) = sqlx::query_as::<_, (Json<Preferences>, i64)>(
+            r#"
+            INSERT INTO preferences (owner, document)
+            VALUES ($1, $2)
+            ON CONFLICT (owner) DO UPDATE SET document = EXCLUDED.document, updated_at = now()
+            RETURNING document, (EXTRACT(EPOCH FROM updated_at) * 1000)::BIGINT
+            "#,
+        )
+        .bind(owner)
+        .bind(Json(preferences))
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(StoredPreferences {
+            preferences,
+            updated_at: Some(updated_at),
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use axum::async_trait;
+
+    use crate::models::preferences::{Preferences, StoredPreferences};
+    use crate::repositories::preferences_repository::PreferencesRepository;
+
+    #[derive(Debug, Clone, Default)]
+    pub struct PreferencesRepositoryForMemory {
+        store: Arc<RwLock<HashMap<String, StoredPreferences>>>,
+    }
+
+    impl PreferencesRepositoryForMemory {
+        pub fn new() -> Self {
+            Self::default()
+        }
+    }
+
+    #[async_trait]
+    impl PreferencesRepository for PreferencesRepositoryForMemory {
+        async fn get(&self, owner: &str) -> anyhow::Result<StoredPreferences> {
+            let store = self.store.read().unwrap();
+            Ok(store.get(owner).cloned().unwrap_or_default())
+        }
+
+        async fn put(
+            &self,
+            owner: &str,
+            preferences: Preferences,
+        ) -> anyhow::Result<StoredPreferences> {
+            let updated_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+            let stored = StoredPreferences {
+                preferences,
+                updated_at: Some(updated_at),
+            };
+            let mut store = self.store.write().unwrap();
+            store.insert(owner.to_string(), stored.clone());
+            Ok(stored)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use dotenv::dotenv;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::models::preferences::{DefaultSort, Theme};
+    use crate::repositories::preferences_repository::test_utils::PreferencesRepositoryForMemory;
+
+    fn preferences() -> Preferences {
+        Preferences {
+            default_sort: Some(DefaultSort::Oldest),
+            hide_completed: Some(true),
+            theme: Some(Theme::Dark),
+            custom: json!({ "density": "compact" }).as_object().unwrap().clone(),
+        }
+    }
+
+    /// Returns the owner it wrote for.
+    async fn round_trip(repository: impl PreferencesRepository) -> String {
+        let owner = format!("owner_{}", Uuid::new_v4().simple());
+        assert_eq!(
+            StoredPreferences::default(),
+            repository.get(&owner).await.unwrap()
+        );
+
+        let first = repository.put(&owner, preferences()).await.unwrap();
+        assert_eq!(preferences(), first.preferences);
+        assert!(first.updated_at.is_some());
+        assert_eq!(first, repository.get(&owner).await.unwrap());
+
+        // a put replaces the whole document
+        let replaced = Preferences {
+            theme: Some(Theme::Light),
+            ..Preferences::default()
+        };
+        let second = repository.put(&owner, replaced.clone()).await.unwrap();
+        assert_eq!(replaced, second.preferences);
+        assert!(second.updated_at >= first.updated_at);
+        assert_eq!(second, repository.get(&owner).await.unwrap());
+        assert_eq!(
+            StoredPreferences::default(),
+            repository.get("someone else").await.unwrap()
+        );
+        owner
+    }
+
+    #[tokio::test]
+    async fn memory_round_trips_and_replaces() {
+        round_trip(PreferencesRepositoryForMemory::new()).await;
+    }
+
+    #[tokio::test]
+    async fn db_round_trips_and_replaces() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let owner = round_trip(PreferencesRepositoryForDb::new(pool.clone())).await;
+        sqlx::query("DELETE FROM preferences WHERE owner = $1")
+            .bind(owner)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+}
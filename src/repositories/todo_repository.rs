@@ -1,299 +1,3728 @@
-use super::RepositoryError;
-use crate::models::todo::{CreateTodo, Todo, UpdateTodo};
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_stream::try_stream;
 use axum::async_trait;
-use sqlx::PgPool;
+use futures_util::stream::BoxStream;
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use sqlx::{Executor, FromRow, PgPool, Postgres, Transaction};
+
+use super::deadline::{self, is_deadline_exceeded};
+use super::encryption::TextCipher;
+use super::RepositoryError;
+use crate::models::id::{LabelId, TodoId};
+use crate::models::label::Label;
+use crate::models::todo::{
+    CompletedFilter, CreateTodo, LabelsView, Todo, TodoDependencies, TodoListParams, TodoSync,
+    UpdateTodo, UpdatedTodo,
+};
+
+/// How far the cycle check follows dependencies below a new edge; an edge
+/// whose dependencies go deeper is rejected rather than added unchecked.
+pub const MAX_DEPENDENCY_DEPTH: usize = 32;
+
+/// How long a read holding a consistency token waits for the replica to
+/// catch up before going to the primary instead.
+const REPLICA_CATCH_UP_WAIT: Duration = Duration::from_millis(500);
+const REPLICA_CATCH_UP_POLL: Duration = Duration::from_millis(25);
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+struct TodoFromRow {
+    id: TodoId,
+    text: String,
+    completed: bool,
+    pinned: bool,
+    external_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+struct TodoWithLabelFromRow {
+    id: TodoId,
+    text: String,
+    completed: bool,
+    pinned: bool,
+    external_id: Option<String>,
+    label_id: Option<LabelId>,
+    label_name: Option<String>,
+}
+
+/// A `todo_labels` row, by the raw ids it points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, FromRow)]
+pub struct TodoLabelLink {
+    pub todo_id: i32,
+    pub label_id: i32,
+}
+
+/// Connections of the pool behind a repository, see
+/// [`TodoRepository::pool_usage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolUsage {
+    pub open: u32,
+    pub idle: u32,
+}
+
+/// What [`TodoRepository::repair`] fixed, or would fix on a dry run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RepairReport {
+    pub dry_run: bool,
+    /// Associations whose todo or label no longer exists.
+    pub orphaned_links: Vec<TodoLabelLink>,
+    /// Extra copies of an association that already exists.
+    pub duplicate_links: u64,
+}
+
+/// A page of todos, pinned first, then newest first, joined with their labels; `$1` is the
+/// limit, `$2` the offset and `$3` the `completed` value to match, if any.
+const PAGE_WITH_LABELS: &str = r#"
+    SELECT todos.*, labels.id AS label_id, labels.name AS label_name
+    FROM (
+        SELECT * FROM todos
+        WHERE $3::BOOLEAN IS NULL OR completed = $3
+        ORDER BY pinned DESC, id DESC
+        LIMIT $1 OFFSET $2
+    ) todos
+        LEFT OUTER JOIN todo_labels tl ON todos.id = tl.todo_id
+        LEFT OUTER JOIN labels ON labels.id = tl.label_id
+    ORDER BY todos.pinned DESC, todos.id DESC, labels.id ASC
+"#;
+
+/// Like [`PAGE_WITH_LABELS`], but without joining `labels`: only label ids,
+/// for `labels=ids`.
+const PAGE_WITH_LABEL_IDS: &str = r#"
+    SELECT todos.*, tl.label_id, NULL::TEXT AS label_name
+    FROM (
+        SELECT * FROM todos
+        WHERE $3::BOOLEAN IS NULL OR completed = $3
+        ORDER BY pinned DESC, id DESC
+        LIMIT $1 OFFSET $2
+    ) todos
+        LEFT OUTER JOIN todo_labels tl ON todos.id = tl.todo_id
+    ORDER BY todos.pinned DESC, todos.id DESC, tl.label_id ASC
+"#;
+
+/// Todos changed at or after `$1`, joined with their labels: created then
+/// when `$2` is true, created before and updated since when false.
+const CHANGED_SINCE: &str = r#"
+    SELECT todos.*, labels.id AS label_id, labels.name AS label_name
+    FROM todos
+        LEFT OUTER JOIN todo_labels tl ON todos.id = tl.todo_id
+        LEFT OUTER JOIN labels ON labels.id = tl.label_id
+    WHERE todos.seq >= $1 AND (todos.created_seq >= $1) = $2
+    ORDER BY todos.id ASC, labels.id ASC
+"#;
+
+fn page_query(params: &TodoListParams) -> &'static str {
+    match params.labels.unwrap_or_default() {
+        LabelsView::Full => PAGE_WITH_LABELS,
+        LabelsView::Ids => PAGE_WITH_LABEL_IDS,
+    }
+}
+
+/// Adds a joined row to the todo being built in `current`, handing back the
+/// previous todo once `row` starts the next one.
+fn push_row(current: &mut Option<Todo>, row: TodoWithLabelFromRow) -> Option<Todo> {
+    // the name is left empty when only ids were selected
+    let label = row.label_id.map(|id| Label {
+        id,
+        name: row.label_name.unwrap_or_default(),
+    });
+    match current {
+        Some(todo) if todo.id == row.id => {
+            todo.labels.extend(label);
+            None
+        }
+        _ => current.replace(Todo {
+            id: row.id,
+            text: row.text,
+            completed: row.completed,
+            pinned: row.pinned,
+            labels: label.into_iter().collect(),
+            external_id: row.external_id,
+        }),
+    }
+}
+
+/// Folds joined rows (ordered by todo id) into todos carrying their labels.
+fn fold_entities(rows: Vec<TodoWithLabelFromRow>) -> Vec<Todo> {
+    let mut current = None;
+    let mut todos = rows
+        .into_iter()
+        .filter_map(|row| push_row(&mut current, row))
+        .collect::<Vec<_>>();
+    todos.extend(current);
+    todos
+}
+
+/// `todo` with its text opened, when it is stored encrypted.
+fn open_text(cipher: Option<&TextCipher>, mut todo: Todo) -> anyhow::Result<Todo> {
+    if let Some(cipher) = cipher {
+        todo.text = cipher.open(&todo.text)?;
+    }
+    Ok(todo)
+}
+
+fn open_texts(cipher: Option<&TextCipher>, todos: Vec<Todo>) -> anyhow::Result<Vec<Todo>> {
+    todos
+        .into_iter()
+        .map(|todo| open_text(cipher, todo))
+        .collect()
+}
+
+/// Outcome of [`TodoRepositoryForDb::reencrypt_text`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Reencrypted {
+    pub rewritten: u64,
+    /// Sealed with a key the cipher doesn't know, so left as they are.
+    pub skipped: u64,
+}
+
+/// Unique index on `todos.external_id`.
+const EXTERNAL_ID_INDEX: &str = "todos_external_id_key";
+
+/// Reports a clash on [`EXTERNAL_ID_INDEX`] as a duplicate `external_id`.
+fn external_id_clash(err: sqlx::Error, external_id: Option<&str>) -> anyhow::Error {
+    match (&err, external_id) {
+        (sqlx::Error::Database(e), Some(external_id))
+            if e.constraint() == Some(EXTERNAL_ID_INDEX) =>
+        {
+            RepositoryError::DuplicateExternalId(external_id.to_string()).into()
+        }
+        _ => err.into(),
+    }
+}
+
+/// Refuses `payload` when completed todos are locked, `todo` is completed
+/// and the patch doesn't reopen it.
+fn guard_completed(lock_completed: bool, todo: &Todo, payload: &UpdateTodo) -> anyhow::Result<()> {
+    if lock_completed && todo.completed && payload.completed != Some(false) {
+        return Err(RepositoryError::CompletedLocked(todo.id.get()).into());
+    }
+    Ok(())
+}
+
+/// Postgres arrays are bound as plain integers.
+fn raw_ids(label_ids: &[LabelId]) -> Vec<i32> {
+    label_ids.iter().map(|id| id.get()).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadConsistency {
+    /// Reads may be served by a replica that lags behind the primary.
+    Eventual,
+    /// Reads must observe every committed write, so they go to the primary.
+    Primary,
+    /// Reads must observe the writes up to this
+    /// [`TodoRepository::write_position`]: the replica serves them once it has
+    /// replayed that far, the primary if it doesn't in time.
+    AtLeast(u64),
+}
+
+tokio::task_local! {
+    /// Consistency requested by the request currently being handled.
+    pub static READ_CONSISTENCY: ReadConsistency;
+}
+
+fn read_consistency() -> ReadConsistency {
+    READ_CONSISTENCY
+        .try_with(|consistency| *consistency)
+        .unwrap_or(ReadConsistency::Eventual)
+}
+
+/// How far a server has replayed the primary's write-ahead log; `None` when
+/// it is not a standby, so nothing it serves lags behind.
+#[async_trait]
+trait Replayed {
+    async fn replayed(&self) -> anyhow::Result<Option<u64>>;
+}
+
+#[async_trait]
+impl Replayed for PgPool {
+    async fn replayed(&self) -> anyhow::Result<Option<u64>> {
+        let replayed: Option<i64> =
+            sqlx::query_scalar("SELECT (pg_last_wal_replay_lsn() - '0/0'::pg_lsn)::BIGINT")
+                .fetch_one(self)
+                .await?;
+        Ok(replayed.map(|lsn| lsn as u64))
+    }
+}
+
+/// Whether `replica` replays up to `position` within
+/// [`REPLICA_CATCH_UP_WAIT`], or the request's deadline if that is sooner.
+async fn caught_up<P: Replayed>(replica: &P, position: u64) -> bool {
+    let wait = deadline::remaining().map_or(REPLICA_CATCH_UP_WAIT, |remaining| {
+        remaining.min(REPLICA_CATCH_UP_WAIT)
+    });
+    let give_up = Instant::now() + wait;
+    loop {
+        match replica.replayed().await {
+            Ok(None) => return true,
+            Ok(Some(replayed)) if replayed >= position => return true,
+            Ok(Some(_)) if Instant::now() < give_up => {
+                tokio::time::sleep(REPLICA_CATCH_UP_POLL).await
+            }
+            Ok(Some(replayed)) => {
+                tracing::debug!(
+                    "replica at {} did not reach {} in time, reading from primary",
+                    replayed,
+                    position
+                );
+                return false;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "replica replay position unknown, reading from primary: {}",
+                    e
+                );
+                return false;
+            }
+        }
+    }
+}
+
+/// Where queries are sent; writes always go to the primary.
+#[derive(Debug, Clone)]
+enum Pools<P> {
+    Primary(P),
+    Replicated { primary: P, replica: P },
+}
+
+impl<P: Clone> Pools<P> {
+    fn primary(&self) -> &P {
+        match self {
+            Pools::Primary(primary) => primary,
+            Pools::Replicated { primary, .. } => primary,
+        }
+    }
+
+    /// Where a read that can't be retried elsewhere once it started (a
+    /// stream) goes: the replica unless the request asked for the primary or
+    /// holds a consistency token, which isn't worth waiting on here.
+    fn reader(&self) -> &P {
+        match self {
+            Pools::Replicated { replica, .. }
+                if read_consistency() == ReadConsistency::Eventual =>
+            {
+                replica
+            }
+            _ => self.primary(),
+        }
+    }
+
+    /// Runs a read-only query on the replica unless the request asked for
+    /// primary consistency or the replica is behind the request's token,
+    /// falling back to the primary if the replica fails.
+    async fn read<T, F, Fut>(&self, query: F) -> anyhow::Result<T>
+    where
+        P: Replayed,
+        F: Fn(P) -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let (primary, replica) = match self {
+            Pools::Replicated { primary, replica } => (primary, replica),
+            Pools::Primary(primary) => return query(primary.clone()).await,
+        };
+        let on_replica = match read_consistency() {
+            ReadConsistency::Eventual => true,
+            ReadConsistency::Primary => false,
+            ReadConsistency::AtLeast(position) => caught_up(replica, position).await,
+        };
+        if !on_replica {
+            return query(primary.clone()).await;
+        }
+        match query(replica.clone()).await {
+            // repository errors (not found, ...) are answers, not replica
+            // failures, and a spent deadline won't be any longer on the primary
+            Err(e)
+                if e.downcast_ref::<RepositoryError>().is_none() && !is_deadline_exceeded(&e) =>
+            {
+                tracing::warn!("replica read failed, falling back to primary: {}", e);
+                query(primary.clone()).await
+            }
+            res => res,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct TodoRepositoryForDb {
-    pool: PgPool,
+    pools: Pools<PgPool>,
+    lock_completed: bool,
+    cipher: Option<Arc<TextCipher>>,
 }
 
 impl TodoRepositoryForDb {
     pub fn new(pool: PgPool) -> Self {
-        TodoRepositoryForDb { pool }
+        TodoRepositoryForDb {
+            pools: Pools::Primary(pool),
+            lock_completed: false,
+            cipher: None,
+        }
     }
-}
 
-#[async_trait]
-impl TodoRepository for TodoRepositoryForDb {
-    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
-        let todo = sqlx::query_as::<_, Todo>(
+    /// Serves `find` and `all` from `replica`, keeping mutations on `primary`.
+    pub fn with_replica(primary: PgPool, replica: PgPool) -> Self {
+        TodoRepositoryForDb {
+            pools: Pools::Replicated { primary, replica },
+            lock_completed: false,
+            cipher: None,
+        }
+    }
+
+    /// Stores todo text encrypted with `cipher`, transparently to callers.
+    /// Nothing can search or sort by text in the database then; no query
+    /// does today.
+    pub fn encrypt_text(self, cipher: Option<TextCipher>) -> Self {
+        Self {
+            cipher: cipher.map(Arc::new),
+            ..self
+        }
+    }
+
+    /// `text` as it is stored.
+    fn seal_text(&self, text: &str) -> String {
+        match &self.cipher {
+            Some(cipher) => cipher.seal(text),
+            None => text.to_string(),
+        }
+    }
+
+    /// Re-encrypts, `batch_size` rows per transaction, the text of every todo
+    /// not sealed with the current key; with `include_plaintext` also the
+    /// text stored before encryption was turned on. Rows sealed with a key
+    /// the cipher doesn't know are skipped, as is plaintext that happens to
+    /// start with the prefix and so can't be opened. Rewritten todos count as
+    /// updated for [`TodoRepository::sync`].
+    pub async fn reencrypt_text(
+        &self,
+        batch_size: i64,
+        include_plaintext: bool,
+    ) -> anyhow::Result<Reencrypted> {
+        let cipher = self
+            .cipher
+            .as_deref()
+            .ok_or_else(|| RepositoryError::Unexpected("no encryption key".to_string()))?;
+        let mut report = Reencrypted::default();
+        let mut after = 0;
+        loop {
+            let mut tx = self.pools.primary().begin().await?;
+            // locked, so a concurrent update isn't overwritten with old text
+            let rows: Vec<(TodoId, String)> = sqlx::query_as(
+                r#"
+                SELECT id, text FROM todos
+                WHERE id > $1
+                ORDER BY id
+                LIMIT $2
+                FOR UPDATE
+                "#,
+            )
+            .bind(after)
+            .bind(batch_size)
+            .fetch_all(&mut tx)
+            .await?;
+            let last = match rows.last() {
+                Some((id, _)) => id.get(),
+                None => break,
+            };
+            for (id, stored) in rows {
+                if cipher.is_current(&stored)
+                    || !(include_plaintext || TextCipher::is_sealed(&stored))
+                {
+                    continue;
+                }
+                let text = match cipher.open(&stored) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        tracing::warn!("todo {} not re-encrypted: {}", id.get(), e);
+                        report.skipped += 1;
+                        continue;
+                    }
+                };
+                sqlx::query("UPDATE todos SET text = $1 WHERE id = $2")
+                    .bind(cipher.seal(&text))
+                    .bind(id)
+                    .execute(&mut tx)
+                    .await?;
+                report.rewritten += 1;
+            }
+            tx.commit().await?;
+            after = last;
+        }
+        Ok(report)
+    }
+
+    /// Makes `update` refuse completed todos unless it reopens them.
+    pub fn lock_completed(self, lock_completed: bool) -> Self {
+        Self {
+            lock_completed,
+            ..self
+        }
+    }
+
+    async fn find_with<'e, E>(&self, executor: E, id: TodoId) -> anyhow::Result<Todo>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        let rows = sqlx::query_as::<_, TodoWithLabelFromRow>(
             r#"
-            INSERT INTO todos (text, completed)
-            VALUES ($1, false)
-            RETURNING *
+            SELECT todos.*, labels.id AS label_id, labels.name AS label_name
+            FROM todos
+                LEFT OUTER JOIN todo_labels tl ON todos.id = tl.todo_id
+                LEFT OUTER JOIN labels ON labels.id = tl.label_id
+            WHERE todos.id = $1
+            ORDER BY labels.id ASC
             "#,
         )
-        .bind(payload.text.clone())
-        .fetch_one(&self.pool)
+        .bind(id)
+        .fetch_all(executor)
         .await?;
 
-        Ok(todo)
+        let todo = fold_entities(rows)
+            .pop()
+            .ok_or(RepositoryError::NotFound(id.get()))?;
+
+        open_text(self.cipher.as_deref(), todo)
     }
 
-    async fn find(&self, id: i32) -> anyhow::Result<Todo> {
-        let todo = sqlx::query_as::<_, Todo>(
+    /// Starts a transaction on the primary, bounded by the request's
+    /// deadline, for the `*_in` methods: several writes made with them, also
+    /// across repositories, are committed or rolled back together.
+    pub async fn transaction(&self) -> anyhow::Result<Transaction<'static, Postgres>> {
+        deadline::begin(self.pools.primary()).await
+    }
+
+    /// [`TodoRepository::create`] within `tx`, kept only once the caller
+    /// commits it.
+    pub async fn create_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        mut payload: CreateTodo,
+    ) -> anyhow::Result<Todo> {
+        payload.labels.sort();
+        payload.labels.dedup();
+        Self::ensure_labels_exist(&mut *tx, &payload.labels).await?;
+        let row = sqlx::query_as::<_, TodoFromRow>(
             r#"
-            SELECT * FROM todos
-            WHERE id = $1
+            INSERT INTO todos (text, completed, external_id)
+            VALUES ($1, false, $2)
+            RETURNING *
             "#,
         )
-        .bind(id)
-        .fetch_one(&self.pool)
+        .bind(self.seal_text(&payload.text))
+        .bind(payload.external_id.as_deref())
+        .fetch_one(&mut *tx)
         .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-            _ => RepositoryError::Unexpected(e.to_string()),
-        })?;
+        .map_err(|e| external_id_clash(e, payload.external_id.as_deref()))?;
 
-        Ok(todo)
+        Self::attach_labels(&mut *tx, row.id, &payload.labels).await?;
+        self.find_with(&mut *tx, row.id).await
     }
 
-    async fn all(&self) -> anyhow::Result<Vec<Todo>> {
-        let todos = sqlx::query_as::<_, Todo>(
+    /// [`TodoRepository::set_labels`] within `tx`.
+    pub async fn set_labels_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        id: TodoId,
+        mut label_ids: Vec<LabelId>,
+    ) -> anyhow::Result<Todo> {
+        label_ids.sort();
+        label_ids.dedup();
+        self.find_with(&mut *tx, id).await?;
+        Self::ensure_labels_exist(&mut *tx, &label_ids).await?;
+
+        sqlx::query(
             r#"
-            SELECT * FROM todos order by id desc
+            DELETE FROM todo_labels
+            WHERE todo_id = $1
             "#,
         )
-        .fetch_all(&self.pool)
+        .bind(id)
+        .execute(&mut *tx)
         .await?;
+        Self::attach_labels(&mut *tx, id, &label_ids).await?;
 
-        Ok(todos)
+        self.find_with(&mut *tx, id).await
     }
 
-    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
-        let old_todo = self.find(id).await?;
-        let todo = sqlx::query_as::<_, Todo>(
+    async fn dependencies_with(
+        tx: &mut Transaction<'_, Postgres>,
+        id: TodoId,
+    ) -> anyhow::Result<TodoDependencies> {
+        let found: Option<(TodoId,)> = sqlx::query_as("SELECT id FROM todos WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        found.ok_or(RepositoryError::NotFound(id.get()))?;
+        let edges: Vec<(TodoId, TodoId)> = sqlx::query_as(
             r#"
-            UPDATE todos
-            SET text=$1, completed=$2
-            WHERE id = $3
-            RETURNING *
+            SELECT todo_id, depends_on FROM todo_dependencies
+            WHERE todo_id = $1 OR depends_on = $1
+            ORDER BY todo_id, depends_on
             "#,
         )
-        .bind(payload.text.unwrap_or(old_todo.text))
-        .bind(payload.completed.unwrap_or(old_todo.completed))
         .bind(id)
-        .fetch_one(&self.pool)
+        .fetch_all(&mut *tx)
         .await?;
 
-        Ok(todo)
+        let mut dependencies = TodoDependencies::default();
+        for (todo_id, depends_on) in edges {
+            if todo_id == id {
+                dependencies.dependencies.push(depends_on);
+            } else {
+                dependencies.dependents.push(todo_id);
+            }
+        }
+        Ok(dependencies)
+    }
+
+    async fn ensure_labels_exist(
+        tx: &mut Transaction<'_, Postgres>,
+        label_ids: &[LabelId],
+    ) -> anyhow::Result<()> {
+        // locked, so the labels can't be deleted before the links to them
+        // are committed
+        let found: Vec<(LabelId,)> = sqlx::query_as(
+            r#"
+            SELECT id FROM labels
+            WHERE id = ANY($1)
+            FOR SHARE
+            "#,
+        )
+        .bind(raw_ids(label_ids))
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let missing = label_ids
+            .iter()
+            .filter(|id| !found.iter().any(|(found_id,)| found_id == *id))
+            .map(|id| id.get())
+            .collect::<Vec<_>>();
+        match missing.is_empty() {
+            true => Ok(()),
+            false => Err(RepositoryError::LabelsNotFound(missing).into()),
+        }
     }
 
-    async fn delete(&self, id: i32) -> anyhow::Result<()> {
+    async fn attach_labels(
+        tx: &mut Transaction<'_, Postgres>,
+        todo_id: TodoId,
+        label_ids: &[LabelId],
+    ) -> anyhow::Result<()> {
         sqlx::query(
             r#"
-            DELETE FROM todos
-            WHERE id = $1
+            INSERT INTO todo_labels (todo_id, label_id)
+            SELECT $1, id FROM unnest($2::INTEGER[]) AS t(id)
             "#,
         )
-        .bind(id)
-        .execute(&self.pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-            _ => RepositoryError::Unexpected(e.to_string()),
-        })?;
+        .bind(todo_id)
+        .bind(raw_ids(label_ids))
+        .execute(&mut *tx)
+        .await?;
 
         Ok(())
     }
 }
 
 #[async_trait]
-pub trait TodoRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
-    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo>;
-    async fn find(&self, id: i32) -> anyhow::Result<Todo>;
-    async fn all(&self) -> anyhow::Result<Vec<Todo>>;
-    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo>;
-    async fn delete(&self, id: i32) -> anyhow::Result<()>;
-}
+impl TodoRepository for TodoRepositoryForDb {
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let mut tx = self.transaction().await?;
+        let todo = self.create_in(&mut tx, payload).await?;
+        tx.commit().await?;
 
-#[cfg(test)]
-mod test {
-    use std::env;
+        Ok(todo)
+    }
 
-    use dotenv::dotenv;
-    use sqlx::PgPool;
+    async fn begin(&self) -> anyhow::Result<Box<dyn TodoUnitOfWork>> {
+        Ok(Box::new(TodoUnitOfWorkForDb {
+            tx: self.transaction().await?,
+            repository: self.clone(),
+        }))
+    }
 
-    use super::*;
+    async fn find(&self, id: TodoId) -> anyhow::Result<Todo> {
+        self.pools
+            .read(|pool| async move {
+                let mut tx = deadline::begin(&pool).await?;
+                let todo = self.find_with(&mut tx, id).await?;
+                tx.commit().await?;
+                Ok(todo)
+            })
+            .await
+    }
 
-    #[tokio::test]
-    async fn crud_scenario() {
-        dotenv().ok();
-        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        let pool = PgPool::connect(database_url).await.expect(&format!(
-            "failed to connect database, url is [{}]",
-            database_url
-        ));
+    async fn dependencies(&self, id: TodoId) -> anyhow::Result<TodoDependencies> {
+        self.pools
+            .read(|pool| async move {
+                let mut tx = deadline::begin(&pool).await?;
+                let dependencies = Self::dependencies_with(&mut tx, id).await?;
+                tx.commit().await?;
+                Ok(dependencies)
+            })
+            .await
+    }
 
-        let repository = TodoRepositoryForDb::new(pool.clone());
-        let todo_text = "test todo";
+    async fn open_dependencies(&self, id: TodoId) -> anyhow::Result<Vec<TodoId>> {
+        // from the primary, as it guards completing the todo
+        let mut tx = deadline::begin(self.pools.primary()).await?;
+        let open: Vec<(TodoId,)> = sqlx::query_as(
+            r#"
+            SELECT todos.id
+            FROM todo_dependencies d
+                JOIN todos ON todos.id = d.depends_on
+            WHERE d.todo_id = $1 AND NOT todos.completed
+            ORDER BY todos.id
+            "#,
+        )
+        .bind(id)
+        .fetch_all(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(open.into_iter().map(|(id,)| id).collect())
+    }
 
-        // create
-        let created = repository
-            .create(CreateTodo::new(todo_text.to_string()))
+    async fn add_dependency(
+        &self,
+        id: TodoId,
+        depends_on: TodoId,
+    ) -> anyhow::Result<TodoDependencies> {
+        if id == depends_on {
+            return Err(RepositoryError::DependencyCycle(id.get(), depends_on.get()).into());
+        }
+        let mut tx = deadline::begin(self.pools.primary()).await?;
+        // edges are added one at a time, so two concurrent adds can't each
+        // pass the check and close a cycle together
+        sqlx::query("LOCK TABLE todo_dependencies IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut tx)
+            .await?;
+        for todo in [id, depends_on] {
+            let found: Option<(TodoId,)> =
+                sqlx::query_as("SELECT id FROM todos WHERE id = $1 FOR SHARE")
+                    .bind(todo)
+                    .fetch_optional(&mut tx)
+                    .await?;
+            found.ok_or(RepositoryError::NotFound(todo.get()))?;
+        }
+        let (cycle, depth): (Option<bool>, Option<i32>) = sqlx::query_as(
+            r#"
+            WITH RECURSIVE below (id, depth) AS (
+                SELECT $2::INTEGER, 0
+                UNION
+                SELECT d.depends_on, below.depth + 1
+                FROM todo_dependencies d
+                    JOIN below ON d.todo_id = below.id
+                WHERE below.depth < $3
+            )
+            SELECT bool_or(id = $1), max(depth) FROM below
+            "#,
+        )
+        .bind(id)
+        .bind(depends_on)
+        .bind(MAX_DEPENDENCY_DEPTH as i32)
+        .fetch_one(&mut tx)
+        .await?;
+        if cycle.unwrap_or_default() {
+            return Err(RepositoryError::DependencyCycle(id.get(), depends_on.get()).into());
+        }
+        if depth.unwrap_or_default() >= MAX_DEPENDENCY_DEPTH as i32 {
+            return Err(
+                RepositoryError::DependencyTooDeep(depends_on.get(), MAX_DEPENDENCY_DEPTH).into(),
+            );
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO todo_dependencies (todo_id, depends_on)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(id)
+        .bind(depends_on)
+        .execute(&mut tx)
+        .await?;
+        let dependencies = Self::dependencies_with(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(dependencies)
+    }
+
+    async fn remove_dependency(
+        &self,
+        id: TodoId,
+        depends_on: TodoId,
+    ) -> anyhow::Result<TodoDependencies> {
+        let mut tx = deadline::begin(self.pools.primary()).await?;
+        sqlx::query(
+            r#"
+            DELETE FROM todo_dependencies
+            WHERE todo_id = $1 AND depends_on = $2
+            "#,
+        )
+        .bind(id)
+        .bind(depends_on)
+        .execute(&mut tx)
+        .await?;
+        let dependencies = Self::dependencies_with(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(dependencies)
+    }
+
+    async fn existing(&self, ids: &[TodoId]) -> anyhow::Result<BTreeSet<TodoId>> {
+        if ids.is_empty() {
+            return Ok(BTreeSet::new());
+        }
+        let ids: Vec<i32> = ids.iter().map(|id| id.get()).collect();
+        self.pools
+            .read(|pool| {
+                let ids = &ids;
+                async move {
+                    let mut tx = deadline::begin(&pool).await?;
+                    let found: Vec<(TodoId,)> = sqlx::query_as(
+                        r#"
+                        SELECT id FROM todos
+                        WHERE id = ANY($1)
+                        "#,
+                    )
+                    .bind(ids)
+                    .fetch_all(&mut tx)
+                    .await?;
+                    tx.commit().await?;
+                    Ok(found.into_iter().map(|(id,)| id).collect())
+                }
+            })
             .await
-            .expect("failed to create todo");
-        assert_eq!(created.text, todo_text);
-        assert_eq!(created.completed, false);
+    }
 
-        // find
-        let todo = repository
-            .find(created.id)
+    async fn find_by_external_id(&self, external_id: &str) -> anyhow::Result<Todo> {
+        self.pools
+            .read(|pool| async move {
+                let mut tx = deadline::begin(&pool).await?;
+                let (id,): (TodoId,) = sqlx::query_as(
+                    r#"
+                    SELECT id FROM todos
+                    WHERE external_id = $1
+                    "#,
+                )
+                .bind(external_id)
+                .fetch_optional(&mut tx)
+                .await?
+                .ok_or_else(|| RepositoryError::ExternalIdNotFound(external_id.to_string()))?;
+                let todo = self.find_with(&mut tx, id).await?;
+                tx.commit().await?;
+                Ok(todo)
+            })
             .await
-            .expect("failed to find todo");
-        assert_eq!(created, todo);
+    }
 
-        // all
-        let todos = repository.all().await.expect("failed to find all todos");
-        let todo = todos.first().unwrap();
-        assert_eq!(created, *todo);
+    async fn random(&self, label: Option<LabelId>) -> anyhow::Result<Option<Todo>> {
+        self.pools
+            .read(|pool| async move {
+                let mut tx = deadline::begin(&pool).await?;
+                // sorts only the candidates' ids, so the labels are joined
+                // for the one picked
+                let picked: Option<(TodoId,)> = sqlx::query_as(
+                    r#"
+                    SELECT id FROM todos
+                    WHERE NOT completed
+                        AND ($1::INTEGER IS NULL OR EXISTS (
+                            SELECT 1 FROM todo_labels tl
+                            WHERE tl.todo_id = todos.id AND tl.label_id = $1
+                        ))
+                    ORDER BY random()
+                    LIMIT 1
+                    "#,
+                )
+                .bind(label)
+                .fetch_optional(&mut tx)
+                .await?;
+                let todo = match picked {
+                    Some((id,)) => Some(self.find_with(&mut tx, id).await?),
+                    None => None,
+                };
+                tx.commit().await?;
+                Ok(todo)
+            })
+            .await
+    }
 
-        // update
-        let updated_text = "updated todo";
-        let updated = repository
-            .update(
-                created.id,
-                UpdateTodo {
-                    text: Some(updated_text.to_string()),
-                    completed: Some(true),
+    async fn all(&self, params: TodoListParams) -> anyhow::Result<Vec<Todo>> {
+        let query = page_query(&params);
+        self.pools
+            .read(|pool| async move {
+                let mut tx = deadline::begin(&pool).await?;
+                // page the todos before joining, so labels don't count against the limit
+                let rows = sqlx::query_as::<_, TodoWithLabelFromRow>(query)
+                    .bind(params.limit)
+                    .bind(params.offset)
+                    .bind(params.completed.and_then(CompletedFilter::completed))
+                    .fetch_all(&mut tx)
+                    .await?;
+                tx.commit().await?;
+
+                open_texts(self.cipher.as_deref(), fold_entities(rows))
+            })
+            .await
+    }
+
+    async fn count(&self) -> anyhow::Result<i64> {
+        self.pools
+            .read(|pool| async move {
+                let mut tx = deadline::begin(&pool).await?;
+                let count = sqlx::query_scalar("SELECT COUNT(*) FROM todos")
+                    .fetch_one(&mut tx)
+                    .await?;
+                tx.commit().await?;
+                Ok(count)
+            })
+            .await
+    }
+
+    fn stream(&self, params: TodoListParams) -> BoxStream<'static, anyhow::Result<Todo>> {
+        let pool = self.pools.reader().clone();
+        let cipher = self.cipher.clone();
+        Box::pin(try_stream! {
+            let mut tx = deadline::begin(&pool).await?;
+            let mut rows = sqlx::query_as::<_, TodoWithLabelFromRow>(page_query(&params))
+                .bind(params.limit)
+                .bind(params.offset)
+                .bind(params.completed.and_then(CompletedFilter::completed))
+                .fetch(&mut tx);
+            let mut current = None;
+            while let Some(row) = rows.try_next().await? {
+                if let Some(todo) = push_row(&mut current, row) {
+                    yield open_text(cipher.as_deref(), todo)?;
+                }
+            }
+            drop(rows);
+            tx.commit().await?;
+            if let Some(todo) = current {
+                yield open_text(cipher.as_deref(), todo)?;
+            }
+        })
+    }
+
+    /// Only replicated setups hand out positions: with a single pool every
+    /// read already sees every write.
+    async fn write_position(&self) -> anyhow::Result<Option<u64>> {
+        let primary = match &self.pools {
+            Pools::Primary(_) => return Ok(None),
+            Pools::Replicated { primary, .. } => primary,
+        };
+        let position: i64 =
+            sqlx::query_scalar("SELECT (pg_current_wal_lsn() - '0/0'::pg_lsn)::BIGINT")
+                .fetch_one(primary)
+                .await?;
+        Ok(Some(position as u64))
+    }
+
+    /// Kept by the `track_todo_collection_version` migration's trigger.
+    async fn version(&self) -> anyhow::Result<u64> {
+        self.pools
+            .read(|pool| async move {
+                let mut tx = deadline::begin(&pool).await?;
+                let version: i64 =
+                    sqlx::query_scalar("SELECT version FROM todo_collection_version")
+                        .fetch_one(&mut tx)
+                        .await?;
+                tx.commit().await?;
+                Ok(version as u64)
+            })
+            .await
+    }
+
+    async fn seq(&self, id: TodoId) -> anyhow::Result<u64> {
+        self.pools
+            .read(|pool| async move {
+                let mut tx = deadline::begin(&pool).await?;
+                let seq: Option<i64> = sqlx::query_scalar("SELECT seq FROM todos WHERE id = $1")
+                    .bind(id)
+                    .fetch_optional(&mut tx)
+                    .await?;
+                tx.commit().await?;
+                let seq = seq.ok_or(RepositoryError::NotFound(id.get()))?;
+                Ok(seq as u64)
+            })
+            .await
+    }
+
+    /// Changes are stamped with the id of the transaction that made them
+    /// (see the `track_todo_changes` migration). Ids are handed out in start
+    /// order but committed in any order, so the token is the oldest
+    /// transaction that may still be running when the sync starts: anything
+    /// older is visible to the queries below, anything newer is returned
+    /// again next time. A sequence would skip a change committed after a
+    /// later-numbered one had been synced.
+    async fn sync(&self, since: Option<u64>) -> anyhow::Result<TodoSync> {
+        // primary only: a replica lagging behind would hand out tokens for
+        // changes it hasn't seen
+        let mut tx = deadline::begin(self.pools.primary()).await?;
+        let (token,): (i64,) =
+            sqlx::query_as("SELECT pg_snapshot_xmin(pg_current_snapshot())::TEXT::BIGINT")
+                .fetch_one(&mut tx)
+                .await?;
+        let since = since.map_or(0, |since| i64::try_from(since).unwrap_or(i64::MAX));
+        let mut changed = Vec::new();
+        for created in [true, false] {
+            let rows = sqlx::query_as::<_, TodoWithLabelFromRow>(CHANGED_SINCE)
+                .bind(since)
+                .bind(created)
+                .fetch_all(&mut tx)
+                .await?;
+            changed.push(open_texts(self.cipher.as_deref(), fold_entities(rows))?);
+        }
+        let updated = changed.pop().unwrap_or_default();
+        let created = changed.pop().unwrap_or_default();
+        // a first sync has nothing to delete
+        let deleted: Vec<(TodoId,)> = sqlx::query_as(
+            r#"
+            SELECT id FROM todo_tombstones
+            WHERE seq >= $1 AND $1 > 0
+            ORDER BY id
+            "#,
+        )
+        .bind(since)
+        .fetch_all(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(TodoSync {
+            created,
+            updated,
+            deleted: deleted.into_iter().map(|(id,)| id).collect(),
+            token: u64::try_from(token).unwrap_or_default(),
+        })
+    }
+
+    async fn update(&self, id: TodoId, payload: UpdateTodo) -> anyhow::Result<UpdatedTodo> {
+        let mut tx = deadline::begin(self.pools.primary()).await?;
+        if self.lock_completed {
+            // so the todo can't be completed between the guard and the write
+            sqlx::query("SELECT 1 FROM todos WHERE id = $1 FOR UPDATE")
+                .bind(id)
+                .execute(&mut tx)
+                .await?;
+        }
+        let old_todo = self.find_with(&mut tx, id).await?;
+        let changed_fields = payload.changed_fields(&old_todo);
+        if changed_fields.is_empty() {
+            tx.commit().await?;
+            return Ok(UpdatedTodo {
+                todo: old_todo,
+                changed_fields,
+            });
+        }
+        guard_completed(self.lock_completed, &old_todo, &payload)?;
+        sqlx::query(
+            r#"
+            UPDATE todos
+            SET text=$1, completed=$2, external_id=$3
+            WHERE id = $4
+            "#,
+        )
+        .bind(self.seal_text(payload.text.as_deref().unwrap_or(&old_todo.text)))
+        .bind(payload.completed.unwrap_or(old_todo.completed))
+        .bind(
+            payload
+                .external_id
+                .as_deref()
+                .or(old_todo.external_id.as_deref()),
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await
+        .map_err(|e| external_id_clash(e, payload.external_id.as_deref()))?;
+
+        let todo = self.find_with(&mut tx, id).await?;
+        tx.commit().await?;
+
+        Ok(UpdatedTodo {
+            todo,
+            changed_fields,
+        })
+    }
+
+    async fn set_pinned(&self, id: TodoId, pinned: bool) -> anyhow::Result<Todo> {
+        let mut tx = deadline::begin(self.pools.primary()).await?;
+        let updated = sqlx::query(
+            r#"
+            UPDATE todos
+            SET pinned = $1
+            WHERE id = $2
+            "#,
+        )
+        .bind(pinned)
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id.get()).into());
+        }
+
+        let todo = self.find_with(&mut tx, id).await?;
+        tx.commit().await?;
+
+        Ok(todo)
+    }
+
+    async fn reset(&self, id: TodoId) -> anyhow::Result<Todo> {
+        let mut tx = deadline::begin(self.pools.primary()).await?;
+        let updated = sqlx::query(
+            r#"
+            UPDATE todos
+            SET completed = FALSE, pinned = FALSE
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id.get()).into());
+        }
+        sqlx::query(
+            r#"
+            DELETE FROM todo_labels
+            WHERE todo_id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+
+        let todo = self.find_with(&mut tx, id).await?;
+        tx.commit().await?;
+
+        Ok(todo)
+    }
+
+    async fn set_labels(&self, id: TodoId, label_ids: Vec<LabelId>) -> anyhow::Result<Todo> {
+        let mut tx = self.transaction().await?;
+        let todo = self.set_labels_in(&mut tx, id, label_ids).await?;
+        tx.commit().await?;
+
+        Ok(todo)
+    }
+
+    async fn move_label(&self, from: LabelId, to: LabelId) -> anyhow::Result<Vec<Todo>> {
+        let mut tx = deadline::begin(self.pools.primary()).await?;
+        Self::ensure_labels_exist(&mut tx, &[from, to]).await?;
+
+        let moved: Vec<(TodoId,)> = sqlx::query_as(
+            r#"
+            DELETE FROM todo_labels
+            WHERE label_id = $1
+            RETURNING todo_id
+            "#,
+        )
+        .bind(from)
+        .fetch_all(&mut tx)
+        .await?;
+        let mut todo_ids = moved.into_iter().map(|(id,)| id.get()).collect::<Vec<_>>();
+        todo_ids.sort_unstable();
+        todo_ids.dedup();
+        // todos that already had `to` keep a single link
+        sqlx::query(
+            r#"
+            INSERT INTO todo_labels (todo_id, label_id)
+            SELECT t.id, $2 FROM unnest($1::INTEGER[]) AS t(id)
+            WHERE NOT EXISTS (
+                SELECT 1 FROM todo_labels
+                WHERE todo_id = t.id AND label_id = $2
+            )
+            "#,
+        )
+        .bind(&todo_ids)
+        .bind(to)
+        .execute(&mut tx)
+        .await?;
+
+        let rows = sqlx::query_as::<_, TodoWithLabelFromRow>(
+            r#"
+            SELECT todos.*, labels.id AS label_id, labels.name AS label_name
+            FROM todos
+                LEFT OUTER JOIN todo_labels tl ON todos.id = tl.todo_id
+                LEFT OUTER JOIN labels ON labels.id = tl.label_id
+            WHERE todos.id = ANY($1)
+            ORDER BY todos.id DESC, labels.id ASC
+            "#,
+        )
+        .bind(&todo_ids)
+        .fetch_all(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        open_texts(self.cipher.as_deref(), fold_entities(rows))
+    }
+
+    async fn delete(&self, id: TodoId) -> anyhow::Result<()> {
+        let mut tx = deadline::begin(self.pools.primary()).await?;
+        // the associations go with the todo, or the deferred foreign key
+        // fails the commit
+        sqlx::query(
+            r#"
+            DELETE FROM todo_labels
+            WHERE todo_id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query(
+            r#"
+            DELETE FROM todo_dependencies
+            WHERE todo_id = $1 OR depends_on = $1
+            "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query(
+            r#"
+            DELETE FROM todos
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound(id.get()).into(),
+            // kept as is, so a cancel at the deadline is still recognized
+            e => anyhow::Error::from(e),
+        })?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn ping(&self, write: bool) -> anyhow::Result<()> {
+        let mut tx = deadline::begin(self.pools.primary()).await?;
+        sqlx::query("SELECT 1").execute(&mut tx).await?;
+        if write {
+            // fails on a read-only replica or a full disk, and leaves nothing
+            // behind once rolled back
+            sqlx::query("CREATE TEMP TABLE health_check (ok BOOLEAN) ON COMMIT DROP")
+                .execute(&mut tx)
+                .await?;
+            sqlx::query("INSERT INTO health_check VALUES (TRUE)")
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.rollback().await?;
+
+        Ok(())
+    }
+
+    fn pool_usage(&self) -> Option<PoolUsage> {
+        let pool = self.pools.primary();
+        Some(PoolUsage {
+            open: pool.size(),
+            idle: pool.num_idle() as u32,
+        })
+    }
+
+    async fn repair(&self, dry_run: bool) -> anyhow::Result<RepairReport> {
+        let mut tx = deadline::begin(self.pools.primary()).await?;
+        let mut orphaned_links = sqlx::query_as::<_, TodoLabelLink>(
+            r#"
+            DELETE FROM todo_labels tl
+            WHERE NOT EXISTS (SELECT 1 FROM todos WHERE todos.id = tl.todo_id)
+               OR NOT EXISTS (SELECT 1 FROM labels WHERE labels.id = tl.label_id)
+            RETURNING tl.todo_id, tl.label_id
+            "#,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        orphaned_links.sort();
+        let duplicate_links = sqlx::query(
+            r#"
+            DELETE FROM todo_labels dup
+            USING todo_labels kept
+            WHERE dup.todo_id = kept.todo_id
+              AND dup.label_id = kept.label_id
+              AND dup.id > kept.id
+            "#,
+        )
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+
+        // a dry run does the same work and throws it away
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+
+        Ok(RepairReport {
+            dry_run,
+            orphaned_links,
+            duplicate_links,
+        })
+    }
+}
+
+/// [`TodoUnitOfWork`] of [`TodoRepositoryForDb`]: one transaction on the
+/// primary, rolled back by sqlx when dropped uncommitted.
+struct TodoUnitOfWorkForDb {
+    repository: TodoRepositoryForDb,
+    tx: Transaction<'static, Postgres>,
+}
+
+#[async_trait]
+impl TodoUnitOfWork for TodoUnitOfWorkForDb {
+    async fn create(&mut self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        self.repository.create_in(&mut self.tx, payload).await
+    }
+
+    async fn set_labels(&mut self, id: TodoId, label_ids: Vec<LabelId>) -> anyhow::Result<Todo> {
+        self.repository
+            .set_labels_in(&mut self.tx, id, label_ids)
+            .await
+    }
+
+    async fn commit(self: Box<Self>) -> anyhow::Result<()> {
+        self.tx.commit().await?;
+        Ok(())
+    }
+}
+
+/// Writes from [`TodoRepository::begin`] that are kept together or not at
+/// all: nothing is visible to other readers before
+/// [`TodoUnitOfWork::commit`], and dropping it without committing, e.g.
+/// after a step failed, rolls every step back.
+#[async_trait]
+pub trait TodoUnitOfWork: Send {
+    async fn create(&mut self, payload: CreateTodo) -> anyhow::Result<Todo>;
+    async fn set_labels(&mut self, id: TodoId, label_ids: Vec<LabelId>) -> anyhow::Result<Todo>;
+    async fn commit(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+pub trait TodoRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo>;
+    /// Starts a [`TodoUnitOfWork`]. Other writes may wait until it commits
+    /// or is dropped, so finish it before writing through the repository.
+    async fn begin(&self) -> anyhow::Result<Box<dyn TodoUnitOfWork>>;
+    async fn find(&self, id: TodoId) -> anyhow::Result<Todo>;
+    async fn find_by_external_id(&self, external_id: &str) -> anyhow::Result<Todo>;
+    /// A todo picked at random among the incomplete ones, labeled `label`
+    /// when given; `None` when there is none.
+    async fn random(&self, label: Option<LabelId>) -> anyhow::Result<Option<Todo>>;
+    async fn dependencies(&self, id: TodoId) -> anyhow::Result<TodoDependencies>;
+    /// Those of the todo's dependencies not completed yet.
+    async fn open_dependencies(&self, id: TodoId) -> anyhow::Result<Vec<TodoId>>;
+    /// Makes `id` wait for `depends_on`, unless `depends_on` already waits for
+    /// `id`, directly or through other todos, or its dependencies go deeper
+    /// than [`MAX_DEPENDENCY_DEPTH`].
+    async fn add_dependency(
+        &self,
+        id: TodoId,
+        depends_on: TodoId,
+    ) -> anyhow::Result<TodoDependencies>;
+    async fn remove_dependency(
+        &self,
+        id: TodoId,
+        depends_on: TodoId,
+    ) -> anyhow::Result<TodoDependencies>;
+    /// Those of `ids` that exist, without loading the todos.
+    async fn existing(&self, ids: &[TodoId]) -> anyhow::Result<BTreeSet<TodoId>>;
+    async fn all(&self, params: TodoListParams) -> anyhow::Result<Vec<Todo>>;
+    /// Number of todos, completed or not.
+    ///
+    /// By default this lists every todo with [`TodoRepository::all`] and
+    /// counts them, so it loads them all, labels included: linear in time
+    /// and memory. Backends that can count cheaply should override it.
+    async fn count(&self) -> anyhow::Result<i64> {
+        let todos = self.all(TodoListParams::default()).await?;
+        Ok(todos.len() as i64)
+    }
+    /// The same todos as [`TodoRepository::all`], one at a time, so large
+    /// results are never held in memory at once. Nothing runs until the
+    /// stream is polled.
+    fn stream(&self, params: TodoListParams) -> BoxStream<'static, anyhow::Result<Todo>>;
+    /// Todos created, updated and deleted at or after `since`, which is a
+    /// token handed out by an earlier call; everything when `None`.
+    async fn sync(&self, since: Option<u64>) -> anyhow::Result<TodoSync>;
+    /// Version of the todo collection as a whole, higher after any change to
+    /// a todo. Read it before listing: the list is then at least as new.
+    async fn version(&self) -> anyhow::Result<u64>;
+    /// Stamp of the last change to the todo `id`, its labels or its
+    /// dependencies. Read it before the todo: the todo is then at least as
+    /// new.
+    async fn seq(&self, id: TodoId) -> anyhow::Result<u64>;
+    /// Position covering every write committed so far, for read-your-writes
+    /// when reads are served by a replica: reads at
+    /// [`ReadConsistency::AtLeast`] this position observe those writes.
+    /// `None`, the default, when reads never lag behind writes.
+    async fn write_position(&self) -> anyhow::Result<Option<u64>> {
+        Ok(None)
+    }
+    /// Applies `payload`, writing nothing when it matches the todo already.
+    async fn update(&self, id: TodoId, payload: UpdateTodo) -> anyhow::Result<UpdatedTodo>;
+    async fn set_pinned(&self, id: TodoId, pinned: bool) -> anyhow::Result<Todo>;
+    /// Puts the todo back as if just created: open, unpinned and unlabeled,
+    /// keeping its id and text.
+    async fn reset(&self, id: TodoId) -> anyhow::Result<Todo>;
+    /// Replaces the todo's labels with exactly `label_ids`.
+    async fn set_labels(&self, id: TodoId, label_ids: Vec<LabelId>) -> anyhow::Result<Todo>;
+    /// Relabels every todo labeled `from` with `to` instead, all or nothing,
+    /// and returns those todos as they are now.
+    async fn move_label(&self, from: LabelId, to: LabelId) -> anyhow::Result<Vec<Todo>>;
+    async fn delete(&self, id: TodoId) -> anyhow::Result<()>;
+    /// Checks that the database answers and, with `write`, that it accepts
+    /// writes, without changing anything.
+    async fn ping(&self, write: bool) -> anyhow::Result<()>;
+    /// Connections of the primary pool, which takes the writes; `None`
+    /// without one.
+    fn pool_usage(&self) -> Option<PoolUsage>;
+    /// Removes orphaned and duplicate label associations left behind by
+    /// manual database work, all or nothing; `dry_run` only reports them.
+    async fn repair(&self, dry_run: bool) -> anyhow::Result<RepairReport>;
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::models::todo::TodoField;
+    use crate::repositories::label_repository::LabelRepositoryForDB;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let todo_text = "test todo";
+
+        // create
+        let created = repository
+            .create(CreateTodo::new(todo_text.to_string()))
+            .await
+            .expect("failed to create todo");
+        assert_eq!(created.text, todo_text);
+        assert!(repository.count().await.unwrap() >= 1);
+        assert!(!created.completed);
+
+        // find
+        let todo = repository
+            .find(created.id)
+            .await
+            .expect("failed to find todo");
+        assert_eq!(created, todo);
+
+        // all
+        let todos = repository
+            .all(TodoListParams::default())
+            .await
+            .expect("failed to find all todos");
+        let todo = todos.iter().find(|todo| todo.id == created.id).unwrap();
+        assert_eq!(created, *todo);
+
+        // update
+        let updated_text = "updated todo";
+        let updated = repository
+            .update(
+                created.id,
+                UpdateTodo {
+                    text: Some(updated_text.to_string()),
+                    completed: Some(true),
+                    external_id: None,
+                },
+            )
+            .await
+            .expect("failed to update todo");
+        assert_eq!(created.id, todo.id);
+        assert!(updated.todo.completed);
+        assert_eq!(
+            vec![TodoField::Text, TodoField::Completed],
+            updated.changed_fields
+        );
+
+        // the same values again change nothing
+        let unchanged = repository
+            .update(
+                created.id,
+                UpdateTodo {
+                    text: Some(updated_text.to_string()),
+                    completed: Some(true),
+                    external_id: None,
+                },
+            )
+            .await
+            .expect("failed to update todo");
+        assert_eq!(updated.todo, unchanged.todo);
+        assert!(unchanged.changed_fields.is_empty());
+
+        // delete
+        repository
+            .delete(created.id)
+            .await
+            .expect("failed to delete todo");
+        let _res = repository.find(created.id).await;
+
+        let todo_rows = sqlx::query(
+            r#"
+            SELECT * FROM todos where id = $1
+            "#,
+        )
+        .bind(todo.id)
+        .fetch_all(&pool)
+        .await
+        .expect("failed to fetch all todos");
+        assert_eq!(todo_rows.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn update_refuses_completed_todo_when_locked() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool).lock_completed(true);
+        let created = repository
+            .create(CreateTodo::new("locked todo".to_string()))
+            .await
+            .unwrap();
+        let update = |text: &str, completed| {
+            let repository = repository.clone();
+            let payload = UpdateTodo {
+                text: Some(text.to_string()),
+                completed,
+                external_id: None,
+            };
+            async move { repository.update(created.id, payload).await }
+        };
+
+        update("locked todo", Some(true)).await.unwrap();
+        let err = update("edited", None).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::CompletedLocked(_))
+        ));
+        // changing nothing is no edit
+        update("locked todo", Some(true)).await.unwrap();
+        let reopened = update("edited", Some(false)).await.unwrap();
+        assert_eq!("edited", reopened.todo.text);
+        assert!(!reopened.todo.completed);
+
+        repository.delete(created.id).await.unwrap();
+    }
+
+    async fn answer(pool: &'static str) -> anyhow::Result<&'static str> {
+        match pool {
+            "broken" => Err(anyhow::anyhow!("connection refused")),
+            "missing" => Err(RepositoryError::NotFound(1).into()),
+            pool => Ok(pool),
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_are_routed_by_consistency() {
+        let single = Pools::Primary("primary");
+        assert_eq!("primary", single.read(answer).await.unwrap());
+
+        let replicated = Pools::Replicated {
+            primary: "primary",
+            replica: "replica",
+        };
+        assert_eq!("primary", *replicated.primary());
+        assert_eq!("replica", replicated.read(answer).await.unwrap());
+        let forced = READ_CONSISTENCY
+            .scope(ReadConsistency::Primary, replicated.read(answer))
+            .await;
+        assert_eq!("primary", forced.unwrap());
+    }
+
+    #[async_trait]
+    impl Replayed for &'static str {
+        async fn replayed(&self) -> anyhow::Result<Option<u64>> {
+            Ok(None)
+        }
+    }
+
+    /// A replica that has replayed the primary's log up to `.1`.
+    #[derive(Clone)]
+    struct Lagging(&'static str, Arc<AtomicU64>);
+
+    #[async_trait]
+    impl Replayed for Lagging {
+        async fn replayed(&self) -> anyhow::Result<Option<u64>> {
+            Ok(Some(self.1.load(Ordering::SeqCst)))
+        }
+    }
+
+    #[tokio::test]
+    async fn tokens_wait_for_lagging_replicas() {
+        let replayed = Arc::new(AtomicU64::new(10));
+        let pools = Pools::Replicated {
+            primary: Lagging("primary", Arc::new(AtomicU64::new(20))),
+            replica: Lagging("replica", replayed.clone()),
+        };
+        let read_at = |position| {
+            READ_CONSISTENCY.scope(
+                ReadConsistency::AtLeast(position),
+                pools.read(|server: Lagging| async move { Ok(server.0) }),
+            )
+        };
+        assert_eq!("replica", read_at(10).await.unwrap());
+
+        // still behind once the wait is over
+        let started = Instant::now();
+        assert_eq!("primary", read_at(20).await.unwrap());
+        assert!(started.elapsed() >= REPLICA_CATCH_UP_WAIT);
+
+        // catches up while the read waits
+        let catch_up = tokio::spawn(async move {
+            tokio::time::sleep(REPLICA_CATCH_UP_WAIT / 4).await;
+            replayed.store(25, Ordering::SeqCst);
+        });
+        assert_eq!("replica", read_at(20).await.unwrap());
+        catch_up.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn replica_failures_fall_back_to_primary() {
+        let broken = Pools::Replicated {
+            primary: "primary",
+            replica: "broken",
+        };
+        assert_eq!("primary", broken.read(answer).await.unwrap());
+
+        // not found is a legitimate answer and is not retried on the primary
+        let missing = Pools::Replicated {
+            primary: "primary",
+            replica: "missing",
+        };
+        assert!(missing.read(answer).await.is_err());
+    }
+
+    /// A key no other test run shares, so rows it sealed are only this test's.
+    fn random_key() -> String {
+        use base64::Engine;
+        base64::engine::general_purpose::STANDARD.encode(rand::random::<[u8; 32]>())
+    }
+
+    async fn stored_text(pool: &PgPool, id: TodoId) -> String {
+        sqlx::query_scalar("SELECT text FROM todos WHERE id = $1")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn text_is_stored_encrypted() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let key = random_key();
+        let repository = TodoRepositoryForDb::new(pool.clone())
+            .encrypt_text(Some(TextCipher::new(&key, &[]).unwrap()));
+
+        let created = repository
+            .create(CreateTodo::new("secret plan".to_string()))
+            .await
+            .unwrap();
+        assert_eq!("secret plan", created.text);
+        let stored = stored_text(&pool, created.id).await;
+        assert!(TextCipher::is_sealed(&stored));
+        assert!(!stored.contains("secret plan"));
+        assert_eq!(created, repository.find(created.id).await.unwrap());
+
+        let updated = repository
+            .update(
+                created.id,
+                UpdateTodo {
+                    text: Some("other plan".to_string()),
+                    completed: None,
+                    external_id: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!("other plan", updated.todo.text);
+        let restored = stored_text(&pool, created.id).await;
+        assert!(TextCipher::is_sealed(&restored));
+        assert_ne!(stored, restored);
+        assert_eq!(updated.todo, repository.find(created.id).await.unwrap());
+
+        // without the key the ciphertext is all there is
+        let plain = TodoRepositoryForDb::new(pool.clone());
+        assert_eq!(restored, plain.find(created.id).await.unwrap().text);
+        let other_key = TodoRepositoryForDb::new(pool)
+            .encrypt_text(Some(TextCipher::new(&random_key(), &[]).unwrap()));
+        assert!(other_key.find(created.id).await.is_err());
+
+        repository.delete(created.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reencrypt_moves_rows_to_the_current_key() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let (old, new) = (random_key(), random_key());
+        let before = TodoRepositoryForDb::new(pool.clone())
+            .encrypt_text(Some(TextCipher::new(&old, &[]).unwrap()));
+        let mut sealed = Vec::new();
+        for text in ["rotated 1", "rotated 2"] {
+            let todo = before
+                .create(CreateTodo::new(text.to_string()))
+                .await
+                .unwrap();
+            sealed.push((todo.id, stored_text(&pool, todo.id).await));
+        }
+        let plain = TodoRepositoryForDb::new(pool.clone())
+            .create(CreateTodo::new("left plain".to_string()))
+            .await
+            .unwrap();
+
+        let after = TodoRepositoryForDb::new(pool.clone())
+            .encrypt_text(Some(TextCipher::new(&new, &[&old]).unwrap()));
+        // rows other tests sealed are skipped, not rewritten
+        let report = after.reencrypt_text(2, false).await.unwrap();
+        assert_eq!(2, report.rewritten);
+        assert_eq!(0, after.reencrypt_text(2, false).await.unwrap().rewritten);
+
+        let only_new = TodoRepositoryForDb::new(pool.clone())
+            .encrypt_text(Some(TextCipher::new(&new, &[]).unwrap()));
+        for ((id, old_stored), text) in sealed.iter().zip(["rotated 1", "rotated 2"]) {
+            assert_ne!(*old_stored, stored_text(&pool, *id).await);
+            assert_eq!(text, only_new.find(*id).await.unwrap().text);
+            only_new.delete(*id).await.unwrap();
+        }
+        assert_eq!("left plain", stored_text(&pool, plain.id).await);
+        only_new.delete(plain.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn replicated_repository_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+
+        assert_eq!(
+            None,
+            TodoRepositoryForDb::new(pool.clone())
+                .write_position()
+                .await
+                .unwrap()
+        );
+        // the same database stands in for both the primary and the replica
+        let repository = TodoRepositoryForDb::with_replica(pool.clone(), pool);
+        let created = repository
+            .create(CreateTodo::new("replicated todo".to_string()))
+            .await
+            .expect("failed to create todo");
+
+        // a server that isn't a standby has nothing to catch up on
+        let position = repository.write_position().await.unwrap().unwrap();
+        let todo = READ_CONSISTENCY
+            .scope(
+                ReadConsistency::AtLeast(position),
+                repository.find(created.id),
+            )
+            .await
+            .expect("failed to find todo");
+        assert_eq!(created, todo);
+
+        let todo = repository
+            .find(created.id)
+            .await
+            .expect("failed to find todo");
+        assert_eq!(created, todo);
+        let todos = repository
+            .all(TodoListParams::default())
+            .await
+            .expect("failed to find all todos");
+        assert!(todos.contains(&created));
+
+        repository
+            .delete(created.id)
+            .await
+            .expect("failed to delete todo");
+    }
+
+    #[tokio::test]
+    async fn set_labels_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+
+        let label_ids: Vec<(LabelId,)> = sqlx::query_as(
+            r#"
+            INSERT INTO labels (name) VALUES ('set label 1'), ('set label 2') RETURNING id
+            "#,
+        )
+        .fetch_all(&pool)
+        .await
+        .expect("failed to insert labels");
+        let (first, second) = (label_ids[0].0, label_ids[1].0);
+
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let created = repository
+            .create(CreateTodo {
+                text: "set labels todo".to_string(),
+                labels: vec![first],
+                external_id: None,
+            })
+            .await
+            .expect("failed to create todo");
+
+        // replace
+        let todo = repository
+            .set_labels(created.id, vec![second])
+            .await
+            .expect("failed to set labels");
+        assert_eq!(
+            vec![second],
+            todo.labels.iter().map(|label| label.id).collect::<Vec<_>>()
+        );
+
+        // unknown labels leave the labels untouched and are all reported
+        let err = repository
+            .set_labels(
+                created.id,
+                vec![
+                    first,
+                    LabelId::new(i32::MAX).unwrap(),
+                    LabelId::new(i32::MAX - 1).unwrap(),
+                ],
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::LabelsNotFound(missing))
+                if *missing == vec![i32::MAX - 1, i32::MAX]
+        ));
+        let todo = repository
+            .find(created.id)
+            .await
+            .expect("failed to find todo");
+        assert_eq!(
+            vec![second],
+            todo.labels.iter().map(|label| label.id).collect::<Vec<_>>()
+        );
+
+        // unknown todo
+        let res = repository
+            .set_labels(TodoId::new(i32::MAX).unwrap(), vec![first])
+            .await;
+        assert!(res.is_err());
+
+        sqlx::query("DELETE FROM todo_labels WHERE todo_id = $1")
+            .bind(created.id)
+            .execute(&pool)
+            .await
+            .expect("failed to delete todo_labels");
+        repository
+            .delete(created.id)
+            .await
+            .expect("failed to delete todo");
+        sqlx::query("DELETE FROM labels WHERE id = ANY($1)")
+            .bind(vec![first.get(), second.get()])
+            .execute(&pool)
+            .await
+            .expect("failed to delete labels");
+    }
+
+    #[tokio::test]
+    async fn stream_yields_todos_with_labels() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+
+        let label_ids: Vec<(LabelId,)> = sqlx::query_as(
+            r#"
+            INSERT INTO labels (name) VALUES ('stream label 1'), ('stream label 2')
+            RETURNING id
+            "#,
+        )
+        .fetch_all(&pool)
+        .await
+        .expect("failed to insert labels");
+        let label_ids = label_ids.into_iter().map(|(id,)| id).collect::<Vec<_>>();
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let mut created = Vec::new();
+        for labels in [label_ids.clone(), vec![], vec![label_ids[0]]] {
+            let todo = repository
+                .create(CreateTodo {
+                    text: "stream todo".to_string(),
+                    labels,
+                    external_id: None,
+                })
+                .await
+                .expect("failed to create todo");
+            created.push(todo);
+        }
+        created.reverse();
+
+        // other tests share the table, so only look at what this one created
+        let streamed = repository
+            .stream(TodoListParams::default())
+            .try_filter(|todo| futures_util::future::ready(created.contains(todo)))
+            .try_collect::<Vec<_>>()
+            .await
+            .expect("failed to stream todos");
+        assert_eq!(created, streamed);
+
+        // ids only: same todos and label ids, without the names
+        let params = TodoListParams {
+            labels: Some(LabelsView::Ids),
+            ..TodoListParams::default()
+        };
+        let ids_only = repository
+            .all(params)
+            .await
+            .expect("failed to list todos")
+            .into_iter()
+            .filter(|todo| created.iter().any(|created| created.id == todo.id))
+            .collect::<Vec<_>>();
+        let unnamed = created
+            .iter()
+            .map(|todo| Todo {
+                labels: todo
+                    .labels
+                    .iter()
+                    .map(|label| Label::new(label.id, String::new()))
+                    .collect(),
+                ..todo.clone()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(unnamed, ids_only);
+
+        for todo in &created {
+            repository.set_labels(todo.id, vec![]).await.unwrap();
+            repository.delete(todo.id).await.unwrap();
+        }
+        sqlx::query("DELETE FROM labels WHERE id = ANY($1)")
+            .bind(raw_ids(&label_ids))
+            .execute(&pool)
+            .await
+            .expect("failed to delete labels");
+    }
+
+    thread_local! {
+        static QUERIES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    /// Counts the statements sqlx logs on the current thread; each test runs
+    /// its runtime on its own thread, so other tests don't add to the count.
+    struct QueryCounter;
+
+    impl log::Log for QueryCounter {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target() == "sqlx::query"
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                QUERIES.with(|queries| queries.set(queries.get() + 1));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    async fn count_queries<F: std::future::Future>(f: F) -> usize {
+        QUERIES.with(|queries| queries.set(0));
+        f.await;
+        QUERIES.with(|queries| queries.get())
+    }
+
+    #[tokio::test]
+    async fn all_loads_labels_in_a_fixed_number_of_queries() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let _ = log::set_logger(&QueryCounter);
+        log::set_max_level(log::LevelFilter::Info);
+
+        let (label_id,): (LabelId,) =
+            sqlx::query_as("INSERT INTO labels (name) VALUES ('query count label') RETURNING id")
+                .fetch_one(&pool)
+                .await
+                .expect("failed to insert label");
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let mut created = Vec::new();
+        for _ in 0..20 {
+            let todo = repository
+                .create(CreateTodo {
+                    labels: vec![label_id],
+                    ..CreateTodo::new("query count todo".to_string())
+                })
+                .await
+                .expect("failed to create todo");
+            created.push(todo);
+        }
+
+        let page = |limit| TodoListParams {
+            limit: Some(limit),
+            ..TodoListParams::default()
+        };
+        // the first call may look up types; count from the second
+        repository.all(page(1)).await.expect("failed to list todos");
+        let one = count_queries(async {
+            assert_eq!(1, repository.all(page(1)).await.unwrap().len());
+        })
+        .await;
+        let twenty = count_queries(async {
+            assert_eq!(20, repository.all(page(20)).await.unwrap().len());
+        })
+        .await;
+        assert!(one > 0, "sqlx statements were not logged");
+        assert_eq!(one, twenty);
+
+        for todo in &created {
+            repository.delete(todo.id).await.unwrap();
+        }
+        sqlx::query("DELETE FROM labels WHERE id = $1")
+            .bind(label_id)
+            .execute(&pool)
+            .await
+            .expect("failed to delete label");
+    }
+
+    #[tokio::test]
+    async fn all_filters_by_completed() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let open = repository
+            .create(CreateTodo::new("filter open".to_string()))
+            .await
+            .expect("failed to create todo");
+        let done = repository
+            .create(CreateTodo::new("filter done".to_string()))
+            .await
+            .expect("failed to create todo");
+        let done = repository
+            .update(
+                done.id,
+                UpdateTodo {
+                    text: None,
+                    completed: Some(true),
+                    external_id: None,
+                },
+            )
+            .await
+            .expect("failed to complete todo")
+            .todo;
+
+        // other tests share the table, so only look at what this one created
+        for (completed, expected) in [
+            (Some(CompletedFilter::Open), vec![open.clone()]),
+            (Some(CompletedFilter::Completed), vec![done.clone()]),
+            (Some(CompletedFilter::Any), vec![done.clone(), open.clone()]),
+            (None, vec![done.clone(), open.clone()]),
+        ] {
+            let params = TodoListParams {
+                completed,
+                ..TodoListParams::default()
+            };
+            let todos = repository.all(params).await.unwrap();
+            let todos = todos
+                .into_iter()
+                .filter(|todo| todo.id == open.id || todo.id == done.id)
+                .collect::<Vec<_>>();
+            assert_eq!(expected, todos, "{:?}", completed);
+        }
+
+        repository.delete(open.id).await.unwrap();
+        repository.delete(done.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pinned_todos_are_listed_first() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let older = repository
+            .create(CreateTodo::new("pin older".to_string()))
+            .await
+            .expect("failed to create todo");
+        let newer = repository
+            .create(CreateTodo::new("pin newer".to_string()))
+            .await
+            .expect("failed to create todo");
+
+        let pinned = repository
+            .set_pinned(older.id, true)
+            .await
+            .expect("failed to pin todo");
+        assert!(pinned.pinned);
+        // other tests share the table, so only look at what this one created
+        let ids = repository
+            .all(TodoListParams::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|todo| todo.id)
+            .filter(|id| *id == older.id || *id == newer.id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![older.id, newer.id], ids);
+
+        let missing = TodoId::new(i32::MAX).unwrap();
+        let err = repository.set_pinned(missing, true).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(_))
+        ));
+
+        repository.delete(older.id).await.unwrap();
+        repository.delete(newer.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reset_restores_defaults() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let (label,): (LabelId,) =
+            sqlx::query_as("INSERT INTO labels (name) VALUES ('reset label') RETURNING id")
+                .fetch_one(&pool)
+                .await
+                .expect("failed to insert label");
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let created = repository
+            .create(CreateTodo {
+                text: "reset todo".to_string(),
+                labels: vec![label],
+                external_id: None,
+            })
+            .await
+            .expect("failed to create todo");
+        repository
+            .update(
+                created.id,
+                UpdateTodo {
+                    text: None,
+                    completed: Some(true),
+                    external_id: None,
+                },
+            )
+            .await
+            .unwrap();
+        repository.set_pinned(created.id, true).await.unwrap();
+
+        let todo = repository
+            .reset(created.id)
+            .await
+            .expect("failed to reset todo");
+        assert_eq!(Todo::new(created.id, "reset todo".to_string()), todo);
+        assert_eq!(todo, repository.find(created.id).await.unwrap());
+
+        let missing = TodoId::new(i32::MAX).unwrap();
+        let err = repository.reset(missing).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(_))
+        ));
+
+        repository.delete(created.id).await.unwrap();
+        sqlx::query("DELETE FROM labels WHERE id = $1")
+            .bind(label)
+            .execute(&pool)
+            .await
+            .expect("failed to delete label");
+    }
+
+    #[tokio::test]
+    async fn external_id_is_unique_and_found() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool);
+        // unique per run, as the table is shared
+        let external_id = format!("ext-{}", uuid::Uuid::new_v4().simple());
+        let renamed = format!("{}-renamed", external_id);
+
+        let created = repository
+            .create(CreateTodo {
+                external_id: Some(external_id.clone()),
+                ..CreateTodo::new("synced todo".to_string())
+            })
+            .await
+            .expect("failed to create todo");
+        assert_eq!(Some(external_id.clone()), created.external_id);
+        assert_eq!(
+            created,
+            repository.find_by_external_id(&external_id).await.unwrap()
+        );
+
+        let err = repository
+            .create(CreateTodo {
+                external_id: Some(external_id.clone()),
+                ..CreateTodo::new("clashing todo".to_string())
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::DuplicateExternalId(_))
+        ));
+
+        let other = repository
+            .create(CreateTodo::new("unsynced todo".to_string()))
+            .await
+            .expect("failed to create todo");
+        let err = repository
+            .update(
+                other.id,
+                UpdateTodo {
+                    text: None,
+                    completed: None,
+                    external_id: Some(external_id.clone()),
+                },
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::DuplicateExternalId(_))
+        ));
+
+        // leaving it out keeps it; giving another one moves it
+        let todo = repository
+            .update(
+                created.id,
+                UpdateTodo {
+                    text: None,
+                    completed: Some(true),
+                    external_id: None,
+                },
+            )
+            .await
+            .unwrap()
+            .todo;
+        assert_eq!(Some(external_id.clone()), todo.external_id);
+        repository
+            .update(
+                created.id,
+                UpdateTodo {
+                    text: None,
+                    completed: None,
+                    external_id: Some(renamed.clone()),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            created.id,
+            repository.find_by_external_id(&renamed).await.unwrap().id
+        );
+        let err = repository
+            .find_by_external_id(&external_id)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::ExternalIdNotFound(_))
+        ));
+
+        repository.delete(created.id).await.unwrap();
+        repository.delete(other.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dependencies_reject_cycles() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool);
+        let mut ids = Vec::new();
+        for text in ["design", "build", "ship"] {
+            let todo = repository
+                .create(CreateTodo::new(text.to_string()))
+                .await
+                .expect("failed to create todo");
+            ids.push(todo.id);
+        }
+        let (design, build, ship) = (ids[0], ids[1], ids[2]);
+
+        repository.add_dependency(build, design).await.unwrap();
+        let deps = repository.add_dependency(ship, build).await.unwrap();
+        assert_eq!(vec![build], deps.dependencies);
+        let deps = repository.dependencies(build).await.unwrap();
+        assert_eq!(vec![design], deps.dependencies);
+        assert_eq!(vec![ship], deps.dependents);
+
+        // design -> ship -> build -> design
+        for (id, depends_on) in [(design, ship), (design, design)] {
+            let err = repository.add_dependency(id, depends_on).await.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::DependencyCycle(..))
+            ));
+        }
+
+        assert_eq!(
+            vec![design],
+            repository.open_dependencies(build).await.unwrap()
+        );
+        let done = UpdateTodo {
+            text: None,
+            completed: Some(true),
+            external_id: None,
+        };
+        repository.update(design, done).await.unwrap();
+        assert!(repository
+            .open_dependencies(build)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // deleting a todo takes its edges with it
+        repository.delete(build).await.unwrap();
+        assert_eq!(
+            TodoDependencies::default(),
+            repository.dependencies(ship).await.unwrap()
+        );
+        let deps = repository.remove_dependency(ship, build).await.unwrap();
+        assert_eq!(TodoDependencies::default(), deps);
+
+        repository.delete(design).await.unwrap();
+        repository.delete(ship).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn existing_returns_only_todos_present() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool);
+        let kept = repository
+            .create(CreateTodo::new("kept todo".to_string()))
+            .await
+            .expect("failed to create todo");
+        let deleted = repository
+            .create(CreateTodo::new("deleted todo".to_string()))
+            .await
+            .expect("failed to create todo");
+        repository.delete(deleted.id).await.unwrap();
+
+        let existing = repository
+            .existing(&[deleted.id, kept.id, kept.id])
+            .await
+            .unwrap();
+        assert_eq!(BTreeSet::from([kept.id]), existing);
+        assert!(repository.existing(&[]).await.unwrap().is_empty());
+
+        repository.delete(kept.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn all_answers_empty_page_past_the_end() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool);
+
+        let params = TodoListParams {
+            limit: Some(100),
+            offset: Some(i64::MAX),
+            ..TodoListParams::default()
+        };
+        assert!(repository.all(params).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn seq_moves_with_the_todo_and_its_dependencies() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let todo = repository
+            .create(CreateTodo::new("seq todo".to_string()))
+            .await
+            .expect("failed to create todo");
+        let other = repository
+            .create(CreateTodo::new("seq other".to_string()))
+            .await
+            .expect("failed to create todo");
+        let seq = |id| repository.seq(id);
+
+        let before = seq(todo.id).await.expect("failed to read seq");
+        repository
+            .update(
+                other.id,
+                UpdateTodo {
+                    text: None,
+                    completed: Some(true),
+                    external_id: None,
+                },
+            )
+            .await
+            .expect("failed to update todo");
+        assert_eq!(before, seq(todo.id).await.unwrap());
+
+        let other_before = seq(other.id).await.unwrap();
+        repository
+            .add_dependency(other.id, todo.id)
+            .await
+            .expect("failed to add dependency");
+        let added = seq(todo.id).await.unwrap();
+        assert_ne!(before, added);
+        assert_ne!(other_before, seq(other.id).await.unwrap());
+        repository
+            .remove_dependency(other.id, todo.id)
+            .await
+            .expect("failed to remove dependency");
+        assert_ne!(added, seq(todo.id).await.unwrap());
+
+        for id in [todo.id, other.id] {
+            repository.delete(id).await.expect("failed to delete todo");
+        }
+        let missing = seq(todo.id).await.unwrap_err();
+        assert!(matches!(
+            missing.downcast_ref(),
+            Some(RepositoryError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn version_grows_with_every_change() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool.clone());
+
+        // other tests change todos too, so only check that it grows
+        let mut version = repository.version().await.unwrap();
+        let mut assert_grew = |next: u64| {
+            assert!(next > version, "{} after {}", next, version);
+            version = next;
+        };
+        let todo = repository
+            .create(CreateTodo::new("version todo".to_string()))
+            .await
+            .unwrap();
+        assert_grew(repository.version().await.unwrap());
+        repository.set_pinned(todo.id, true).await.unwrap();
+        assert_grew(repository.version().await.unwrap());
+        repository.delete(todo.id).await.unwrap();
+        assert_grew(repository.version().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn sync_returns_changes_since_token() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool);
+
+        let first = repository.sync(None).await.expect("failed to sync");
+        assert!(first.deleted.is_empty());
+        let mut ours = Vec::new();
+        for text in ["kept todo", "changed todo", "deleted todo"] {
+            let todo = repository
+                .create(CreateTodo::new(text.to_string()))
+                .await
+                .expect("failed to create todo");
+            ours.push(todo.id);
+        }
+        // the table is shared, so only look at our own todos
+        let only_ours = |todos: &[Todo]| {
+            todos
+                .iter()
+                .map(|todo| todo.id)
+                .filter(|id| ours.contains(id))
+                .collect::<Vec<_>>()
+        };
+        let second = repository.sync(Some(first.token)).await.unwrap();
+        assert_eq!(ours, only_ours(&second.created));
+        assert!(second.token >= first.token);
+
+        let update = UpdateTodo {
+            text: None,
+            completed: Some(true),
+            external_id: None,
+        };
+        repository.update(ours[1], update).await.unwrap();
+        repository.delete(ours[2]).await.unwrap();
+        let third = repository.sync(Some(second.token)).await.unwrap();
+        // a sync running alongside other transactions may repeat changes,
+        // but never miss one
+        let seen = [only_ours(&third.created), only_ours(&third.updated)].concat();
+        assert!(seen.contains(&ours[1]));
+        assert!(!seen.contains(&ours[2]));
+        assert!(third.deleted.contains(&ours[2]));
+
+        for id in &ours[..2] {
+            repository.delete(*id).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn ping_detects_read_only_database() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool);
+        repository.ping(false).await.expect("failed to read");
+        repository.ping(true).await.expect("failed to write");
+
+        // what a primary that failed over to a replica looks like
+        let read_only = PgPoolOptions::new()
+            .after_connect(|conn| {
+                Box::pin(async move {
+                    conn.execute("SET default_transaction_read_only = on")
+                        .await
+                        .map(|_| ())
+                })
+            })
+            .connect(database_url)
+            .await
+            .expect("failed to connect read-only pool");
+        let repository = TodoRepositoryForDb::new(read_only);
+        repository.ping(false).await.expect("failed to read");
+        assert!(repository.ping(true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn move_label_relabels_todos() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+
+        let label_ids: Vec<(LabelId,)> = sqlx::query_as(
+            r#"
+            INSERT INTO labels (name) VALUES ('move from'), ('move to')
+            RETURNING id
+            "#,
+        )
+        .fetch_all(&pool)
+        .await
+        .expect("failed to insert labels");
+        let (from, to) = (label_ids[0].0, label_ids[1].0);
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let mut created = Vec::new();
+        for labels in [vec![from], vec![from, to]] {
+            let todo = repository
+                .create(CreateTodo {
+                    text: "move todo".to_string(),
+                    labels,
+                    external_id: None,
+                })
+                .await
+                .expect("failed to create todo");
+            created.push(todo);
+        }
+
+        let moved = repository
+            .move_label(from, to)
+            .await
+            .expect("failed to move label");
+        assert_eq!(
+            vec![created[1].id, created[0].id],
+            moved.iter().map(|todo| todo.id).collect::<Vec<_>>()
+        );
+        for todo in &moved {
+            assert_eq!(
+                vec![to],
+                todo.labels.iter().map(|label| label.id).collect::<Vec<_>>()
+            );
+        }
+        assert!(repository.move_label(from, to).await.unwrap().is_empty());
+        let missing = LabelId::new(i32::MAX).unwrap();
+        let err = repository.move_label(from, missing).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::LabelsNotFound(_))
+        ));
+
+        for todo in &created {
+            repository.delete(todo.id).await.unwrap();
+        }
+        sqlx::query("DELETE FROM labels WHERE id = ANY($1)")
+            .bind(vec![from.get(), to.get()])
+            .execute(&pool)
+            .await
+            .expect("failed to delete labels");
+    }
+
+    #[tokio::test]
+    async fn random_picks_open_todos_with_label() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+
+        let label_ids: Vec<(LabelId,)> = sqlx::query_as(
+            r#"
+            INSERT INTO labels (name) VALUES ('random picked'), ('random done')
+            RETURNING id
+            "#,
+        )
+        .fetch_all(&pool)
+        .await
+        .expect("failed to insert labels");
+        let (picked, done) = (label_ids[0].0, label_ids[1].0);
+        // other tests share the table, so only pick among this test's labels
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let mut created = Vec::new();
+        for (labels, completed) in [(vec![picked], false), (vec![picked, done], true)] {
+            let todo = repository
+                .create(CreateTodo {
+                    text: "random todo".to_string(),
+                    labels,
+                    external_id: None,
+                })
+                .await
+                .expect("failed to create todo");
+            let payload = UpdateTodo {
+                text: None,
+                completed: Some(completed),
+                external_id: None,
+            };
+            let updated = repository.update(todo.id, payload).await.unwrap();
+            created.push(updated.todo);
+        }
+
+        for _ in 0..5 {
+            let todo = repository.random(Some(picked)).await.unwrap().unwrap();
+            assert_eq!(created[0], todo);
+        }
+        assert_eq!(None, repository.random(Some(done)).await.unwrap());
+
+        for todo in &created {
+            repository.delete(todo.id).await.unwrap();
+        }
+        sqlx::query("DELETE FROM labels WHERE id = ANY($1)")
+            .bind(vec![picked.get(), done.get()])
+            .execute(&pool)
+            .await
+            .expect("failed to delete labels");
+    }
+
+    #[tokio::test]
+    async fn delete_removes_label_links() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+
+        let (label_id,): (LabelId,) = sqlx::query_as(
+            r#"
+            INSERT INTO labels (name) VALUES ('delete label') RETURNING id
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to insert label");
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let created = repository
+            .create(CreateTodo {
+                text: "delete todo".to_string(),
+                labels: vec![label_id],
+                external_id: None,
+            })
+            .await
+            .expect("failed to create todo");
+
+        repository
+            .delete(created.id)
+            .await
+            .expect("failed to delete labeled todo");
+        let links: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM todo_labels WHERE todo_id = $1")
+            .bind(created.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(0, links);
+        assert!(repository.find(created.id).await.is_err());
+
+        sqlx::query("DELETE FROM labels WHERE id = $1")
+            .bind(label_id)
+            .execute(&pool)
+            .await
+            .expect("failed to delete label");
+    }
+
+    #[tokio::test]
+    async fn repair_removes_duplicate_links() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+
+        let (label_id,): (LabelId,) = sqlx::query_as(
+            r#"
+            INSERT INTO labels (name) VALUES ('repair label') RETURNING id
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to insert label");
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let created = repository
+            .create(CreateTodo {
+                text: "repair todo".to_string(),
+                labels: vec![label_id],
+                external_id: None,
+            })
+            .await
+            .expect("failed to create todo");
+        sqlx::query("INSERT INTO todo_labels (todo_id, label_id) VALUES ($1, $2)")
+            .bind(created.id)
+            .bind(label_id)
+            .execute(&pool)
+            .await
+            .expect("failed to duplicate link");
+        let count_links = || {
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM todo_labels WHERE todo_id = $1")
+                .bind(created.id)
+                .fetch_one(&pool)
+        };
+
+        let report = repository.repair(true).await.expect("failed to repair");
+        assert!(report.dry_run);
+        assert!(report.duplicate_links >= 1);
+        assert_eq!(2, count_links().await.unwrap());
+
+        let report = repository.repair(false).await.expect("failed to repair");
+        assert!(report.duplicate_links >= 1);
+        assert_eq!(1, count_links().await.unwrap());
+        let todo = repository.find(created.id).await.unwrap();
+        assert_eq!(created, todo);
+
+        sqlx::query("DELETE FROM todo_labels WHERE todo_id = $1")
+            .bind(created.id)
+            .execute(&pool)
+            .await
+            .expect("failed to delete todo_labels");
+        repository
+            .delete(created.id)
+            .await
+            .expect("failed to delete todo");
+        sqlx::query("DELETE FROM labels WHERE id = $1")
+            .bind(label_id)
+            .execute(&pool)
+            .await
+            .expect("failed to delete label");
+    }
+
+    #[tokio::test]
+    async fn delete_reports_a_cancel_at_the_deadline() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let todo = repository
+            .create(CreateTodo::new("locked todo".to_string()))
+            .await
+            .unwrap();
+
+        // held by another transaction, so the delete waits until cancelled
+        let mut lock = pool.begin().await.unwrap();
+        sqlx::query("SELECT id FROM todos WHERE id = $1 FOR UPDATE")
+            .bind(todo.id)
+            .execute(&mut lock)
+            .await
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_millis(300);
+        let res = deadline::DEADLINE
+            .scope(deadline, repository.delete(todo.id))
+            .await;
+        assert!(is_deadline_exceeded(&res.unwrap_err()));
+        lock.rollback().await.unwrap();
+
+        repository.delete(todo.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn create_with_labels_rolls_back_on_failure() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database, url is [{}]", database_url));
+
+        let (label_id,): (LabelId,) = sqlx::query_as(
+            r#"
+            INSERT INTO labels (name) VALUES ('rollback label') RETURNING id
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to insert label");
+
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let todo_text = "rolled back todo";
+
+        // the second label does not exist, so nothing may be kept
+        let res = repository
+            .create(CreateTodo {
+                text: todo_text.to_string(),
+                labels: vec![label_id, LabelId::new(i32::MAX).unwrap()],
+                external_id: None,
+            })
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref(),
+            Some(RepositoryError::LabelsNotFound(missing)) if missing == &[i32::MAX]
+        ));
+
+        // writes made in a transaction the caller drops are rolled back
+        // too, across repositories
+        let labels = LabelRepositoryForDB::new(pool.clone());
+        let mut tx = repository.transaction().await.expect("failed to begin");
+        let staged = labels
+            .create_in(&mut tx, "rolled back label".to_string())
+            .await
+            .expect("failed to create label");
+        let todo = repository
+            .create_in(
+                &mut tx,
+                CreateTodo {
+                    text: todo_text.to_string(),
+                    labels: vec![label_id, staged.id, label_id],
+                    external_id: None,
                 },
             )
             .await
-            .expect("failed to update todo");
-        assert_eq!(created.id, todo.id);
-        assert_eq!(updated.completed, true);
+            .expect("failed to create todo");
+        let linked = todo.labels.iter().map(|label| label.id).collect::<Vec<_>>();
+        assert_eq!(vec![label_id, staged.id], linked);
+        drop(tx);
 
-        // delete
-        let _ = repository
-            .delete(created.id)
+        // a unit of work whose labeling fails keeps none of its steps
+        let mut work = TodoRepository::begin(&repository)
             .await
-            .expect("failed to delete todo");
-        let _res = repository.find(created.id).await;
+            .expect("failed to begin");
+        let created = work
+            .create(CreateTodo::new(todo_text.to_string()))
+            .await
+            .expect("failed to create todo");
+        let res = work
+            .set_labels(created.id, vec![label_id, LabelId::new(i32::MAX).unwrap()])
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref(),
+            Some(RepositoryError::LabelsNotFound(missing)) if missing == &[i32::MAX]
+        ));
+        drop(work);
+        let staged_rows = sqlx::query("SELECT * FROM labels WHERE id = $1")
+            .bind(staged.id)
+            .fetch_all(&pool)
+            .await
+            .expect("failed to fetch labels");
+        assert!(staged_rows.is_empty());
 
         let todo_rows = sqlx::query(
             r#"
-            SELECT * FROM todos where id = $1
+            SELECT * FROM todos WHERE text = $1
             "#,
         )
-        .bind(todo.id)
+        .bind(todo_text)
         .fetch_all(&pool)
         .await
-        .expect("failed to fetch all todos");
+        .expect("failed to fetch todos");
         assert_eq!(todo_rows.len(), 0);
+
+        let todo_label_rows = sqlx::query(
+            r#"
+            SELECT * FROM todo_labels WHERE label_id = $1
+            "#,
+        )
+        .bind(label_id)
+        .fetch_all(&pool)
+        .await
+        .expect("failed to fetch todo_labels");
+        assert_eq!(todo_label_rows.len(), 0);
+
+        sqlx::query("DELETE FROM labels WHERE id = $1")
+            .bind(label_id)
+            .execute(&pool)
+            .await
+            .expect("failed to delete label");
     }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "test-utils"))]
 pub mod test_utils {
     use std::{
-        collections::HashMap,
-        sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+        collections::{BTreeMap, HashMap},
+        sync::Arc,
     };
 
     use anyhow::Context;
+    use arc_swap::ArcSwap;
     use axum::async_trait;
+    use futures_util::{stream, StreamExt};
+    use rand::seq::SliceRandom;
+    use tokio::sync::OwnedMutexGuard;
+
+    use crate::repositories::label_repository::test_utils::LabelRepositoryForMemory;
 
     use super::*;
 
-    impl Todo {
-        pub fn new(id: i32, text: String) -> Self {
-            Self {
-                id,
-                text,
-                completed: false,
+    /// One snapshot of the store: the todos, their ids by external id as a
+    /// secondary index, and the change log [`TodoRepository::sync`] reads.
+    #[derive(Debug, Clone, Default)]
+    struct TodoDatas {
+        todos: BTreeMap<TodoId, Arc<Todo>>,
+        external_ids: HashMap<String, TodoId>,
+        /// The id the latest created todo got, so deleting one never frees
+        /// its id for the next.
+        last_id: i32,
+        /// Number of the latest change, counting from 1.
+        seq: u64,
+        /// The changes that created and last changed each todo.
+        versions: HashMap<TodoId, (u64, u64)>,
+        /// The change that deleted each todo.
+        tombstones: BTreeMap<TodoId, u64>,
+        /// The todos each todo depends on.
+        dependencies: BTreeMap<TodoId, BTreeSet<TodoId>>,
+    }
+
+    impl TodoDatas {
+        fn dependencies_of(&self, id: TodoId) -> anyhow::Result<TodoDependencies> {
+            if !self.todos.contains_key(&id) {
+                return Err(RepositoryError::NotFound(id.get()).into());
             }
+            Ok(TodoDependencies {
+                dependencies: self
+                    .dependencies
+                    .get(&id)
+                    .map(|deps| deps.iter().copied().collect())
+                    .unwrap_or_default(),
+                dependents: self
+                    .dependencies
+                    .iter()
+                    .filter(|(_, deps)| deps.contains(&id))
+                    .map(|(todo, _)| *todo)
+                    .collect(),
+            })
         }
-    }
 
-    impl CreateTodo {
-        pub fn new(text: String) -> Self {
-            Self { text }
+        /// Logs a change to `id`, the first one creating it.
+        fn stamp(&mut self, id: TodoId) {
+            self.seq += 1;
+            let seq = self.seq;
+            self.versions.entry(id).or_insert((seq, seq)).1 = seq;
+        }
+
+        fn bury(&mut self, id: TodoId) {
+            self.seq += 1;
+            self.versions.remove(&id);
+            self.tombstones.insert(id, self.seq);
         }
-    }
 
-    type TodoDatas = HashMap<i32, Todo>;
+        /// Moves the todo `id` from its `old` external id to `new`, failing
+        /// when another todo already has `new`.
+        fn claim_external_id(
+            &mut self,
+            id: TodoId,
+            old: Option<&str>,
+            new: Option<&str>,
+        ) -> anyhow::Result<()> {
+            let new = match new {
+                Some(new) if Some(new) != old => new,
+                _ => return Ok(()),
+            };
+            if self.external_ids.contains_key(new) {
+                return Err(RepositoryError::DuplicateExternalId(new.to_string()).into());
+            }
+            if let Some(old) = old {
+                self.external_ids.remove(old);
+            }
+            self.external_ids.insert(new.to_string(), id);
+            Ok(())
+        }
+    }
 
-    #[derive(Debug, Clone)]
+    /// Read-mostly store: readers load the current snapshot without taking a
+    /// lock, writers serialize on `writer`, then clone, modify and publish a
+    /// new snapshot. Todos are shared between snapshots, so a write only
+    /// copies the map, never the todos.
+    #[derive(Debug, Clone, Default)]
     pub struct TodoRepositoryForMemory {
-        store: Arc<RwLock<TodoDatas>>,
+        store: Arc<ArcSwap<TodoDatas>>,
+        writer: Arc<tokio::sync::Mutex<()>>,
+        labels: LabelRepositoryForMemory,
+        lock_completed: bool,
     }
 
     impl TodoRepositoryForMemory {
         pub fn new() -> Self {
+            Self::with_labels(LabelRepositoryForMemory::new())
+        }
+
+        /// Hands out ids from `start` instead of 1, for tests expecting
+        /// particular ids.
+        pub fn new_with_start(start: TodoId) -> Self {
+            let repository = Self::new();
+            repository.store.store(Arc::new(TodoDatas {
+                last_id: start.get() - 1,
+                ..TodoDatas::default()
+            }));
+            repository
+        }
+
+        /// Holds `count` open todos with ids 1 to `count`, stored at once
+        /// rather than by `count` creates that each copy the growing store.
+        pub fn seeded(count: usize) -> Self {
+            let repository = Self::new();
+            let mut store = TodoDatas::default();
+            for _ in 0..count {
+                store.last_id += 1;
+                let id = TodoId::new(store.last_id).expect("ids start at 1");
+                let todo = Todo::new(id, format!("todo {}", store.last_id));
+                store.todos.insert(id, Arc::new(todo));
+                store.stamp(id);
+            }
+            repository.store.store(Arc::new(store));
+            repository
+        }
+
+        /// Shares the label store so that label ids on create resolve against it.
+        pub fn with_labels(labels: LabelRepositoryForMemory) -> Self {
             TodoRepositoryForMemory {
                 store: Arc::default(),
+                writer: Arc::default(),
+                labels,
+                lock_completed: false,
+            }
+        }
+
+        /// Makes `update` refuse completed todos unless it reopens them.
+        pub fn lock_completed(self, lock_completed: bool) -> Self {
+            Self {
+                lock_completed,
+                ..self
+            }
+        }
+
+        /// Applies `f` to a copy of the store and publishes it only if `f`
+        /// succeeds, so a failed write leaves nothing behind (the memory
+        /// equivalent of a rollback).
+        async fn write<R>(
+            &self,
+            f: impl FnOnce(&mut TodoDatas) -> anyhow::Result<R> + Send,
+        ) -> anyhow::Result<R> {
+            let _writer = self.writer.lock().await;
+            let mut store = TodoDatas::clone(&self.store.load());
+            let res = f(&mut store)?;
+            self.store.store(Arc::new(store));
+            Ok(res)
+        }
+
+        fn resolve_labels(&self, label_ids: &[LabelId]) -> anyhow::Result<Vec<Label>> {
+            let labels = self.labels.read_store_ref();
+            let missing = label_ids
+                .iter()
+                .filter(|id| !labels.contains_key(id))
+                .map(|id| id.get())
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                return Err(RepositoryError::LabelsNotFound(missing).into());
             }
+            Ok(label_ids.iter().map(|id| labels[id].clone()).collect())
+        }
+
+        /// The todos `params` selects, in listing order: pinned first, then
+        /// newest first.
+        fn listed<'a>(
+            store: &'a TodoDatas,
+            params: &TodoListParams,
+        ) -> impl Iterator<Item = &'a Arc<Todo>> {
+            let completed = params.completed.unwrap_or(CompletedFilter::Any);
+            let newest_first = || store.todos.values().rev();
+            newest_first()
+                .filter(|todo| todo.pinned)
+                .chain(newest_first().filter(|todo| !todo.pinned))
+                .filter(move |todo| completed.matches(todo))
+                .skip(params.offset.unwrap_or(0) as usize)
+                .take(params.limit.map_or(usize::MAX, |limit| limit as usize))
+        }
+
+        /// Mirrors the todo's labels into the shared `todo_labels` set.
+        fn link_labels(&self, id: TodoId, label_ids: &[LabelId]) {
+            let mut todo_labels = self.labels.write_todo_labels_ref();
+            todo_labels.retain(|(todo_id, _label_id)| *todo_id != id);
+            todo_labels.extend(label_ids.iter().map(|label_id| (id, *label_id)));
+        }
+    }
+
+    /// [`TodoUnitOfWork`] of [`TodoRepositoryForMemory`]: holds off other
+    /// writers, makes each step on a copy of the store, and publishes the
+    /// copy and links the labels only on commit.
+    struct TodoUnitOfWorkForMemory {
+        repository: TodoRepositoryForMemory,
+        staged: TodoDatas,
+        /// Labels to link to each todo on commit, in step order.
+        links: Vec<(TodoId, Vec<LabelId>)>,
+        _writer: OwnedMutexGuard<()>,
+    }
+
+    impl TodoUnitOfWorkForMemory {
+        /// Applies `f` to a copy of the staged store, kept only if `f`
+        /// succeeds, so a failed step stages nothing.
+        fn step<R>(
+            &mut self,
+            f: impl FnOnce(&mut TodoDatas) -> anyhow::Result<R>,
+        ) -> anyhow::Result<R> {
+            let mut store = self.staged.clone();
+            let res = f(&mut store)?;
+            self.staged = store;
+            Ok(res)
+        }
+    }
+
+    #[async_trait]
+    impl TodoUnitOfWork for TodoUnitOfWorkForMemory {
+        async fn create(&mut self, mut payload: CreateTodo) -> anyhow::Result<Todo> {
+            payload.labels.sort();
+            payload.labels.dedup();
+            let labels = self.repository.resolve_labels(&payload.labels)?;
+            let todo = self.step(|store| {
+                let id = TodoId::new(store.last_id + 1).expect("ids start at 1");
+                store.last_id = id.get();
+                store.claim_external_id(id, None, payload.external_id.as_deref())?;
+                let todo = Todo {
+                    labels,
+                    external_id: payload.external_id.clone(),
+                    ..Todo::new(id, payload.text.clone())
+                };
+                store.todos.insert(id, Arc::new(todo.clone()));
+                store.stamp(id);
+                Ok(todo)
+            })?;
+            self.links.push((todo.id, payload.labels));
+            Ok(todo)
         }
 
-        fn write_store_ref(&self) -> RwLockWriteGuard<TodoDatas> {
-            self.store.write().unwrap()
+        async fn set_labels(
+            &mut self,
+            id: TodoId,
+            mut label_ids: Vec<LabelId>,
+        ) -> anyhow::Result<Todo> {
+            label_ids.sort();
+            label_ids.dedup();
+            let labels = self.repository.resolve_labels(&label_ids)?;
+            let todo = self.step(|store| {
+                let todo = store
+                    .todos
+                    .get_mut(&id)
+                    .context(RepositoryError::NotFound(id.get()))?;
+                Arc::make_mut(todo).labels = labels;
+                let todo = Todo::clone(todo);
+                store.stamp(id);
+                Ok(todo)
+            })?;
+            self.links.push((id, label_ids));
+            Ok(todo)
         }
 
-        fn read_store_ref(&self) -> RwLockReadGuard<TodoDatas> {
-            self.store.read().unwrap()
+        async fn commit(self: Box<Self>) -> anyhow::Result<()> {
+            self.repository.store.store(Arc::new(self.staged));
+            for (id, label_ids) in &self.links {
+                self.repository.link_labels(*id, label_ids);
+            }
+            Ok(())
         }
     }
 
     #[async_trait]
     impl TodoRepository for TodoRepositoryForMemory {
         async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
-            let mut store = self.write_store_ref();
-            let id = (store.len() + 1) as i32;
-            let todo = Todo::new(id, payload.text.clone());
-            store.insert(id, todo.clone());
+            let mut work = self.begin().await?;
+            let todo = work.create(payload).await?;
+            work.commit().await?;
             Ok(todo)
         }
 
-        async fn find(&self, id: i32) -> anyhow::Result<Todo> {
-            let store = self.read_store_ref();
+        async fn begin(&self) -> anyhow::Result<Box<dyn TodoUnitOfWork>> {
+            let writer = self.writer.clone().lock_owned().await;
+            Ok(Box::new(TodoUnitOfWorkForMemory {
+                repository: self.clone(),
+                staged: TodoDatas::clone(&self.store.load()),
+                links: Vec::new(),
+                _writer: writer,
+            }))
+        }
+
+        async fn find(&self, id: TodoId) -> anyhow::Result<Todo> {
+            let store = self.store.load();
             let todo = store
+                .todos
                 .get(&id)
-                .map(|todo| todo.clone())
-                .ok_or(RepositoryError::NotFound(id))?;
+                .map(|todo| Todo::clone(todo))
+                .ok_or(RepositoryError::NotFound(id.get()))?;
             Ok(todo)
         }
 
-        async fn all(&self) -> anyhow::Result<Vec<Todo>> {
-            let store = self.read_store_ref();
-            Ok(Vec::from_iter(store.values().map(|todo| todo.clone())))
+        async fn dependencies(&self, id: TodoId) -> anyhow::Result<TodoDependencies> {
+            self.store.load().dependencies_of(id)
         }
 
-        async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
-            let mut store = self.write_store_ref();
-            let todo = store.get(&id).context(RepositoryError::NotFound(id))?;
-            let text = payload.text.unwrap_or(todo.text.clone());
-            let completed = payload.completed.unwrap_or(todo.completed);
-            let todo = Todo {
-                id,
-                text,
-                completed,
+        async fn open_dependencies(&self, id: TodoId) -> anyhow::Result<Vec<TodoId>> {
+            let store = self.store.load();
+            Ok(store
+                .dependencies
+                .get(&id)
+                .into_iter()
+                .flatten()
+                .filter(|todo| !store.todos[todo].completed)
+                .copied()
+                .collect())
+        }
+
+        async fn add_dependency(
+            &self,
+            id: TodoId,
+            depends_on: TodoId,
+        ) -> anyhow::Result<TodoDependencies> {
+            self.write(|store| {
+                for todo in [id, depends_on] {
+                    if !store.todos.contains_key(&todo) {
+                        return Err(RepositoryError::NotFound(todo.get()).into());
+                    }
+                }
+                // breadth first below `depends_on`, one level of depth at a time
+                let mut level = BTreeSet::from([depends_on]);
+                let mut depth = 0;
+                while !level.is_empty() {
+                    if level.contains(&id) {
+                        return Err(
+                            RepositoryError::DependencyCycle(id.get(), depends_on.get()).into()
+                        );
+                    }
+                    if depth == MAX_DEPENDENCY_DEPTH {
+                        return Err(RepositoryError::DependencyTooDeep(
+                            depends_on.get(),
+                            MAX_DEPENDENCY_DEPTH,
+                        )
+                        .into());
+                    }
+                    level = level
+                        .iter()
+                        .filter_map(|todo| store.dependencies.get(todo))
+                        .flatten()
+                        .copied()
+                        .collect();
+                    depth += 1;
+                }
+                if store.dependencies.entry(id).or_default().insert(depends_on) {
+                    store.stamp(id);
+                    store.stamp(depends_on);
+                }
+                store.dependencies_of(id)
+            })
+            .await
+        }
+
+        async fn remove_dependency(
+            &self,
+            id: TodoId,
+            depends_on: TodoId,
+        ) -> anyhow::Result<TodoDependencies> {
+            self.write(|store| {
+                let removed = store
+                    .dependencies
+                    .get_mut(&id)
+                    .is_some_and(|deps| deps.remove(&depends_on));
+                if removed {
+                    store.stamp(id);
+                    store.stamp(depends_on);
+                }
+                store.dependencies_of(id)
+            })
+            .await
+        }
+
+        async fn existing(&self, ids: &[TodoId]) -> anyhow::Result<BTreeSet<TodoId>> {
+            let store = self.store.load();
+            Ok(ids
+                .iter()
+                .filter(|id| store.todos.contains_key(id))
+                .copied()
+                .collect())
+        }
+
+        async fn find_by_external_id(&self, external_id: &str) -> anyhow::Result<Todo> {
+            let store = self.store.load();
+            let todo = store
+                .external_ids
+                .get(external_id)
+                .map(|id| Todo::clone(&store.todos[id]))
+                .ok_or_else(|| RepositoryError::ExternalIdNotFound(external_id.to_string()))?;
+            Ok(todo)
+        }
+
+        async fn random(&self, label: Option<LabelId>) -> anyhow::Result<Option<Todo>> {
+            let store = self.store.load();
+            let candidates = store
+                .todos
+                .values()
+                .filter(|todo| !todo.completed)
+                .filter(|todo| label.is_none_or(|id| todo.labels.iter().any(|l| l.id == id)))
+                .collect::<Vec<_>>();
+            Ok(candidates
+                .choose(&mut rand::thread_rng())
+                .map(|todo| Todo::clone(todo)))
+        }
+
+        async fn all(&self, params: TodoListParams) -> anyhow::Result<Vec<Todo>> {
+            // straight from the ordered snapshot; only the requested page is
+            // cloned
+            let store = self.store.load();
+            Ok(Self::listed(&store, &params)
+                .map(|todo| Todo::clone(todo))
+                .collect())
+        }
+
+        fn stream(&self, params: TodoListParams) -> BoxStream<'static, anyhow::Result<Todo>> {
+            let store = self.store.load_full();
+            let ids = Self::listed(&store, &params)
+                .map(|todo| todo.id)
+                .collect::<Vec<_>>();
+            stream::iter(ids)
+                .map(move |id| Ok(Todo::clone(&store.todos[&id])))
+                .boxed()
+        }
+
+        async fn version(&self) -> anyhow::Result<u64> {
+            Ok(self.store.load().seq)
+        }
+
+        async fn seq(&self, id: TodoId) -> anyhow::Result<u64> {
+            let store = self.store.load();
+            let (_, seq) = store
+                .versions
+                .get(&id)
+                .ok_or(RepositoryError::NotFound(id.get()))?;
+            Ok(*seq)
+        }
+
+        async fn sync(&self, since: Option<u64>) -> anyhow::Result<TodoSync> {
+            let store = self.store.load();
+            let mut sync = TodoSync {
+                token: store.seq + 1,
+                ..TodoSync::default()
             };
-            store.insert(id, todo.clone());
+            let since = since.unwrap_or(0);
+            for (id, todo) in &store.todos {
+                match store.versions.get(id) {
+                    Some((created, _)) if *created >= since => sync.created.push(Todo::clone(todo)),
+                    Some((_, changed)) if *changed >= since => sync.updated.push(Todo::clone(todo)),
+                    _ => {}
+                }
+            }
+            // a first sync has nothing to delete
+            if since > 0 {
+                sync.deleted = store
+                    .tombstones
+                    .iter()
+                    .filter(|(_, deleted)| **deleted >= since)
+                    .map(|(id, _)| *id)
+                    .collect();
+            }
+            Ok(sync)
+        }
+
+        async fn update(&self, id: TodoId, payload: UpdateTodo) -> anyhow::Result<UpdatedTodo> {
+            self.write(|store| {
+                let todo = Todo::clone(
+                    store
+                        .todos
+                        .get(&id)
+                        .context(RepositoryError::NotFound(id.get()))?,
+                );
+                let changed_fields = payload.changed_fields(&todo);
+                if changed_fields.is_empty() {
+                    return Ok(UpdatedTodo {
+                        todo,
+                        changed_fields,
+                    });
+                }
+                guard_completed(self.lock_completed, &todo, &payload)?;
+                store.claim_external_id(
+                    id,
+                    todo.external_id.as_deref(),
+                    payload.external_id.as_deref(),
+                )?;
+                let todo = Todo {
+                    text: payload.text.unwrap_or(todo.text),
+                    completed: payload.completed.unwrap_or(todo.completed),
+                    external_id: payload.external_id.or(todo.external_id),
+                    ..todo
+                };
+                store.todos.insert(id, Arc::new(todo.clone()));
+                store.stamp(id);
+                Ok(UpdatedTodo {
+                    todo,
+                    changed_fields,
+                })
+            })
+            .await
+        }
+
+        async fn set_pinned(&self, id: TodoId, pinned: bool) -> anyhow::Result<Todo> {
+            self.write(|store| {
+                let todo = store
+                    .todos
+                    .get_mut(&id)
+                    .context(RepositoryError::NotFound(id.get()))?;
+                Arc::make_mut(todo).pinned = pinned;
+                let todo = Todo::clone(todo);
+                store.stamp(id);
+                Ok(todo)
+            })
+            .await
+        }
+
+        async fn reset(&self, id: TodoId) -> anyhow::Result<Todo> {
+            self.write(|store| {
+                let todo = store
+                    .todos
+                    .get_mut(&id)
+                    .context(RepositoryError::NotFound(id.get()))?;
+                *Arc::make_mut(todo) = Todo {
+                    external_id: todo.external_id.clone(),
+                    ..Todo::new(id, todo.text.clone())
+                };
+                let todo = Todo::clone(todo);
+                store.stamp(id);
+                self.link_labels(id, &[]);
+                Ok(todo)
+            })
+            .await
+        }
+
+        async fn set_labels(&self, id: TodoId, label_ids: Vec<LabelId>) -> anyhow::Result<Todo> {
+            let mut work = self.begin().await?;
+            let todo = work.set_labels(id, label_ids).await?;
+            work.commit().await?;
             Ok(todo)
         }
 
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
-            let mut store = self.write_store_ref();
-            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
+        async fn move_label(&self, from: LabelId, to: LabelId) -> anyhow::Result<Vec<Todo>> {
+            let target = self.resolve_labels(&[from, to])?.pop().expect("two labels");
+            self.write(|store| {
+                let mut moved = Vec::new();
+                for todo in store.todos.values_mut().rev() {
+                    if !todo.labels.iter().any(|label| label.id == from) {
+                        continue;
+                    }
+                    let todo = Arc::make_mut(todo);
+                    todo.labels
+                        .retain(|label| label.id != from && label.id != to);
+                    todo.labels.push(target.clone());
+                    todo.labels.sort_by_key(|label| label.id);
+                    let label_ids = todo.labels.iter().map(|label| label.id).collect::<Vec<_>>();
+                    self.link_labels(todo.id, &label_ids);
+                    moved.push(todo.clone());
+                }
+                for todo in &moved {
+                    store.stamp(todo.id);
+                }
+                Ok(moved)
+            })
+            .await
+        }
+
+        async fn delete(&self, id: TodoId) -> anyhow::Result<()> {
+            self.write(|store| {
+                let todo = store
+                    .todos
+                    .remove(&id)
+                    .ok_or(RepositoryError::NotFound(id.get()))?;
+                if let Some(external_id) = &todo.external_id {
+                    store.external_ids.remove(external_id);
+                }
+                store.bury(id);
+                store.dependencies.remove(&id);
+                let dependents = store
+                    .dependencies
+                    .iter_mut()
+                    .filter_map(|(todo, deps)| deps.remove(&id).then_some(*todo))
+                    .collect::<Vec<_>>();
+                for dependent in dependents {
+                    store.stamp(dependent);
+                }
+                self.link_labels(id, &[]);
+                Ok(())
+            })
+            .await
+        }
+
+        async fn ping(&self, _write: bool) -> anyhow::Result<()> {
             Ok(())
         }
+
+        fn pool_usage(&self) -> Option<PoolUsage> {
+            None
+        }
+
+        async fn repair(&self, dry_run: bool) -> anyhow::Result<RepairReport> {
+            // hold off writers, which link labels while publishing
+            let _writer = self.writer.lock().await;
+            let store = self.store.load();
+            let labels = self.labels.read_store_ref();
+            let mut todo_labels = self.labels.write_todo_labels_ref();
+            let orphaned = todo_labels
+                .iter()
+                .filter(|(todo_id, label_id)| {
+                    !store.todos.contains_key(todo_id) || !labels.contains_key(label_id)
+                })
+                .copied()
+                .collect::<Vec<_>>();
+            if !dry_run {
+                todo_labels.retain(|link| !orphaned.contains(link));
+            }
+            // a set can't hold duplicates
+            Ok(RepairReport {
+                dry_run,
+                orphaned_links: orphaned
+                    .into_iter()
+                    .map(|(todo_id, label_id)| TodoLabelLink {
+                        todo_id: todo_id.get(),
+                        label_id: label_id.get(),
+                    })
+                    .collect(),
+                duplicate_links: 0,
+            })
+        }
     }
 
     #[cfg(test)]
     mod test {
+        use crate::models::todo::TodoField;
+        use crate::repositories::label_repository::LabelRepository;
+
         use super::*;
 
+        #[tokio::test]
+        async fn ids_start_where_asked_and_are_never_reused() {
+            let create = |repository: TodoRepositoryForMemory| async move {
+                repository
+                    .create(CreateTodo::new("todo".to_string()))
+                    .await
+                    .unwrap()
+                    .id
+                    .get()
+            };
+            let repository = TodoRepositoryForMemory::new();
+            assert_eq!(1, create(repository.clone()).await);
+
+            let repository = TodoRepositoryForMemory::new_with_start(TodoId::new(100).unwrap());
+            assert_eq!(100, create(repository.clone()).await);
+            assert_eq!(101, create(repository.clone()).await);
+            repository.delete(TodoId::new(100).unwrap()).await.unwrap();
+            assert_eq!(102, create(repository.clone()).await);
+            assert_eq!(2, repository.count().await.unwrap());
+        }
+
+        #[tokio::test]
+        async fn dependency_walk_is_bounded() {
+            let repository = TodoRepositoryForMemory::new();
+            let mut chain = Vec::new();
+            for i in 0..=MAX_DEPENDENCY_DEPTH {
+                let todo = repository
+                    .create(CreateTodo::new(format!("step {}", i)))
+                    .await
+                    .unwrap();
+                chain.push(todo.id);
+            }
+            // each step waits for the one before, as deep as the walk goes
+            for pair in chain.windows(2) {
+                repository.add_dependency(pair[1], pair[0]).await.unwrap();
+            }
+            let last = *chain.last().unwrap();
+            let extra = repository
+                .create(CreateTodo::new("one more".to_string()))
+                .await
+                .unwrap();
+
+            let err = repository.add_dependency(extra.id, last).await.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::DependencyTooDeep(..))
+            ));
+            // a cycle right at the limit is still found
+            let err = repository.add_dependency(chain[0], last).await.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::DependencyCycle(..))
+            ));
+            repository.add_dependency(extra.id, chain[1]).await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn count_defaults_to_listing_all() {
+            let repository = TodoRepositoryForMemory::new();
+            assert_eq!(0, repository.count().await.unwrap());
+            for text in ["open", "done"] {
+                repository
+                    .create(CreateTodo::new(text.to_string()))
+                    .await
+                    .unwrap();
+            }
+            let done = UpdateTodo {
+                text: None,
+                completed: Some(true),
+                external_id: None,
+            };
+            repository
+                .update(TodoId::new(2).unwrap(), done)
+                .await
+                .unwrap();
+            assert_eq!(2, repository.count().await.unwrap());
+        }
+
+        #[tokio::test]
+        async fn external_ids_are_indexed() {
+            let repository = TodoRepositoryForMemory::new();
+            let synced = |text: &str, external_id: &str| CreateTodo {
+                external_id: Some(external_id.to_string()),
+                ..CreateTodo::new(text.to_string())
+            };
+            let todo = repository.create(synced("synced", "a")).await.unwrap();
+            assert_eq!(todo, repository.find_by_external_id("a").await.unwrap());
+
+            let err = repository
+                .create(synced("clashing", "a"))
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::DuplicateExternalId(_))
+            ));
+            // the failed create left nothing behind
+            assert_eq!(
+                1,
+                repository
+                    .all(TodoListParams::default())
+                    .await
+                    .unwrap()
+                    .len()
+            );
+
+            let update = UpdateTodo {
+                text: None,
+                completed: None,
+                external_id: Some("b".to_string()),
+            };
+            repository.update(todo.id, update).await.unwrap();
+            assert!(repository.find_by_external_id("a").await.is_err());
+            // reset keeps the todo's identity
+            let todo = repository.reset(todo.id).await.unwrap();
+            assert_eq!(Some("b".to_string()), todo.external_id);
+            assert_eq!(todo, repository.find_by_external_id("b").await.unwrap());
+
+            repository.delete(todo.id).await.unwrap();
+            let err = repository.find_by_external_id("b").await.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::ExternalIdNotFound(_))
+            ));
+        }
+
+        #[tokio::test]
+        async fn sync_returns_changes_since_token() {
+            let repository = TodoRepositoryForMemory::new();
+            let kept = repository
+                .create(CreateTodo::new("kept".to_string()))
+                .await
+                .unwrap();
+            let first = repository.sync(None).await.unwrap();
+            assert_eq!(vec![kept.clone()], first.created);
+
+            let changed = repository
+                .create(CreateTodo::new("changed".to_string()))
+                .await
+                .unwrap();
+            let second = repository.sync(Some(first.token)).await.unwrap();
+            assert_eq!(vec![changed.clone()], second.created);
+            assert!(second.updated.is_empty());
+
+            let changed = repository.set_pinned(changed.id, true).await.unwrap();
+            repository.delete(kept.id).await.unwrap();
+            let third = repository.sync(Some(second.token)).await.unwrap();
+            assert!(third.created.is_empty());
+            assert_eq!(vec![changed], third.updated);
+            assert_eq!(vec![kept.id], third.deleted);
+
+            let nothing = repository.sync(Some(third.token)).await.unwrap();
+            assert_eq!(
+                TodoSync {
+                    token: third.token,
+                    ..TodoSync::default()
+                },
+                nothing
+            );
+        }
+
         #[tokio::test]
         async fn todo_crud_scenario() {
             let text = "todo text".to_string();
-            let id = 1;
+            let id = TodoId::new(1).unwrap();
             let expected = Todo::new(id, text.clone());
 
             // create
             let repository = TodoRepositoryForMemory::new();
             let todo = repository
-                .create(CreateTodo { text })
+                .create(CreateTodo::new(text))
                 .await
                 .expect("failed create todo");
             assert_eq!(expected, todo);
@@ -303,33 +3732,385 @@ pub mod test_utils {
             assert_eq!(expected, todo);
 
             // all
-            let todo = repository.all().await.expect("failed get all todo");
+            let todo = repository
+                .all(TodoListParams::default())
+                .await
+                .expect("failed get all todo");
             assert_eq!(vec![expected], todo);
 
             // update
             let text = "update todo text".to_string();
             let todo = repository
                 .update(
-                    1,
+                    id,
                     UpdateTodo {
                         text: Some(text.clone()),
                         completed: Some(true),
+                        external_id: None,
                     },
                 )
                 .await
                 .expect("failed update todo.");
             assert_eq!(
                 Todo {
-                    id,
-                    text,
                     completed: true,
+                    ..Todo::new(id, text.clone())
                 },
-                todo
+                todo.todo
+            );
+            assert_eq!(
+                vec![TodoField::Text, TodoField::Completed],
+                todo.changed_fields
             );
 
+            // no-op
+            let todo = repository
+                .update(
+                    id,
+                    UpdateTodo {
+                        text: Some(text),
+                        completed: None,
+                        external_id: None,
+                    },
+                )
+                .await
+                .expect("failed update todo.");
+            assert!(todo.changed_fields.is_empty());
+            assert!(todo.todo.completed);
+
             // delete
             let res = repository.delete(id).await;
             assert!(res.is_ok())
         }
+
+        #[tokio::test]
+        async fn delete_removes_label_links() {
+            let labels = LabelRepositoryForMemory::new();
+            let label = labels.create("label".to_string()).await.unwrap();
+            let repository = TodoRepositoryForMemory::with_labels(labels.clone());
+            let kept = repository
+                .create(CreateTodo {
+                    text: "kept".to_string(),
+                    labels: vec![label.id],
+                    external_id: None,
+                })
+                .await
+                .unwrap();
+            let deleted = repository
+                .create(CreateTodo {
+                    text: "deleted".to_string(),
+                    labels: vec![label.id],
+                    external_id: None,
+                })
+                .await
+                .unwrap();
+
+            repository.delete(deleted.id).await.unwrap();
+            let links = labels
+                .read_todo_labels_ref()
+                .iter()
+                .copied()
+                .collect::<Vec<_>>();
+            assert_eq!(vec![(kept.id, label.id)], links);
+        }
+
+        #[tokio::test]
+        async fn stream_matches_all() {
+            let repository = TodoRepositoryForMemory::new();
+            for i in 0..5 {
+                repository
+                    .create(CreateTodo::new(format!("todo {}", i)))
+                    .await
+                    .unwrap();
+            }
+
+            for (limit, offset) in [(None, None), (Some(2), Some(1)), (Some(10), Some(4))] {
+                let params = TodoListParams {
+                    limit,
+                    offset,
+                    ..TodoListParams::default()
+                };
+                let streamed = repository
+                    .stream(params.clone())
+                    .collect::<Vec<_>>()
+                    .await
+                    .into_iter()
+                    .collect::<anyhow::Result<Vec<_>>>()
+                    .unwrap();
+                assert_eq!(repository.all(params).await.unwrap(), streamed);
+            }
+        }
+
+        #[tokio::test]
+        async fn create_with_labels_stages_changes() {
+            let labels = LabelRepositoryForMemory::new();
+            let label = labels
+                .create("label text".to_string())
+                .await
+                .expect("failed create label");
+            let repository = TodoRepositoryForMemory::with_labels(labels);
+
+            let todo = repository
+                .create(CreateTodo {
+                    text: "labeled todo".to_string(),
+                    labels: vec![label.id],
+                    external_id: None,
+                })
+                .await
+                .expect("failed create todo");
+            assert_eq!(vec![label.clone()], todo.labels);
+
+            // an unknown label aborts the whole create
+            let res = repository
+                .create(CreateTodo {
+                    text: "rolled back todo".to_string(),
+                    labels: vec![label.id, LabelId::new(999).unwrap()],
+                    external_id: None,
+                })
+                .await;
+            assert!(res.is_err());
+            let todos = repository
+                .all(TodoListParams::default())
+                .await
+                .expect("failed get all todo");
+            assert_eq!(vec![todo], todos);
+        }
+
+        #[tokio::test]
+        async fn unit_of_work_rolls_back_when_labeling_fails() {
+            let labels = LabelRepositoryForMemory::new();
+            let label = labels.create("label".to_string()).await.unwrap();
+            let repository = TodoRepositoryForMemory::with_labels(labels.clone());
+
+            let mut work = repository.begin().await.unwrap();
+            let todo = work
+                .create(CreateTodo::new("rolled back".to_string()))
+                .await
+                .unwrap();
+            let res = work
+                .set_labels(todo.id, vec![label.id, LabelId::new(999).unwrap()])
+                .await;
+            assert!(matches!(
+                res.unwrap_err().downcast_ref(),
+                Some(RepositoryError::LabelsNotFound(missing)) if missing == &[999]
+            ));
+            drop(work);
+            assert!(repository.find(todo.id).await.is_err());
+            assert_eq!(0, repository.count().await.unwrap());
+
+            // staged until committed, labels included
+            let mut work = repository.begin().await.unwrap();
+            let todo = work
+                .create(CreateTodo::new("kept".to_string()))
+                .await
+                .unwrap();
+            let todo = work.set_labels(todo.id, vec![label.id]).await.unwrap();
+            assert!(repository.find(todo.id).await.is_err());
+            assert!(labels.read_todo_labels_ref().is_empty());
+            work.commit().await.unwrap();
+            assert_eq!(todo, repository.find(todo.id).await.unwrap());
+            assert!(labels.read_todo_labels_ref().contains(&(todo.id, label.id)));
+        }
+
+        #[tokio::test]
+        async fn set_labels_replaces_labels() {
+            let labels = LabelRepositoryForMemory::new();
+            let first = labels.create("first".to_string()).await.unwrap();
+            let second = labels.create("second".to_string()).await.unwrap();
+            let repository = TodoRepositoryForMemory::with_labels(labels);
+            let todo = repository
+                .create(CreateTodo {
+                    text: "todo text".to_string(),
+                    labels: vec![first.id],
+                    external_id: None,
+                })
+                .await
+                .expect("failed create todo");
+
+            let todo = repository
+                .set_labels(todo.id, vec![second.id])
+                .await
+                .expect("failed set labels");
+            assert_eq!(vec![second.clone()], todo.labels);
+
+            let unknown_label = LabelId::new(999).unwrap();
+            assert!(repository
+                .set_labels(todo.id, vec![unknown_label])
+                .await
+                .is_err());
+            let unknown_todo = TodoId::new(999).unwrap();
+            assert!(repository
+                .set_labels(unknown_todo, vec![first.id])
+                .await
+                .is_err());
+            let todo = repository.find(todo.id).await.unwrap();
+            assert_eq!(vec![second], todo.labels);
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+        async fn concurrent_writes_are_not_lost() {
+            let repository = TodoRepositoryForMemory::new();
+            let writers = (0..8).map(|writer| {
+                let repository = repository.clone();
+                tokio::spawn(async move {
+                    for i in 0..50 {
+                        let todo = repository
+                            .create(CreateTodo::new(format!("{}-{}", writer, i)))
+                            .await
+                            .unwrap();
+                        repository
+                            .update(
+                                todo.id,
+                                UpdateTodo {
+                                    text: None,
+                                    completed: Some(true),
+                                    external_id: None,
+                                },
+                            )
+                            .await
+                            .unwrap();
+                    }
+                })
+            });
+            let writers = writers.collect::<Vec<_>>();
+            let readers = (0..8).map(|_| {
+                let repository = repository.clone();
+                tokio::spawn(async move {
+                    for _ in 0..200 {
+                        // every snapshot holds todos 1..=n, newest first
+                        let todos = repository.all(TodoListParams::default()).await.unwrap();
+                        let ids = todos.iter().map(|todo| todo.id.get()).collect::<Vec<_>>();
+                        let expected = (1..=todos.len() as i32).rev().collect::<Vec<_>>();
+                        assert_eq!(expected, ids);
+                    }
+                })
+            });
+            let readers = readers.collect::<Vec<_>>();
+            for task in writers.into_iter().chain(readers) {
+                task.await.unwrap();
+            }
+
+            let todos = repository.all(TodoListParams::default()).await.unwrap();
+            assert_eq!(400, todos.len());
+            assert!(todos.iter().all(|todo| todo.completed));
+        }
+
+        /// Ad-hoc benchmark against the previous `RwLock<HashMap>` store:
+        /// `cargo test --release concurrent_read_latency -- --ignored --nocapture`
+        #[ignore]
+        #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+        async fn concurrent_read_latency() {
+            use std::collections::HashMap;
+            use std::sync::RwLock;
+            use std::time::{Duration, Instant};
+
+            type Locked = Arc<RwLock<HashMap<TodoId, Todo>>>;
+
+            async fn measure<R, W>(read: R, write: W) -> Duration
+            where
+                R: Fn() -> usize + Clone + Send + 'static,
+                W: Fn(i32) + Clone + Send + 'static,
+            {
+                let writers = (0..2)
+                    .map(|writer| {
+                        let write = write.clone();
+                        tokio::spawn(async move {
+                            for i in 0..200 {
+                                write(writer * 1000 + i + 1);
+                                tokio::task::yield_now().await;
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let readers = (0..32)
+                    .map(|_| {
+                        let read = read.clone();
+                        tokio::spawn(async move {
+                            let mut latencies = Vec::new();
+                            for _ in 0..200 {
+                                let started = Instant::now();
+                                assert!(read() > 0);
+                                latencies.push(started.elapsed());
+                                tokio::task::yield_now().await;
+                            }
+                            latencies
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let mut latencies = Vec::new();
+                for reader in readers {
+                    latencies.extend(reader.await.unwrap());
+                }
+                for writer in writers {
+                    writer.await.unwrap();
+                }
+                latencies.sort();
+                latencies[latencies.len() * 99 / 100]
+            }
+
+            let todo = |id: i32| {
+                let id = TodoId::new(id).unwrap();
+                Todo::new(id, format!("todo {}", id))
+            };
+            let seeded = (1..=5000).map(todo).collect::<Vec<_>>();
+
+            let locked: Locked = Arc::new(RwLock::new(
+                seeded.iter().map(|todo| (todo.id, todo.clone())).collect(),
+            ));
+            let baseline = measure(
+                {
+                    let locked = locked.clone();
+                    move || {
+                        // the old `all`: clone everything, then sort
+                        let store = locked.read().unwrap();
+                        let mut todos = Vec::from_iter(store.values().cloned());
+                        todos.sort_by_key(|todo| std::cmp::Reverse(todo.id));
+                        todos.into_iter().take(50).count()
+                    }
+                },
+                move |id| {
+                    let todo = todo(id);
+                    locked.write().unwrap().insert(todo.id, todo);
+                },
+            )
+            .await;
+
+            let repository = TodoRepositoryForMemory::new();
+            repository.store.store(Arc::new(TodoDatas {
+                todos: seeded
+                    .into_iter()
+                    .map(|todo| (todo.id, Arc::new(todo)))
+                    .collect(),
+                ..TodoDatas::default()
+            }));
+            let params = || TodoListParams {
+                limit: Some(50),
+                ..TodoListParams::default()
+            };
+            let snapshot = measure(
+                {
+                    let repository = repository.clone();
+                    move || {
+                        futures_util::FutureExt::now_or_never(repository.all(params()))
+                            .unwrap()
+                            .unwrap()
+                            .len()
+                    }
+                },
+                move |id| {
+                    let todo = Arc::new(todo(id));
+                    // what `write` publishes, without awaiting its lock
+                    repository.store.rcu(|store| {
+                        let mut store = TodoDatas::clone(store);
+                        store.todos.insert(todo.id, todo.clone());
+                        store
+                    });
+                },
+            )
+            .await;
+
+            println!("p99 all(): rwlock {:?}, snapshot {:?}", baseline, snapshot);
+            assert!(snapshot < baseline);
+        }
     }
 }
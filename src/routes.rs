@@ -0,0 +1,177 @@
+use axum::{
+    body::Body,
+    handler::Handler,
+    middleware,
+    routing::{MethodFilter, MethodRouter},
+    Router,
+};
+use serde::Serialize;
+
+use crate::middlewares::Deprecation;
+
+const METHODS: [(MethodFilter, &str); 8] = [
+    (MethodFilter::GET, "GET"),
+    (MethodFilter::HEAD, "HEAD"),
+    (MethodFilter::POST, "POST"),
+    (MethodFilter::PUT, "PUT"),
+    (MethodFilter::PATCH, "PATCH"),
+    (MethodFilter::DELETE, "DELETE"),
+    (MethodFilter::OPTIONS, "OPTIONS"),
+    (MethodFilter::TRACE, "TRACE"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RouteInfo {
+    pub path: &'static str,
+    pub methods: Vec<&'static str>,
+}
+
+struct Route {
+    path: &'static str,
+    filter: MethodFilter,
+    router: MethodRouter,
+}
+
+/// Routes kept as data, so the router and `/debug/routes` are built from the
+/// same list.
+#[derive(Default)]
+pub struct RouteTable {
+    routes: Vec<Route>,
+}
+
+impl RouteTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `handler` for the methods in `filter`; calls for the same
+    /// path are merged into one entry.
+    pub fn route<H, T>(self, path: &'static str, filter: MethodFilter, handler: H) -> Self
+    where
+        H: Handler<T, Body>,
+        T: 'static,
+    {
+        self.insert(path, filter, MethodRouter::new().on(filter, handler))
+    }
+
+    /// Like [`RouteTable::route`], but responses are marked with
+    /// `deprecation` and its hits are counted.
+    pub fn deprecated_route<H, T>(
+        self,
+        path: &'static str,
+        filter: MethodFilter,
+        handler: H,
+        deprecation: Deprecation,
+    ) -> Self
+    where
+        H: Handler<T, Body>,
+        T: 'static,
+    {
+        let router = MethodRouter::new()
+            .on(filter, handler)
+            .layer(middleware::from_fn(move |req, next| {
+                deprecation.clone().run(path, req, next)
+            }));
+        self.insert(path, filter, router)
+    }
+
+    fn insert(mut self, path: &'static str, filter: MethodFilter, router: MethodRouter) -> Self {
+        match self.routes.iter_mut().find(|route| route.path == path) {
+            Some(route) => {
+                route.filter |= filter;
+                route.router = route.router.clone().merge(router);
+            }
+            None => self.routes.push(Route {
+                path,
+                filter,
+                router,
+            }),
+        }
+        self
+    }
+
+    pub fn info(&self) -> Vec<RouteInfo> {
+        self.routes
+            .iter()
+            .map(|route| RouteInfo {
+                path: route.path,
+                methods: if route.filter.is_all() {
+                    vec!["ANY"]
+                } else {
+                    METHODS
+                        .iter()
+                        .filter(|(filter, _name)| route.filter.contains(*filter))
+                        .map(|(_filter, name)| *name)
+                        .collect()
+                },
+            })
+            .collect()
+    }
+
+    pub fn into_router(self) -> Router {
+        self.routes
+            .into_iter()
+            .fold(Router::new(), |router, route| {
+                router.route(route.path, route.router)
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::SystemTime;
+
+    use axum::http::{Method, Request, StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn handler() {}
+
+    #[test]
+    fn merges_methods_per_path() {
+        let table = RouteTable::new()
+            .route("/todos", MethodFilter::POST, handler)
+            .route("/todos/:id", MethodFilter::GET, handler)
+            .route("/todos", MethodFilter::GET, handler)
+            .route("/echo", MethodFilter::all(), handler);
+
+        assert_eq!(
+            vec![
+                RouteInfo {
+                    path: "/todos",
+                    methods: vec!["GET", "POST"],
+                },
+                RouteInfo {
+                    path: "/todos/:id",
+                    methods: vec!["GET"],
+                },
+                RouteInfo {
+                    path: "/echo",
+                    methods: vec!["ANY"],
+                },
+            ],
+            table.info()
+        );
+    }
+
+    #[tokio::test]
+    async fn counts_deprecated_route_hits() {
+        let deprecation = Deprecation::new(SystemTime::UNIX_EPOCH, "/new");
+        let app = RouteTable::new()
+            .route("/old", MethodFilter::GET, handler)
+            .deprecated_route("/old", MethodFilter::POST, handler, deprecation.clone())
+            .into_router();
+
+        for method in [Method::POST, Method::GET, Method::POST] {
+            let req = Request::builder()
+                .method(method)
+                .uri("/old")
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+        }
+        assert_eq!(2, deprecation.hits());
+    }
+}